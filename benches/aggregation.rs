@@ -0,0 +1,145 @@
+//! # FX Simulator and Aggregator - fx_sim_agg
+//!
+//! `aggregation.rs` benchmarks the aggregator's hot paths with criterion: updating an aggregated book from synthetic
+//! quote streams of varying liquidity providers, tiers and price volatility, encoding and decoding the FIX market
+//! data messages, building a price ordered book side and writing the book as a ladder.
+//!
+//! `cargo bench --bench aggregation`
+use criterion::{BatchSize, BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use fx_sim_agg::aggregator::{self, FxAggBookEntry, FxBook};
+use fx_sim_agg::book_policy::BookPolicyConfig;
+use fx_sim_agg::book_side::BookSide;
+use fx_sim_agg::fix::{self, QuoteDecoder, QuoteEncoder};
+use fx_sim_agg::quote::QuoteUpdate;
+use fx_sim_agg::synthetic::SyntheticQuotes;
+use std::hint::black_box;
+use std::io;
+
+// quote updates generated for each stream, cycled through by the benchmarks
+const STREAM_LENGTH: usize = 10_000;
+
+fn quotes(liquidity_providers: usize, tiers: usize, volatility_pips: f64) -> Vec<QuoteUpdate> {
+    SyntheticQuotes::new(liquidity_providers, tiers, volatility_pips, 1)
+        .take(STREAM_LENGTH)
+        .collect()
+}
+
+fn book(quotes: &[QuoteUpdate]) -> FxBook {
+    // a book that has already aggregated the whole stream
+    let mut fx_book = FxBook::new(
+        "USD/EUR",
+        &BookPolicyConfig::new(Default::default(), Some(0.5)),
+    );
+    for quote in quotes {
+        fx_book.update(quote.clone()).unwrap();
+    }
+    fx_book
+}
+
+fn fx_book_update(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fx_book_update");
+    group.throughput(Throughput::Elements(1));
+    // liquidity providers, tiers each side and largest move of the mid in pips
+    for (liquidity_providers, tiers, volatility_pips) in [
+        (5, 1, 1.0),
+        (5, 5, 1.0),
+        (20, 5, 1.0),
+        (20, 5, 5.0),
+        (50, 5, 1.0),
+        (50, 10, 1.0),
+    ] {
+        let quotes = quotes(liquidity_providers, tiers, volatility_pips);
+        let mut fx_book = book(&quotes);
+        let mut next = quotes.iter().cycle();
+        group.bench_function(
+            BenchmarkId::from_parameter(format!(
+                "{liquidity_providers}_lps_{tiers}_tiers_{volatility_pips}_pips"
+            )),
+            |b| {
+                b.iter_batched(
+                    || next.next().unwrap().clone(),
+                    |quote| fx_book.update(quote),
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+fn fix_messages(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fix");
+    for tiers in [1, 5, 10] {
+        let quotes = quotes(20, tiers, 1.0);
+        let mut quote_encoder = QuoteEncoder::default();
+        let messages: Vec<String> = quotes
+            .iter()
+            .filter_map(|quote| quote_encoder.encode(quote))
+            .map(|market_data| market_data.encode())
+            .collect();
+
+        let mut next_quote = quotes.iter().cycle();
+        group.bench_function(BenchmarkId::new("encode", tiers), |b| {
+            b.iter(|| {
+                quote_encoder
+                    .encode(next_quote.next().unwrap())
+                    .map(|market_data| market_data.encode())
+            })
+        });
+        let mut next_message = messages.iter().cycle();
+        group.bench_function(BenchmarkId::new("parse_fields", tiers), |b| {
+            b.iter(|| fix::parse_fields(black_box(next_message.next().unwrap())))
+        });
+        let mut quote_decoder = QuoteDecoder::default();
+        group.bench_function(BenchmarkId::new("decode", tiers), |b| {
+            b.iter(|| quote_decoder.decode(black_box(next_message.next().unwrap())))
+        });
+    }
+    group.finish();
+}
+
+fn book_side_from_entries(c: &mut Criterion) {
+    // the levels of a book side are kept in price order as they are added, rather than sorted
+    let mut group = c.benchmark_group("book_side_from_entries");
+    for levels in [10, 100, 1000] {
+        let entries: Vec<FxAggBookEntry> = quotes(levels, 1, 1.0)
+            .into_iter()
+            .take(levels)
+            .map(|quote| FxAggBookEntry {
+                lp_vol: vec![(quote.liquidity_provider, quote.bids[0].volume)],
+                volume: quote.bids[0].volume,
+                price: quote.bids[0].price,
+                side: String::from("Buy"),
+            })
+            .collect();
+        group.throughput(Throughput::Elements(levels as u64));
+        group.bench_function(BenchmarkId::from_parameter(levels), |b| {
+            b.iter_batched(
+                || entries.clone(),
+                |entries| BookSide::from_entries("Buy", entries),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn ladder(c: &mut Criterion) {
+    let mut group = c.benchmark_group("write_fxbook_as_ladder");
+    for liquidity_providers in [5, 20, 50] {
+        let fx_book = book(&quotes(liquidity_providers, 5, 1.0));
+        group.bench_function(BenchmarkId::from_parameter(liquidity_providers), |b| {
+            b.iter(|| aggregator::write_fxbook_as_ladder(&mut io::sink(), black_box(&fx_book)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    fx_book_update,
+    fix_messages,
+    book_side_from_entries,
+    ladder
+);
+criterion_main!(benches);
@@ -0,0 +1,95 @@
+//! # FX Simulator and Aggregator - fx_sim_agg
+//!
+//! `book_throughput.rs` measures how many quote updates a second the aggregated book handles. Liquidity providers
+//! stream synthetic tiered quotes around a random walk mid, so levels are continually added, merged, withdrawn,
+//! crossed and removed to keep the minimum spread. The updates are timed on their own and again publishing snapshots
+//! of the book as the aggregator does, and the run fails if either rate is below the target.
+//!
+//! `cargo run --release --example book_throughput -- [updates] [liquidity providers] [tiers]`
+use fx_sim_agg::aggregator::{BookManager, MIN_SPREAD_PIPS};
+use fx_sim_agg::position::PositionKeeper;
+use fx_sim_agg::quote::QuoteUpdate;
+use fx_sim_agg::snapshot::{PendingSnapshots, Snapshots};
+use fx_sim_agg::synthetic::SyntheticQuotes;
+use std::env;
+use std::process::exit;
+use std::time::{Duration, Instant};
+
+// updates a second the book must handle
+const TARGET_RATE: f64 = 100_000.0;
+// largest move of the mid between quote updates
+const VOLATILITY_PIPS: f64 = 1.0;
+
+fn main() {
+    let arg = |index: usize, default: usize| {
+        env::args()
+            .nth(index)
+            .map_or(Ok(default), |arg| arg.parse::<usize>())
+            .unwrap_or_else(|e| {
+                eprintln!("invalid argument {index} - {e}");
+                exit(2);
+            })
+    };
+    let (updates, liquidity_providers, tiers) = (arg(1, 1_000_000), arg(2, 20), arg(3, 5));
+    // the synthetic stream needs a liquidity provider quoting at least one tier to send anything
+    for (index, value) in [(1, updates), (2, liquidity_providers), (3, tiers)] {
+        if value == 0 {
+            eprintln!("invalid argument {index} - must be at least 1");
+            exit(2);
+        }
+    }
+
+    // generated up front so only the book updates are timed
+    let quotes: Vec<QuoteUpdate> =
+        SyntheticQuotes::new(liquidity_providers, tiers, VOLATILITY_PIPS, 1)
+            .take(updates)
+            .collect();
+    println!(
+        "{updates} updates from {liquidity_providers} liquidity providers quoting {tiers} tiers"
+    );
+    let mut below_target = false;
+    for (name, publish) in [("without snapshots", false), ("with snapshots", true)] {
+        let (elapsed, book_manager) = time_updates(quotes.clone(), publish);
+        let rate = updates as f64 / elapsed.as_secs_f64();
+        let Some(fx_book) = book_manager.book("USD/EUR") else {
+            eprintln!("no book was created");
+            exit(1);
+        };
+        println!(
+            "{name}: {rate:.0} updates/s in {elapsed:.2?}, {:.2}us per update, book finished with {} bid and {} offer levels",
+            elapsed.as_secs_f64() * 1e6 / updates as f64,
+            fx_book.buy_book.len(),
+            fx_book.sell_book.len()
+        );
+        below_target |= rate < TARGET_RATE;
+    }
+    if below_target {
+        eprintln!("below the target of {TARGET_RATE:.0} updates/s");
+        exit(1);
+    }
+}
+
+fn time_updates(quotes: Vec<QuoteUpdate>, publish: bool) -> (Duration, BookManager) {
+    // apply the quote updates to a new book with the default book policies, publishing the
+    // changed book at most once a publish interval like the aggregator when publish is set
+    let mut book_manager = BookManager::new(&[], MIN_SPREAD_PIPS);
+    let position_keeper = PositionKeeper::default();
+    let snapshots = Snapshots::new(&book_manager, &position_keeper);
+    let mut pending = PendingSnapshots::default();
+    let start = Instant::now();
+    for quote in quotes {
+        match book_manager.update(quote) {
+            Ok(fx_book) if publish => pending.book_changed(&fx_book.currency_pair),
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("quote update not processed - {e}");
+                exit(1);
+            }
+        }
+        if pending.is_due() {
+            pending.publish(&snapshots, &book_manager, &position_keeper);
+        }
+    }
+    pending.publish(&snapshots, &book_manager, &position_keeper);
+    (start.elapsed(), book_manager)
+}
@@ -1,22 +1,32 @@
 //! # FX Simulator and Aggregator - fx_sim_agg
 //!
 //! `aggregator.rs` aggregates simulated FX market data streams into a real-time book of buys and sells.
+use crate::AppError;
+use crate::book_event::{BOOK_EVENT_CAPACITY, BookChange, BookEvent, TopOfBook, top_of_book};
+use crate::book_policy::{BookPolicy, BookPolicyConfig};
+use crate::book_side::BookSide;
+use crate::order::{self, ExecutionReport, Order, Side};
+use crate::price::{Price, pips_to_ticks};
+use crate::price_history::PriceHistory;
+use crate::quote::QuoteUpdate;
 use crate::simulator::Config;
-use crate::{AppError, get_params, get_str_field};
 extern crate chrono;
 use chrono::Utc;
 use chrono::prelude::DateTime;
-use core::f64;
 //use log::{debug, error, info, trace, warn};
-use log::{error, info};
-use std::cmp::Ordering;
+use log::{info, warn};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{self, Write};
+use std::mem;
+use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FxAggBookEntry {
     pub lp_vol: Vec<(String, i32)>,
     pub volume: i32,
-    pub price: f64,
+    pub price: Price,
     pub side: String,
 }
 
@@ -28,43 +38,106 @@ impl PartialEq for FxAggBookEntry {
 
 impl PartialOrd for FxAggBookEntry {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.price.partial_cmp(&other.price)
+        Some(self.price.cmp(&other.price))
     }
 }
-#[derive(Debug, Default)]
+
+// default minimum spread (in pips) maintained between the top of the buy and sell books
+pub const MIN_SPREAD_PIPS: f64 = 6.0;
+
+#[derive(Debug, Clone)]
 pub struct FxBook {
     pub currency_pair: String,
-    pub buy_book: Vec<FxAggBookEntry>,
-    pub sell_book: Vec<FxAggBookEntry>,
+    pub buy_book: BookSide,
+    pub sell_book: BookSide,
     pub timestamp: u64,
+    // policies applied after each quote update, handling crossed books and the minimum spread
+    pub policies: Vec<Arc<dyn BookPolicy>>,
+    // the top of the buy book is at or above the top of the sell book
+    pub crossed: bool,
+    // broadcasts the changes to the book to its subscribers
+    pub events: broadcast::Sender<BookEvent>,
+    // time to live of each liquidity provider's quotes, liquidity providers without one keep
+    // their quotes until they are replaced
+    pub quote_ttls: HashMap<String, Duration>,
+    // source time in nanoseconds of each liquidity provider's latest quote update
+    pub quote_times: HashMap<String, u64>,
+    // latest quote update of each liquidity provider, which its last look at trade requests is
+    // decided against
+    pub quotes: HashMap<String, QuoteUpdate>,
+    // liquidity providers whose quotes have expired and been removed from the book
+    pub stale_lps: BTreeSet<String>,
+    // top of the book and each liquidity provider's best quotes over time, for charting
+    pub history: PriceHistory,
+}
+
+impl Default for FxBook {
+    fn default() -> Self {
+        FxBook {
+            currency_pair: String::new(),
+            buy_book: BookSide::new("Buy"),
+            sell_book: BookSide::new("Sell"),
+            timestamp: 0,
+            policies: BookPolicyConfig::default().create(),
+            crossed: false,
+            events: broadcast::Sender::new(BOOK_EVENT_CAPACITY),
+            quote_ttls: HashMap::new(),
+            quote_times: HashMap::new(),
+            quotes: HashMap::new(),
+            stale_lps: BTreeSet::new(),
+            history: PriceHistory::default(),
+        }
+    }
 }
 
 impl FxBook {
-    pub fn update(&mut self, market_data: String) -> Result<(), AppError> {
-        // add fxbook entries for all current market data in the order of
-        // 1M buy, 1M sell, 3M buy, 3M sell, 5M buy, 5M sell
-        add_market_data(self, market_data)?;
-        sort_books(self);
-        match check_books_crossed(self) {
-            Some(index) => {
-                info!(
-                    "books crossed at sell book index {} with sell price {}",
-                    index.0, index.1
-                );
-                correct_crossed_books(self, index)?;
+    pub fn update(&mut self, quote: QuoteUpdate) -> Result<(), AppError> {
+        // replace the liquidity provider's quotes with fxbook entries for all the bid and offer
+        // tiers in the quote update
+        let top = self.record_level_changes();
+        replace_quotes(self, &quote);
+        self.publish(BookChange::QuoteReplaced {
+            liquidity_provider: quote.liquidity_provider.clone(),
+            sequence_number: quote.sequence_number,
+            source_time: quote.source_time,
+        });
+        let policies = mem::take(&mut self.policies);
+        for policy in &policies {
+            let crossed_at = check_books_crossed(self)
+                .and_then(|_| Some((self.buy_book.first()?.price, self.sell_book.first()?.price)));
+            policy.apply(self, &quote.liquidity_provider);
+            if let Some((bid, offer)) = crossed_at
+                && check_books_crossed(self).is_none()
+            {
+                self.publish(BookChange::CrossedBookCorrected { bid, offer });
+            }
+        }
+        self.policies = policies;
+        self.publish_level_changes(top);
+        let crossed = check_books_crossed(self).is_some();
+        if crossed && !self.crossed {
+            warn!(
+                "{} book crossed by {} quote",
+                self.currency_pair, quote.liquidity_provider
+            );
+        }
+        self.crossed = crossed;
+        let (bid, offer) = self.top_prices();
+        self.history.record(&quote, bid, offer);
+        match self.quotes.get_mut(&quote.liquidity_provider) {
+            Some(latest) => *latest = quote,
+            None => {
+                self.quotes.insert(quote.liquidity_provider.clone(), quote);
             }
-            None => (),
         }
-        maintain_min_spread(self);
         Ok(())
     }
-    pub fn new(config: &Vec<Config>) -> Self {
-        // create a new FxBook with empty buy and sell books
+    pub fn new(currency_pair: &str, book_policy: &BookPolicyConfig) -> Self {
+        // create a new FxBook for the currency pair with empty buy and sell books
         // and a timestamp of current time
-        // using first config entry to get currency pair
-        let currency_pair = config[0].currency_pair.clone();
-        let buy_book: Vec<FxAggBookEntry> = Vec::new();
-        let sell_book: Vec<FxAggBookEntry> = Vec::new();
+        let currency_pair = String::from(currency_pair);
+        let buy_book = BookSide::new("Buy");
+        let sell_book = BookSide::new("Sell");
         //need to catch this possible panic on unwrap when converting u126 to u64
         let timestamp: u64 = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -78,11 +151,318 @@ impl FxBook {
             buy_book,
             sell_book,
             timestamp,
+            policies: book_policy.create(),
+            crossed: false,
+            events: broadcast::Sender::new(BOOK_EVENT_CAPACITY),
+            quote_ttls: HashMap::new(),
+            quote_times: HashMap::new(),
+            quotes: HashMap::new(),
+            stale_lps: BTreeSet::new(),
+            history: PriceHistory::default(),
+        }
+    }
+
+    pub fn expire_quotes(&mut self, now: u64) -> Vec<String> {
+        // remove the quotes of every liquidity provider that hasn't refreshed them within its
+        // time to live of now on the source clock, flagging it as stale, and return the newly
+        // stale liquidity providers
+        let mut expired: Vec<String> = Vec::new();
+        for (liquidity_provider, quote_time) in &self.quote_times {
+            let Some(quote_ttl) = self.quote_ttls.get(liquidity_provider) else {
+                continue;
+            };
+            if now.saturating_sub(*quote_time) > quote_ttl.as_nanos() as u64
+                && !self.stale_lps.contains(liquidity_provider)
+            {
+                expired.push(liquidity_provider.clone());
+            }
+        }
+        if expired.is_empty() {
+            return expired;
+        }
+        expired.sort();
+        let top = self.record_level_changes();
+        for liquidity_provider in &expired {
+            warn!(
+                "{} {} quotes not refreshed within {:?}, removing them from the book",
+                liquidity_provider, self.currency_pair, self.quote_ttls[liquidity_provider]
+            );
+            remove_lp_quotes(get_book_side(self, "Buy"), liquidity_provider);
+            remove_lp_quotes(get_book_side(self, "Sell"), liquidity_provider);
+            self.stale_lps.insert(liquidity_provider.clone());
+        }
+        self.publish_level_changes(top);
+        // the history is sampled no earlier than its latest sample, now may be on the wall clock
+        let time = now.max(self.history.latest);
+        for liquidity_provider in &expired {
+            self.history.record_removed(liquidity_provider, time);
+        }
+        self.record_top_of_book(time);
+        expired
+    }
+
+    pub fn record_top_of_book(&mut self, time: u64) {
+        // sample the top of the book in the price history after a change other than a quote
+        // update, e.g. expired quotes or a fill
+        let (bid, offer) = self.top_prices();
+        self.history.record_top(time, bid, offer);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<BookEvent> {
+        // receive the events of every change to the book from now on
+        self.events.subscribe()
+    }
+
+    pub fn publish(&self, change: BookChange) {
+        // there is nothing to send without subscribers
+        if self.events.receiver_count() > 0 {
+            let _ = self.events.send(BookEvent {
+                currency_pair: self.currency_pair.clone(),
+                change,
+            });
+        }
+    }
+
+    pub fn record_level_changes(&mut self) -> Option<TopOfBook> {
+        // record the levels a change touches on both sides, returning the top of the book before
+        // the change, only needed when there are subscribers
+        if self.events.receiver_count() == 0 {
+            return None;
+        }
+        self.buy_book.record_level_changes();
+        self.sell_book.record_level_changes();
+        Some(top_of_book(&self.buy_book, &self.sell_book))
+    }
+
+    pub fn publish_level_changes(&mut self, top: Option<TopOfBook>) {
+        // publish the changes to the levels touched since they were recorded, followed by any
+        // change to the top of the book
+        let Some(top) = top else {
+            return;
+        };
+        let changes = [
+            self.buy_book.take_level_changes(),
+            self.sell_book.take_level_changes(),
+        ];
+        for change in changes.into_iter().flatten() {
+            self.publish(change);
+        }
+        let (bid, offer) = top_of_book(&self.buy_book, &self.sell_book);
+        if (bid, offer) != top {
+            self.publish(BookChange::TopOfBookChanged { bid, offer });
+        }
+    }
+
+    pub fn top_prices(&self) -> (Option<Price>, Option<Price>) {
+        // best bid and best offer price, None while that side is empty
+        (
+            self.buy_book.first().map(|entry| entry.price),
+            self.sell_book.first().map(|entry| entry.price),
+        )
+    }
+
+    pub fn fill_side(&self, side: Side) -> &BookSide {
+        // the side of the book an order on this side is filled from
+        match side {
+            Side::Buy => &self.sell_book,
+            Side::Sell => &self.buy_book,
+        }
+    }
+
+    pub fn mid(&self) -> Option<f64> {
+        // mid of the top of the book, None while the book is one sided or empty
+        let (buy, sell) = (self.buy_book.first()?, self.sell_book.first()?);
+        Some((buy.price.to_f64() + sell.price.to_f64()) / 2.0)
+    }
+
+    pub fn sweep_vwap(&self, side: Side, volume: i32) -> Option<f64> {
+        // volume weighted average price of sweeping the volume from the top of the book, None
+        // if the book doesn't hold the volume
+        if volume <= 0 {
+            return None;
+        }
+        let mut remaining = volume;
+        let mut notional = 0.0;
+        for entry in self.fill_side(side) {
+            let filled = remaining.min(entry.volume);
+            notional += entry.price.to_f64() * filled as f64;
+            remaining -= filled;
+            if remaining == 0 {
+                return Some(notional / volume as f64);
+            }
+        }
+        None
+    }
+
+    pub fn full_amount_price(&self, side: Side, volume: i32) -> Option<(&str, Price)> {
+        // best price at which a single liquidity provider quotes the full volume, and the first
+        // liquidity provider to quote it at that price
+        if volume <= 0 {
+            return None;
+        }
+        self.fill_side(side).iter().find_map(|entry| {
+            entry
+                .lp_vol
+                .iter()
+                .find(|(liquidity_provider, _)| {
+                    let lp_volume: i32 = entry
+                        .lp_vol
+                        .iter()
+                        .filter(|lp_vol| lp_vol.0 == *liquidity_provider)
+                        .map(|lp_vol| lp_vol.1)
+                        .sum();
+                    lp_volume >= volume
+                })
+                .map(|(liquidity_provider, _)| (liquidity_provider.as_str(), entry.price))
+        })
+    }
+
+    pub fn depth_within(&self, side: Side, pips: f64) -> i32 {
+        // volume quoted within the given number of pips of the top of the book
+        let fill_side = self.fill_side(side);
+        let Some(top) = fill_side.first() else {
+            return 0;
+        };
+        let max_ticks = pips_to_ticks(pips);
+        fill_side
+            .iter()
+            .take_while(|entry| entry.price.diff_ticks(top.price).abs() <= max_ticks)
+            .map(|entry| entry.volume)
+            .sum()
+    }
+}
+
+#[derive(Debug)]
+pub struct BookManager {
+    // one aggregated book per currency pair, ordered by currency pair
+    pub books: BTreeMap<String, FxBook>,
+    pub min_spread_pips: f64,
+    // book policy of each configured currency pair, other currency pairs keep the minimum spread
+    // and remove crossing entries from the longest side
+    pub book_policies: BTreeMap<String, BookPolicyConfig>,
+    // shared by every book to broadcast their changes to the book manager's subscribers
+    pub events: broadcast::Sender<BookEvent>,
+}
+
+impl Default for BookManager {
+    fn default() -> Self {
+        BookManager {
+            books: BTreeMap::new(),
+            min_spread_pips: MIN_SPREAD_PIPS,
+            book_policies: BTreeMap::new(),
+            events: broadcast::Sender::new(BOOK_EVENT_CAPACITY),
+        }
+    }
+}
+
+impl BookManager {
+    pub fn new(config: &[Config], min_spread_pips: f64) -> Self {
+        // create an empty book for every currency pair quoted by the liquidity providers
+        let mut book_manager = BookManager {
+            books: BTreeMap::new(),
+            min_spread_pips,
+            book_policies: BTreeMap::new(),
+            events: broadcast::Sender::new(BOOK_EVENT_CAPACITY),
+        };
+        for config in config {
+            let fx_book = book_manager.get_or_create_book(&config.currency_pair);
+            if let Some(quote_ttl) = config.quote_ttl {
+                fx_book
+                    .quote_ttls
+                    .insert(config.liquidity_provider.clone(), quote_ttl);
+            }
+        }
+        book_manager
+    }
+
+    pub fn with_book_policies(
+        mut self,
+        book_policies: &BTreeMap<String, BookPolicyConfig>,
+    ) -> Self {
+        // apply the configured book policies to the existing books and any created later
+        self.book_policies = book_policies.clone();
+        for (currency_pair, book_policy) in book_policies {
+            self.get_or_create_book(currency_pair).policies = book_policy.create();
+        }
+        self
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<BookEvent> {
+        // receive the events of every change to the books of all currency pairs from now on
+        self.events.subscribe()
+    }
+
+    pub fn expire_quotes(&mut self, now: u64) -> Vec<(String, String)> {
+        // expire the stale quotes in every book, returning the currency pair and liquidity
+        // provider of each newly stale liquidity provider
+        let mut expired: Vec<(String, String)> = Vec::new();
+        for (currency_pair, fx_book) in &mut self.books {
+            for liquidity_provider in fx_book.expire_quotes(now) {
+                expired.push((currency_pair.clone(), liquidity_provider));
+            }
         }
+        expired
+    }
+
+    pub fn update(&mut self, quote: QuoteUpdate) -> Result<&mut FxBook, AppError> {
+        // route the quote update to the book for its currency pair, creating the book the
+        // first time a currency pair is seen, and return the updated book
+        let fx_book = self.get_or_create_book(&quote.currency_pair);
+        fx_book.update(quote)?;
+        Ok(fx_book)
+    }
+
+    pub fn book(&self, currency_pair: &str) -> Option<&FxBook> {
+        self.books.get(currency_pair)
+    }
+
+    pub fn execute(&mut self, order: &Order) -> Result<ExecutionReport, AppError> {
+        self.execute_within(order, &BTreeMap::new())
+    }
+
+    pub fn execute_within(
+        &mut self,
+        order: &Order,
+        lp_limits: &BTreeMap<String, i32>,
+    ) -> Result<ExecutionReport, AppError> {
+        // execute the order against the book for its currency pair, filling no more than the
+        // limit of each liquidity provider in lp_limits
+        let fx_book = self.books.get_mut(&order.currency_pair).ok_or_else(|| {
+            AppError::InvalidOrder(format!("no book for {}", order.currency_pair))
+        })?;
+        let top = fx_book.record_level_changes();
+        let execution_report = order::execute_order_within(fx_book, order, lp_limits)?;
+        fx_book.publish_level_changes(top);
+        // orders carry no time, so the fills are sampled at the source time of the latest change
+        let time = fx_book.history.latest;
+        fx_book.record_top_of_book(time);
+        Ok(execution_report)
+    }
+
+    pub fn currency_pairs(&self) -> Vec<String> {
+        self.books.keys().cloned().collect()
+    }
+
+    fn get_or_create_book(&mut self, currency_pair: &str) -> &mut FxBook {
+        self.books
+            .entry(String::from(currency_pair))
+            .or_insert_with(|| {
+                let book_policy = self
+                    .book_policies
+                    .get(currency_pair)
+                    .cloned()
+                    .unwrap_or_else(|| {
+                        BookPolicyConfig::new(Default::default(), Some(self.min_spread_pips))
+                    });
+                FxBook {
+                    events: self.events.clone(),
+                    ..FxBook::new(currency_pair, &book_policy)
+                }
+            })
     }
 }
 
-fn correct_crossed_books(fx_book: &mut FxBook, index: (usize, f64)) -> Result<(), AppError> {
+pub fn correct_crossed_books(fx_book: &mut FxBook, index: (usize, Price)) {
     // when books have crossed then need to remove all entries above the cross price from the
     // top of the book that has the highest number of entries
     if fx_book.buy_book.len() > fx_book.sell_book.len() {
@@ -105,321 +485,172 @@ fn correct_crossed_books(fx_book: &mut FxBook, index: (usize, f64)) -> Result<()
         );
         remove_range_entries_from_top(fx_book_side, index.0, "Sell");
     }
-
-    Ok(())
-}
-fn add_market_data(fx_book: &mut FxBook, market_data: String) -> Result<(), AppError> {
-    let mut vol_prices_vec: Vec<(i32, f64, String)> = Vec::new();
-
-    let mut market_data_params = get_params(&market_data, 9)?;
-    let liquidity_provider = get_str_field(market_data_params.next())?;
-    let _currency_pair = get_str_field(market_data_params.next())?;
-    let one_mill_buy_price: f64 = market_data_params.next().unwrap_or("").trim().parse()?;
-    vol_prices_vec.push((1, one_mill_buy_price, String::from("Buy")));
-    let one_mill_sell_price: f64 = market_data_params.next().unwrap_or("").trim().parse()?;
-    vol_prices_vec.push((1, one_mill_sell_price, String::from("Sell")));
-    let three_mill_buy_price: f64 = market_data_params.next().unwrap_or("").trim().parse()?;
-    vol_prices_vec.push((3, three_mill_buy_price, String::from("Buy")));
-    let three_mill_sell_price: f64 = market_data_params.next().unwrap_or("").trim().parse()?;
-    vol_prices_vec.push((3, three_mill_sell_price, String::from("Sell")));
-    let five_mill_buy_price: f64 = market_data_params.next().unwrap_or("").trim().parse()?;
-    vol_prices_vec.push((5, five_mill_buy_price, String::from("Buy")));
-    let five_mill_sell_price: f64 = market_data_params.next().unwrap_or("").trim().parse()?;
-    vol_prices_vec.push((5, five_mill_sell_price, String::from("Sell")));
-    let timestamp: u64 = market_data_params.next().unwrap_or("").trim().parse()?;
-
-    fx_book.timestamp = timestamp;
-
-    let mut i = 0;
-    for val in vol_prices_vec {
-        if i % 2 == 0 {
-            //remove expired quotes before adding any new quotes
-            let fx_book_side = get_book_side(fx_book, "Buy");
-            // match check_expired_quotes(fx_book, liquidity_provider, "Buy", val.0) {
-            match check_expired_quotes(fx_book_side, liquidity_provider, val.0) {
-                Some(entry_to_remove) => remove_single_entry(fx_book, "Buy", entry_to_remove),
-                None => (),
-            }
-            add_agg_book_entry(fx_book, liquidity_provider, val.0, val.1, "Buy");
-        } else {
-            let fx_book_side = get_book_side(fx_book, "Sell");
-            match check_expired_quotes(fx_book_side, liquidity_provider, val.0) {
-                //  match check_expired_quotes(fx_book, liquidity_provider, "Sell", val.0) {
-                Some(entry_to_remove) => remove_single_entry(fx_book, "Sell", entry_to_remove),
-                None => (),
-            }
-            add_agg_book_entry(fx_book, liquidity_provider, val.0, val.1, "Sell");
-        }
-        i += 1;
-    }
-
-    Ok(())
 }
 
-pub fn check_expired_quotes(
-    // fx_book: &mut FxBook,
-    fx_book_side: &mut Vec<FxAggBookEntry>,
-    liquidity_provider: &str,
-    // side: &str,
-    volume: i32,
-) -> Option<usize> {
-    // check to see if there is an expired quote from this liquidity provider
-    let mut index = 0;
-    let mut index_to_remove: usize = 0;
-    let mut remove_entry = false;
-    for entry in fx_book_side {
-        let mut total_volume = 0;
-        let lp_vol_vec: &mut Vec<(String, i32)> = &mut entry.lp_vol;
-        // remove expired quote
-        lp_vol_vec.retain(|lp_vol| {
-            (lp_vol.0 != liquidity_provider)
-                || ((lp_vol.0 == liquidity_provider) && (lp_vol.1 != volume))
-        });
-        // check to see if removing expired quote has left behind an fxbook entry with an
-        // empty liquidity provider and volume pair vector. Return index of this entry so
-        // remove_single_entry function can remove this entry
-        if lp_vol_vec.len() == 0 {
-            index_to_remove = index;
-            remove_entry = true;
-        }
-        // need to re-sum the total volumes here in case an expired quote has been removed
-        for val in lp_vol_vec {
-            total_volume += val.1;
+fn replace_quotes(fx_book: &mut FxBook, quote: &QuoteUpdate) {
+    // apply the quote update tier by tier against the liquidity provider's quotes in the book,
+    // as a FIX incremental refresh would: a tier it no longer quotes is deleted, a tier at a new
+    // price is withdrawn from its old price and added at the new one, and a tier it still quotes
+    // at the same price keeps its place and time in the book
+    let liquidity_provider = quote.liquidity_provider.as_str();
+    fx_book.timestamp = quote.source_time;
+    match fx_book.quote_times.get_mut(liquidity_provider) {
+        Some(quote_time) => *quote_time = quote.source_time,
+        None => {
+            fx_book
+                .quote_times
+                .insert(String::from(liquidity_provider), quote.source_time);
         }
-        entry.volume = total_volume;
-        index += 1;
     }
-
-    if remove_entry {
-        return Some(index_to_remove);
-    } else {
-        return None;
+    if fx_book.stale_lps.remove(liquidity_provider) {
+        info!(
+            "{} {} is quoting again",
+            liquidity_provider, fx_book.currency_pair
+        );
     }
+    fx_book
+        .buy_book
+        .replace_lp_tiers(liquidity_provider, &quote.bids, quote.source_time);
+    fx_book
+        .sell_book
+        .replace_lp_tiers(liquidity_provider, &quote.offers, quote.source_time);
+}
+
+pub fn remove_lp_quotes(fx_book_side: &mut BookSide, liquidity_provider: &str) {
+    // remove every quote from this liquidity provider, re-summing the total volume of each entry
+    // and removing any entry left without a liquidity provider and volume pair
+    fx_book_side.remove_lp(liquidity_provider);
 }
 
 pub fn add_agg_book_entry(
     fx_book: &mut FxBook,
     liquidity_provider: &str,
     volume: i32,
-    price: f64,
+    price: Price,
     side: &str,
 ) {
-    let mut lp_vol_vec: Vec<(String, i32)> = Vec::new();
-    lp_vol_vec.push((String::from(liquidity_provider), volume));
-
-    // if first entry then just add it to book
-    // and using fact that first entry is always a Buy in current config
-    if fx_book.buy_book.len() == 0 && side == "Buy" {
-        let new_agg_book_entry = FxAggBookEntry {
-            lp_vol: lp_vol_vec,
-            volume,
-            price,
-            side: String::from(side),
-        };
-        fx_book.buy_book.push(new_agg_book_entry);
-        println!("{fx_book:?}");
-        return;
-    } else if fx_book.buy_book.len() == 0 && fx_book.sell_book.len() == 0 {
-        error!("first entry should not be on sell side in current configuration");
-        return;
-    } else {
-        let fx_book_side = get_book_side(fx_book, side);
-
-        //search to see if current price already in aggregated book
-        for entry in fx_book_side {
-            if entry.price == price {
-                let lp_tup = (String::from(liquidity_provider), volume);
-                entry.lp_vol.push(lp_tup);
-                entry.volume += volume;
-                return;
-            }
-        }
-
-        // this is new entry
-        let new_agg_book_entry = FxAggBookEntry {
-            lp_vol: lp_vol_vec,
-            volume,
-            price,
-            side: String::from(side),
-        };
-        let fx_book_side = get_book_side(fx_book, side);
-        fx_book_side.push(new_agg_book_entry);
-        return;
-    }
-}
-
-fn sort_books(fx_book: &mut FxBook) {
-    let fx_buy_book = get_book_side(fx_book, "Buy");
-    sort_buy_book(fx_buy_book);
-    let fx_sell_book = get_book_side(fx_book, "Sell");
-    sort_sell_book(fx_sell_book);
-
-    /*   fx_book
-    .sell_book
-    .sort_by(|a, b| a.price.partial_cmp(&b.price).unwrap()); */
-}
-
-pub fn sort_buy_book(fx_buy_book: &mut Vec<FxAggBookEntry>) {
-    // need to do a reverse sort on price for buy side
-    fx_buy_book.sort_by(|a, b| match a.price.partial_cmp(&b.price).unwrap() {
-        Ordering::Less => Ordering::Greater,
-        Ordering::Equal => Ordering::Equal,
-        Ordering::Greater => Ordering::Less,
-    });
-}
-
-pub fn sort_sell_book(fx_sell_book: &mut Vec<FxAggBookEntry>) {
-    fx_sell_book.sort_by(|a, b| a.price.partial_cmp(&b.price).unwrap());
+    // join the entry already at the price in the aggregated book, or add a new entry in price
+    // order
+    get_book_side(fx_book, side).add(liquidity_provider, volume, price);
 }
 
-pub fn find_buy_index_when_crossed(
-    fx_buy_book: &mut Vec<FxAggBookEntry>,
-    sell_price: f64,
-) -> Option<usize> {
+pub fn find_buy_index_when_crossed(fx_buy_book: &BookSide, sell_price: Price) -> Option<usize> {
     // when books have crossed and buy book is longer than sell book
     // then need to find where buy price crosses on sell side and remove
     // all buy entries >= new sell price
-
-    for i in (0..fx_buy_book.len()).rev() {
-        if fx_buy_book[i].price >= sell_price {
-            return Some(i);
-        }
-    }
-    return None;
+    fx_buy_book
+        .iter()
+        .take_while(|entry| entry.price >= sell_price)
+        .count()
+        .checked_sub(1)
 }
 
-pub fn check_books_crossed(fx_book: &mut FxBook) -> Option<(usize, f64)> {
-    let top_of_buy_book_price = fx_book.buy_book[0].price;
-    let fx_book_side = get_book_side(fx_book, "Sell");
+pub fn check_books_crossed(fx_book: &mut FxBook) -> Option<(usize, Price)> {
+    let top_of_buy_book_price = fx_book.buy_book.first()?.price;
 
     // if buy book top of book price >= any fx_book.sell_book price then books have crossed
     // so find where buy price crosses on sell side and remove all sell entries <= new buy price
-    for i in (0..fx_book_side.len()).rev() {
-        if top_of_buy_book_price >= fx_book_side[i].price {
-            return Some((i, fx_book_side[i].price));
-        }
-    }
-    return None;
+    fx_book
+        .sell_book
+        .iter()
+        .take_while(|entry| top_of_buy_book_price >= entry.price)
+        .enumerate()
+        .last()
+        .map(|(i, entry)| (i, entry.price))
 }
 
-pub fn maintain_min_spread(fx_book: &mut FxBook) {
-    // if spread is less than 6 pips (arbitrary) then delete top of book entries
-    // until get this minimum spread
-
-    while fx_book.sell_book[0].price - fx_book.buy_book[0].price <= 0.0006 {
+pub fn maintain_min_spread(fx_book: &mut FxBook, min_spread_ticks: i64) {
+    // if spread is less than the minimum spread (6 pips by default) then delete top of book
+    // entries until get this minimum spread. Spread is compared exactly in ticks of the price
+    while let (Some(buy), Some(sell)) = (fx_book.buy_book.first(), fx_book.sell_book.first())
+        && sell.price.diff_ticks(buy.price) <= min_spread_ticks
+    {
         if fx_book.buy_book.len() >= fx_book.sell_book.len() {
             // remove top entry from buy side
             info!("removing top of buy book to maintain spread");
-            remove_single_entry(fx_book, "Buy", 0);
+            fx_book.buy_book.pop_first();
         } else {
             info!("removing top of sell book to maintain spread");
-            remove_single_entry(fx_book, "Sell", 0)
+            fx_book.sell_book.pop_first();
         }
     }
 }
 
-pub fn remove_range_entries_from_top(
-    fx_book_side: &mut Vec<FxAggBookEntry>,
-    index: usize,
-    side: &str,
-) {
-    //  let fx_book_side = get_book_side(fx_book, side);
+pub fn remove_range_entries_from_top(fx_book_side: &mut BookSide, index: usize, side: &str) {
     for i in 0..index + 1 {
-        // because of removal of [0] entry then entry to remove is always the top one [0]
-        fx_book_side.remove(0);
+        // because of removal of the top entry then entry to remove is always the top one
+        fx_book_side.pop_first();
         info!("removing entry {} from {} book", i, side);
     }
 }
 
-fn remove_single_entry(fx_book: &mut FxBook, side: &str, index_to_remove: usize) {
-    // removing an expired quote can leave behind an fxbook entry with an empty
-    // liquidity provider and volume vector. This funtion removes this hanging entry
-
-    let fx_book_side = get_book_side(fx_book, side);
-    fx_book_side.remove(index_to_remove);
-}
-
-pub fn get_book_side<'a>(fx_book: &'a mut FxBook, side: &str) -> &'a mut Vec<FxAggBookEntry> {
+pub fn get_book_side<'a>(fx_book: &'a mut FxBook, side: &str) -> &'a mut BookSide {
     // Because fx_book is the argument that contains the returned vector of book entries
     // then this fx_book argument is the argument that must be connected to the return
     // value using the lifetime syntax
-    if String::from(side) == String::from("Buy") {
+    if side == "Buy" {
         &mut fx_book.buy_book
     } else {
         &mut fx_book.sell_book
     }
 }
 
-pub fn print_fxbook_as_ladder(fx_book: &mut FxBook) {
+pub fn print_fxbook_as_ladder(fx_book: &FxBook) {
+    let mut stdout = io::stdout().lock();
+    if let Err(e) = write_fxbook_as_ladder(&mut stdout, fx_book) {
+        warn!("problem printing FX book - {e}");
+    }
+}
+
+pub fn write_fxbook_as_ladder(writer: &mut impl Write, fx_book: &FxBook) -> io::Result<()> {
     let d = UNIX_EPOCH + Duration::from_nanos(fx_book.timestamp);
     let datetime = DateTime::<Utc>::from(d);
 
-    println!(
+    writeln!(
+        writer,
         "\nCurrent state of FX Book for {} at timestamp {}:\n",
         fx_book.currency_pair,
-        datetime.format("%Y-%m-%d %H:%M:%S.%f").to_string()
-    );
-    println!("Side\t Price\t Volume(M)\t (Liquidity Providers : Volumes(M))");
-    println!("===================================================================");
-    print_sell_side(fx_book);
-    println!("<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>");
-    print_buy_side(fx_book);
+        datetime.format("%Y-%m-%d %H:%M:%S.%f")
+    )?;
+    writeln!(
+        writer,
+        "Side\t Price\t Volume(M)\t (Liquidity Providers : Volumes(M))"
+    )?;
+    writeln!(
+        writer,
+        "==================================================================="
+    )?;
+    // the sell side is written from the highest price down so the best prices meet in the middle
+    write_book_side(writer, fx_book.sell_book.iter().rev())?;
+    writeln!(
+        writer,
+        "<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>"
+    )?;
+    write_book_side(writer, fx_book.buy_book.iter())
 }
 
-fn print_buy_side(fx_book: &mut FxBook) {
-    let fx_book_side = get_book_side(fx_book, "Buy");
-    for entry in fx_book_side {
-        print!("{}:\t {}\t   {}", entry.side, entry.price, entry.volume);
-        let lp_vol_vec: &mut Vec<(String, i32)> = &mut entry.lp_vol;
-        let len = lp_vol_vec.len() - 1;
-        let mut index = 0;
-        for val in lp_vol_vec {
-            if index == 0 && len == 0 {
-                let lp_vol = format!("\t\t ({}: {})", val.0, val.1);
-                print!("{}", lp_vol);
-            } else if index == 0 {
-                let lp_vol = format!("\t\t ({}: {},", val.0, val.1);
-                print!("{}", lp_vol);
-            } else if index == len {
-                let lp_vol = format!(" {}: {})", val.0, val.1);
-                print!("{}", lp_vol);
-            } else {
-                let lp_vol = format!(" {}: {},", val.0, val.1);
-                print!("{}", lp_vol);
-            }
-            index += 1;
-        }
-        print!("\n");
+fn write_book_side<'a>(
+    writer: &mut impl Write,
+    entries: impl Iterator<Item = &'a FxAggBookEntry>,
+) -> io::Result<()> {
+    for entry in entries {
+        writeln!(
+            writer,
+            "{}:\t {}\t   {}\t\t {}",
+            entry.side,
+            entry.price,
+            entry.volume,
+            format_lp_vol(&entry.lp_vol)
+        )?;
     }
+    Ok(())
 }
 
-fn print_sell_side(fx_book: &mut FxBook) {
-    let fx_book_side = get_book_side(fx_book, "Sell");
-
-    for i in (0..fx_book_side.len()).rev() {
-        print!(
-            "{}:\t {}\t   {}",
-            fx_book_side[i].side, fx_book_side[i].price, fx_book_side[i].volume
-        );
-        let lp_vol_vec: &mut Vec<(String, i32)> = &mut fx_book_side[i].lp_vol;
-        let len = lp_vol_vec.len() - 1;
-        let mut index = 0;
-        for val in lp_vol_vec {
-            if index == 0 && len == 0 {
-                let lp_vol = format!("\t\t ({}: {})", val.0, val.1);
-                print!("{}", lp_vol);
-            } else if index == 0 {
-                let lp_vol = format!("\t\t ({}: {},", val.0, val.1);
-                print!("{}", lp_vol);
-            } else if index == len {
-                let lp_vol = format!(" {}: {})", val.0, val.1);
-                print!("{}", lp_vol);
-            } else {
-                let lp_vol = format!(" {}: {},", val.0, val.1);
-                print!("{}", lp_vol);
-            }
-            index += 1;
-        }
-        print!("\n");
-    }
+fn format_lp_vol(lp_vol_vec: &[(String, i32)]) -> String {
+    // format liquidity provider and volume pairs as (LP1: vol1, LP2: vol2)
+    let lp_vol: Vec<String> = lp_vol_vec
+        .iter()
+        .map(|val| format!("{}: {}", val.0, val.1))
+        .collect();
+    format!("({})", lp_vol.join(", "))
 }
@@ -0,0 +1,96 @@
+//! # FX Simulator and Aggregator - fx_sim_agg
+//!
+//! `book_event.rs` defines the typed events an aggregated book broadcasts to its subscribers as it changes, so
+//! strategies and loggers can react to the changes they are interested in, such as the top of the book moving,
+//! rather than locking and diffing the whole book.
+use crate::book_side::BookSide;
+use crate::price::Price;
+
+// events buffered for each subscriber, a subscriber that falls further behind misses the oldest
+pub const BOOK_EVENT_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookEvent {
+    pub currency_pair: String,
+    pub change: BookChange,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BookChange {
+    // a liquidity provider's quotes were replaced by its quote update
+    QuoteReplaced {
+        liquidity_provider: String,
+        sequence_number: u64,
+        source_time: u64,
+    },
+    // the book policy uncrossed the book crossed at this bid and offer
+    CrossedBookCorrected {
+        bid: Price,
+        offer: Price,
+    },
+    // the top of the book was removed to keep the minimum spread in ticks, leaving this spread
+    SpreadEnforced {
+        min_spread: i64,
+        spread: Option<i64>,
+    },
+    // side is the "Buy" or "Sell" side of the book
+    LevelAdded {
+        side: String,
+        price: Price,
+        volume: i32,
+    },
+    LevelChanged {
+        side: String,
+        price: Price,
+        volume: i32,
+        previous_volume: i32,
+    },
+    LevelRemoved {
+        side: String,
+        price: Price,
+        previous_volume: i32,
+    },
+    // the best bid or offer price or volume changed, None when that side is empty
+    TopOfBookChanged {
+        bid: Option<(Price, i32)>,
+        offer: Option<(Price, i32)>,
+    },
+}
+
+// best bid and best offer price and volume, None while that side is empty
+pub type TopOfBook = (Option<(Price, i32)>, Option<(Price, i32)>);
+
+pub fn top_of_book(buy_book: &BookSide, sell_book: &BookSide) -> TopOfBook {
+    let top = |book: &BookSide| book.first().map(|e| (e.price, e.volume));
+    (top(buy_book), top(sell_book))
+}
+
+pub fn level_change(
+    side: &str,
+    price: Price,
+    previous_volume: i32,
+    volume: i32,
+) -> Option<BookChange> {
+    // the change to the level at the price from its previous volume, 0 for no level
+    let side = String::from(side);
+    match (previous_volume, volume) {
+        (0, 0) => None,
+        (0, volume) => Some(BookChange::LevelAdded {
+            side,
+            price,
+            volume,
+        }),
+        (previous_volume, 0) => Some(BookChange::LevelRemoved {
+            side,
+            price,
+            previous_volume,
+        }),
+        (previous_volume, volume) if previous_volume != volume => Some(BookChange::LevelChanged {
+            side,
+            price,
+            volume,
+            previous_volume,
+        }),
+        _ => None,
+    }
+}
@@ -0,0 +1,231 @@
+//! # FX Simulator and Aggregator - fx_sim_agg
+//!
+//! `book_policy.rs` defines the `BookPolicy` trait applied to an aggregated book after every quote update, and the
+//! policies that can be configured per currency pair for handling crossed books and keeping a minimum spread.
+use crate::AppError;
+use crate::aggregator::{
+    FxAggBookEntry, FxBook, MIN_SPREAD_PIPS, check_books_crossed, correct_crossed_books,
+    get_book_side, maintain_min_spread,
+};
+use crate::book_event::BookChange;
+use crate::book_side::BookSide;
+use crate::price::pips_to_ticks;
+use log::info;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+pub trait BookPolicy: Debug + Send + Sync {
+    // applied to the book after the liquidity provider's quote update has been added
+    fn apply(&self, fx_book: &mut FxBook, liquidity_provider: &str);
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum CrossedBookPolicy {
+    // remove the crossing entries from the top of the side with the most entries
+    #[default]
+    LongestSide,
+    // leave crossed books as they are, flagged as crossed
+    Allow,
+    // remove the crossing quote of the liquidity provider that quoted least recently
+    DropOlder,
+    // remove the crossing quotes of the liquidity provider whose quote update crossed the book
+    DropOffending,
+    // remove the entries of both sides at or through the midpoint of the crossing prices
+    Midpoint,
+}
+
+impl CrossedBookPolicy {
+    pub fn parse(name: &str) -> Result<Self, AppError> {
+        match name {
+            "longest_side" => Ok(CrossedBookPolicy::LongestSide),
+            "allow" => Ok(CrossedBookPolicy::Allow),
+            "drop_older" => Ok(CrossedBookPolicy::DropOlder),
+            "drop_offending" => Ok(CrossedBookPolicy::DropOffending),
+            "midpoint" => Ok(CrossedBookPolicy::Midpoint),
+            _ => Err(AppError::InvalidConfig(format!(
+                "unknown crossed book policy {name}, expected longest_side, allow, drop_older, drop_offending or midpoint"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookPolicyConfig {
+    pub crossed: CrossedBookPolicy,
+    // minimum spread in ticks kept between the top of the buy and sell books, None for no minimum
+    pub min_spread: Option<i64>,
+}
+
+impl Default for BookPolicyConfig {
+    fn default() -> Self {
+        BookPolicyConfig::new(CrossedBookPolicy::default(), Some(MIN_SPREAD_PIPS))
+    }
+}
+
+impl BookPolicyConfig {
+    pub fn new(crossed: CrossedBookPolicy, min_spread_pips: Option<f64>) -> Self {
+        BookPolicyConfig {
+            crossed,
+            min_spread: min_spread_pips.map(pips_to_ticks),
+        }
+    }
+
+    pub fn create(&self) -> Vec<Arc<dyn BookPolicy>> {
+        // the crossed book policy is applied before the minimum spread
+        let mut policies: Vec<Arc<dyn BookPolicy>> = vec![match self.crossed {
+            CrossedBookPolicy::LongestSide => Arc::new(LongestSide),
+            CrossedBookPolicy::Allow => Arc::new(AllowCrossed),
+            CrossedBookPolicy::DropOlder => Arc::new(DropOlderQuote),
+            CrossedBookPolicy::DropOffending => Arc::new(DropOffendingQuote),
+            CrossedBookPolicy::Midpoint => Arc::new(UncrossAtMidpoint),
+        }];
+        if let Some(min_spread) = self.min_spread {
+            policies.push(Arc::new(MinSpread { min_spread }));
+        }
+        policies
+    }
+}
+
+#[derive(Debug)]
+pub struct LongestSide;
+
+impl BookPolicy for LongestSide {
+    fn apply(&self, fx_book: &mut FxBook, _liquidity_provider: &str) {
+        while let Some(index) = check_books_crossed(fx_book) {
+            info!(
+                "books crossed at sell book index {} with sell price {}",
+                index.0, index.1
+            );
+            correct_crossed_books(fx_book, index);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AllowCrossed;
+
+impl BookPolicy for AllowCrossed {
+    fn apply(&self, _fx_book: &mut FxBook, _liquidity_provider: &str) {
+        // the book flags itself as crossed after the policies have been applied
+    }
+}
+
+#[derive(Debug)]
+pub struct DropOlderQuote;
+
+impl BookPolicy for DropOlderQuote {
+    fn apply(&self, fx_book: &mut FxBook, _liquidity_provider: &str) {
+        // of the liquidity providers' tiers at the top of either side, drop the one added or
+        // changed at the earliest source time until the book is no longer crossed
+        while check_books_crossed(fx_book).is_some() {
+            let (Some(buy), Some(sell)) = (fx_book.buy_book.first(), fx_book.sell_book.first())
+            else {
+                return;
+            };
+            let top_lps = [
+                ("Buy", &fx_book.buy_book, buy),
+                ("Sell", &fx_book.sell_book, sell),
+            ]
+            .into_iter()
+            .flat_map(|(side, book_side, entry)| {
+                entry
+                    .lp_vol
+                    .iter()
+                    .map(move |lp_vol| (side, book_side, entry.price, lp_vol))
+            });
+            let Some((side, _, _, (liquidity_provider, _))) =
+                top_lps.min_by_key(|(_, book_side, price, (liquidity_provider, volume))| {
+                    book_side.tier_time(liquidity_provider, *price, *volume)
+                })
+            else {
+                return;
+            };
+            let (side, liquidity_provider) = (side, liquidity_provider.clone());
+            info!("removing the older crossing {side} quote of {liquidity_provider}");
+            remove_lp_from_top(get_book_side(fx_book, side), &liquidity_provider);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DropOffendingQuote;
+
+impl BookPolicy for DropOffendingQuote {
+    fn apply(&self, fx_book: &mut FxBook, liquidity_provider: &str) {
+        // drop the liquidity provider's quotes from the top of the book until the book is no
+        // longer crossed, leaving the quotes of the other liquidity providers it crossed
+        while check_books_crossed(fx_book).is_some() {
+            let quotes_top = |entry: &FxAggBookEntry| {
+                entry
+                    .lp_vol
+                    .iter()
+                    .any(|lp_vol| lp_vol.0 == liquidity_provider)
+            };
+            let side = if fx_book.buy_book.first().is_some_and(quotes_top) {
+                "Buy"
+            } else if fx_book.sell_book.first().is_some_and(quotes_top) {
+                "Sell"
+            } else {
+                return;
+            };
+            info!("removing the crossing {side} quote of {liquidity_provider}");
+            remove_lp_from_top(get_book_side(fx_book, side), liquidity_provider);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct UncrossAtMidpoint;
+
+impl BookPolicy for UncrossAtMidpoint {
+    fn apply(&self, fx_book: &mut FxBook, _liquidity_provider: &str) {
+        // remove the bids at or above, and offers at or below, the midpoint of the top bid and
+        // offer so both sides give up the crossing liquidity
+        if check_books_crossed(fx_book).is_none() {
+            return;
+        }
+        let (Some(buy), Some(sell)) = (fx_book.buy_book.first(), fx_book.sell_book.first()) else {
+            return;
+        };
+        let (bid, offer) = (buy.price, sell.price);
+        let midpoint = offer.add_ticks(bid.diff_ticks(offer) / 2);
+        info!("uncrossing the book at the midpoint {midpoint} of {bid} and {offer}");
+        fx_book.buy_book.retain(|entry| entry.price < midpoint);
+        fx_book.sell_book.retain(|entry| entry.price > midpoint);
+    }
+}
+
+#[derive(Debug)]
+pub struct MinSpread {
+    // minimum spread in ticks
+    pub min_spread: i64,
+}
+
+impl BookPolicy for MinSpread {
+    fn apply(&self, fx_book: &mut FxBook, _liquidity_provider: &str) {
+        // crossed books are left to the crossed book policy
+        if check_books_crossed(fx_book).is_some() {
+            return;
+        }
+        let levels = (fx_book.buy_book.len(), fx_book.sell_book.len());
+        maintain_min_spread(fx_book, self.min_spread);
+        if levels != (fx_book.buy_book.len(), fx_book.sell_book.len()) {
+            let spread = match (fx_book.buy_book.first(), fx_book.sell_book.first()) {
+                (Some(buy), Some(sell)) => Some(sell.price.diff_ticks(buy.price)),
+                _ => None,
+            };
+            fx_book.publish(BookChange::SpreadEnforced {
+                min_spread: self.min_spread,
+                spread,
+            });
+        }
+    }
+}
+
+fn remove_lp_from_top(fx_book_side: &mut BookSide, liquidity_provider: &str) {
+    // remove the liquidity provider's volume from the top entry, and the entry if it is left empty
+    let Some(price) = fx_book_side.first().map(|entry| entry.price) else {
+        return;
+    };
+    fx_book_side.remove_lp_at(liquidity_provider, price);
+}
@@ -0,0 +1,392 @@
+//! # FX Simulator and Aggregator - fx_sim_agg
+//!
+//! `book_side.rs` defines `BookSide`, one side of an aggregated book keyed by price. Levels are kept in price order,
+//! best first, and each liquidity provider is indexed to the quotes it has at each price and when they were added, so
+//! adding, replacing or withdrawing a liquidity provider's quotes takes O(log n) per level rather than sorting or
+//! scanning the whole side. While a book has
+//! subscribers each side records the prices a change touches, so its level events are built from those levels alone.
+use crate::aggregator::FxAggBookEntry;
+use crate::book_event::{self, BookChange};
+use crate::price::Price;
+use crate::quote::QuoteTier;
+use std::collections::btree_map::{self, Entry};
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LpTier {
+    pub price: Price,
+    // volume in millions still quoted, less any filled
+    pub volume: i32,
+    // source time in nanoseconds the quote was added at its price and volume
+    pub time: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct BookSide {
+    // "Buy" for the bids, best first from the highest price, or "Sell" for the offers, best
+    // first from the lowest price
+    side: String,
+    levels: BTreeMap<Price, FxAggBookEntry>,
+    // each liquidity provider's quotes in the order they were added, matching its volume at the
+    // levels as quotes are added, filled and removed
+    lp_tiers: HashMap<String, Vec<LpTier>>,
+    // volume before the change of each price touched while level changes are recorded, 0 for a
+    // price without a level
+    touched: Option<BTreeMap<Price, i32>>,
+}
+
+impl BookSide {
+    pub fn new(side: &str) -> Self {
+        BookSide {
+            side: String::from(side),
+            levels: BTreeMap::new(),
+            lp_tiers: HashMap::new(),
+            touched: None,
+        }
+    }
+
+    pub fn from_entries(side: &str, entries: impl IntoIterator<Item = FxAggBookEntry>) -> Self {
+        // entries at the same price are merged into one level, in the order given
+        let mut book_side = BookSide::new(side);
+        for entry in entries {
+            for (liquidity_provider, volume) in entry.lp_vol {
+                book_side.add(&liquidity_provider, volume, entry.price);
+            }
+        }
+        book_side
+    }
+
+    pub fn side(&self) -> &str {
+        &self.side
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels.is_empty()
+    }
+
+    pub fn first(&self) -> Option<&FxAggBookEntry> {
+        self.iter().next()
+    }
+
+    pub fn iter(&self) -> Levels<'_> {
+        Levels {
+            levels: self.levels.values(),
+            descending: self.descending(),
+        }
+    }
+
+    pub fn level(&self, price: Price) -> Option<&FxAggBookEntry> {
+        self.levels.get(&price)
+    }
+
+    pub fn to_vec(&self) -> Vec<FxAggBookEntry> {
+        self.iter().cloned().collect()
+    }
+
+    pub fn add(&mut self, liquidity_provider: &str, volume: i32, price: Price) {
+        // add the volume to the level at the price, behind the liquidity providers already
+        // quoting it, or as a new level
+        self.add_at(liquidity_provider, volume, price, 0);
+    }
+
+    pub fn add_at(&mut self, liquidity_provider: &str, volume: i32, price: Price, time: u64) {
+        // add a quote the liquidity provider sent at the source time
+        self.add_to_level(liquidity_provider, volume, price);
+        let tier = LpTier {
+            price,
+            volume,
+            time,
+        };
+        match self.lp_tiers.get_mut(liquidity_provider) {
+            Some(tiers) => tiers.push(tier),
+            None => {
+                self.lp_tiers
+                    .insert(String::from(liquidity_provider), vec![tier]);
+            }
+        }
+    }
+
+    pub fn replace_lp_tiers(&mut self, liquidity_provider: &str, tiers: &[QuoteTier], time: u64) {
+        // apply the liquidity provider's latest tiers against its quotes on this side: a quote
+        // it no longer sends is withdrawn, a new tier is added behind the quotes already at its
+        // price, and a tier still quoted at the same price and volume keeps its place and time
+        let (key, mut quoted) = self
+            .lp_tiers
+            .remove_entry(liquidity_provider)
+            .unwrap_or_else(|| (String::from(liquidity_provider), Vec::new()));
+        let mut added: Vec<&QuoteTier> = tiers.iter().collect();
+        quoted.retain(|quote| {
+            match added
+                .iter()
+                .position(|tier| tier.price == quote.price && tier.volume == quote.volume)
+            {
+                Some(position) => {
+                    added.remove(position);
+                    true
+                }
+                None => {
+                    self.remove_from_level(liquidity_provider, quote.price, quote.volume);
+                    false
+                }
+            }
+        });
+        for tier in added {
+            self.add_to_level(liquidity_provider, tier.volume, tier.price);
+            quoted.push(LpTier {
+                price: tier.price,
+                volume: tier.volume,
+                time,
+            });
+        }
+        if !quoted.is_empty() {
+            self.lp_tiers.insert(key, quoted);
+        }
+    }
+
+    pub fn remove_lp(&mut self, liquidity_provider: &str) {
+        // withdraw the liquidity provider's volume from every level it quotes
+        let Some(tiers) = self.lp_tiers.remove(liquidity_provider) else {
+            return;
+        };
+        for tier in tiers {
+            self.remove_lp_volume(liquidity_provider, tier.price);
+        }
+    }
+
+    pub fn remove_lp_at(&mut self, liquidity_provider: &str, price: Price) {
+        // withdraw the liquidity provider's volume from the level at the price
+        self.unindex_lp(liquidity_provider, price);
+        self.remove_lp_volume(liquidity_provider, price);
+    }
+
+    pub fn lp_tiers(&self, liquidity_provider: &str) -> &[LpTier] {
+        // the liquidity provider's quotes in the order they were added
+        self.lp_tiers
+            .get(liquidity_provider)
+            .map_or(&[], Vec::as_slice)
+    }
+
+    pub fn tier_time(&self, liquidity_provider: &str, price: Price, volume: i32) -> Option<u64> {
+        // when the liquidity provider's quote of the volume at the price was added
+        self.lp_tiers(liquidity_provider)
+            .iter()
+            .find(|tier| tier.price == price && tier.volume == volume)
+            .map(|tier| tier.time)
+    }
+
+    pub fn fill(&mut self, liquidity_provider: &str, price: Price, volume: i32) {
+        // take the filled volume from the liquidity provider's first quote at the price with
+        // volume left, withdrawing the quote once it is used up
+        self.touch(price);
+        let Some(entry) = self.levels.get_mut(&price) else {
+            return;
+        };
+        let Some(lp_vol) = entry
+            .lp_vol
+            .iter_mut()
+            .find(|lp_vol| lp_vol.0 == liquidity_provider && lp_vol.1 > 0)
+        else {
+            return;
+        };
+        lp_vol.1 -= volume;
+        entry.volume -= volume;
+        if lp_vol.1 <= 0 {
+            entry.lp_vol.retain(|lp_vol| lp_vol.1 > 0);
+            if entry.lp_vol.is_empty() {
+                self.levels.remove(&price);
+            }
+        }
+        if let Some(tiers) = self.lp_tiers.get_mut(liquidity_provider)
+            && let Some(position) = tiers
+                .iter()
+                .position(|tier| tier.price == price && tier.volume > 0)
+        {
+            tiers[position].volume -= volume;
+            if tiers[position].volume <= 0 {
+                tiers.remove(position);
+                if tiers.is_empty() {
+                    self.lp_tiers.remove(liquidity_provider);
+                }
+            }
+        }
+    }
+
+    pub fn pop_first(&mut self) -> Option<FxAggBookEntry> {
+        // remove the best level
+        let price = self.first()?.price;
+        self.touch(price);
+        let entry = if self.descending() {
+            self.levels.pop_last()
+        } else {
+            self.levels.pop_first()
+        }
+        .map(|(_, entry)| entry)?;
+        self.unindex(&entry);
+        Some(entry)
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&FxAggBookEntry) -> bool) {
+        let removed: Vec<Price> = self
+            .levels
+            .values()
+            .filter(|entry| !keep(entry))
+            .map(|entry| entry.price)
+            .collect();
+        for price in removed {
+            self.touch(price);
+            if let Some(entry) = self.levels.remove(&price) {
+                self.unindex(&entry);
+            }
+        }
+    }
+
+    pub fn record_level_changes(&mut self) {
+        // record the prices touched from now on, replacing any recorded so far
+        self.touched = Some(BTreeMap::new());
+    }
+
+    pub fn take_level_changes(&mut self) -> Vec<BookChange> {
+        // the levels added, changed and removed since level changes were recorded, in price
+        // order, and stop recording them
+        let Some(touched) = self.touched.take() else {
+            return Vec::new();
+        };
+        touched
+            .into_iter()
+            .filter_map(|(price, previous_volume)| {
+                let volume = self.levels.get(&price).map_or(0, |entry| entry.volume);
+                book_event::level_change(&self.side, price, previous_volume, volume)
+            })
+            .collect()
+    }
+
+    fn descending(&self) -> bool {
+        self.side == "Buy"
+    }
+
+    fn touch(&mut self, price: Price) {
+        // keep the volume at the price before the first change to it
+        if let Some(touched) = &mut self.touched {
+            let levels = &self.levels;
+            touched
+                .entry(price)
+                .or_insert_with(|| levels.get(&price).map_or(0, |entry| entry.volume));
+        }
+    }
+
+    fn add_to_level(&mut self, liquidity_provider: &str, volume: i32, price: Price) {
+        self.touch(price);
+        let lp_vol = (String::from(liquidity_provider), volume);
+        match self.levels.entry(price) {
+            Entry::Occupied(mut level) => {
+                let entry = level.get_mut();
+                entry.lp_vol.push(lp_vol);
+                entry.volume += volume;
+            }
+            Entry::Vacant(level) => {
+                level.insert(FxAggBookEntry {
+                    lp_vol: vec![lp_vol],
+                    volume,
+                    price,
+                    side: self.side.clone(),
+                });
+            }
+        }
+    }
+
+    fn remove_from_level(&mut self, liquidity_provider: &str, price: Price, volume: i32) {
+        // withdraw one of the liquidity provider's quotes of the volume at the price, leaving its
+        // other quotes at the level where they are
+        self.touch(price);
+        let Some(entry) = self.levels.get_mut(&price) else {
+            return;
+        };
+        if let Some(position) = entry
+            .lp_vol
+            .iter()
+            .position(|lp_vol| lp_vol.0 == liquidity_provider && lp_vol.1 == volume)
+        {
+            entry.lp_vol.remove(position);
+            entry.volume -= volume;
+        }
+        if entry.lp_vol.is_empty() {
+            self.levels.remove(&price);
+        }
+    }
+
+    fn remove_lp_volume(&mut self, liquidity_provider: &str, price: Price) {
+        // remove the level too if no other liquidity provider quotes it
+        self.touch(price);
+        let Some(entry) = self.levels.get_mut(&price) else {
+            return;
+        };
+        entry.lp_vol.retain(|lp_vol| lp_vol.0 != liquidity_provider);
+        entry.volume = entry.lp_vol.iter().map(|lp_vol| lp_vol.1).sum();
+        if entry.lp_vol.is_empty() {
+            self.levels.remove(&price);
+        }
+    }
+
+    fn unindex(&mut self, entry: &FxAggBookEntry) {
+        for (liquidity_provider, _) in &entry.lp_vol {
+            self.unindex_lp(liquidity_provider, entry.price);
+        }
+    }
+
+    fn unindex_lp(&mut self, liquidity_provider: &str, price: Price) {
+        if let Some(tiers) = self.lp_tiers.get_mut(liquidity_provider) {
+            tiers.retain(|tier| tier.price != price);
+            if tiers.is_empty() {
+                self.lp_tiers.remove(liquidity_provider);
+            }
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a BookSide {
+    type Item = &'a FxAggBookEntry;
+    type IntoIter = Levels<'a>;
+
+    fn into_iter(self) -> Levels<'a> {
+        self.iter()
+    }
+}
+
+// the levels of a book side from the best price
+#[derive(Debug, Clone)]
+pub struct Levels<'a> {
+    levels: btree_map::Values<'a, Price, FxAggBookEntry>,
+    descending: bool,
+}
+
+impl<'a> Iterator for Levels<'a> {
+    type Item = &'a FxAggBookEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.descending {
+            self.levels.next_back()
+        } else {
+            self.levels.next()
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.levels.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for Levels<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.descending {
+            self.levels.next()
+        } else {
+            self.levels.next_back()
+        }
+    }
+}
+
+impl ExactSizeIterator for Levels<'_> {}
@@ -0,0 +1,104 @@
+//! # FX Simulator and Aggregator - fx_sim_agg
+//!
+//! `cli.rs` defines the command line arguments of the `fx_sim_agg` binary. Arguments given on the command
+//! line override the matching settings of the config file.
+use crate::AppError;
+use crate::config::{AppConfig, DEFAULT_CONFIG_FILE};
+use crate::replay::{Replay, ReplaySpeed};
+use crate::simulator;
+use clap::Parser;
+use std::time::Duration;
+
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "Simulates FX liquidity providers and aggregates their quotes into a book per currency pair"
+)]
+pub struct Cli {
+    /// TOML config file, or a CSV config file in the original format
+    #[arg(short, long, value_name = "FILE", default_value = DEFAULT_CONFIG_FILE)]
+    pub config: String,
+
+    /// log4rs config file
+    #[arg(long, value_name = "FILE")]
+    pub log_config: Option<String>,
+
+    /// File the FIX market data messages are logged to
+    #[arg(long, value_name = "FILE")]
+    pub fix_log: Option<String>,
+
+    /// Seed for a reproducible simulation on a virtual clock, from 0 to 9223372036854775807
+    #[arg(short, long, value_parser = clap::value_parser!(u64).range(..=simulator::MAX_SEED))]
+    pub seed: Option<u64>,
+
+    /// Print each updated book as a ladder to the terminal
+    #[arg(long, overrides_with = "no_ladder")]
+    pub ladder: bool,
+
+    /// Do not print the book ladder to the terminal
+    #[arg(long, overrides_with = "ladder")]
+    pub no_ladder: bool,
+
+    /// Run the simulation and aggregation without the GUI, exiting when every liquidity provider is done
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Stop the simulation after this many seconds
+    #[arg(short, long, value_name = "SECONDS", value_parser = parse_duration)]
+    pub duration: Option<Duration>,
+
+    /// Rebuild the books from a recorded FIX log instead of simulating the liquidity providers
+    #[arg(short, long, value_name = "FILE")]
+    pub replay: Option<String>,
+
+    /// Blotter file of trades to keep positions and P&L for
+    #[arg(short, long, value_name = "FILE")]
+    pub blotter: Option<String>,
+
+    /// Replay speed: realtime (the default), a multiplier such as 10x, or max for as fast as possible
+    #[arg(long, value_name = "SPEED", requires = "replay", value_parser = parse_replay_speed)]
+    pub replay_speed: Option<ReplaySpeed>,
+}
+
+impl Cli {
+    pub fn apply(&self, config: &mut AppConfig) -> Result<(), AppError> {
+        // override the config file settings with any given on the command line
+        if let Some(log_config) = &self.log_config {
+            config.logging.log_config = log_config.clone();
+        }
+        if let Some(fix_log) = &self.fix_log {
+            config.logging.fix_log = fix_log.clone();
+        }
+        if self.seed.is_some() {
+            config.general.seed = self.seed;
+        }
+        if self.ladder {
+            config.general.print_ladder = true;
+        }
+        if self.no_ladder {
+            config.general.print_ladder = false;
+        }
+        if self.duration.is_some() {
+            config.general.run_duration = self.duration;
+        }
+        if self.blotter.is_some() {
+            config.general.blotter = self.blotter.clone();
+        }
+        if let Some(file) = &self.replay {
+            let speed = self.replay_speed.unwrap_or(ReplaySpeed::Multiplier(1.0));
+            config.replay = Some(Replay::load(file, speed)?);
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_duration(seconds: &str) -> Result<Duration, String> {
+    match seconds.parse::<f64>() {
+        Ok(seconds) if seconds > 0.0 && seconds.is_finite() => Ok(Duration::from_secs_f64(seconds)),
+        _ => Err(format!("{seconds} is not a positive number of seconds")),
+    }
+}
+
+pub fn parse_replay_speed(speed: &str) -> Result<ReplaySpeed, String> {
+    ReplaySpeed::parse(speed).map_err(|e| e.to_string())
+}
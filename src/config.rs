@@ -0,0 +1,873 @@
+//! # FX Simulator and Aggregator - fx_sim_agg
+//!
+//! `config.rs` loads the simulator configuration from a TOML file with global sections and a section per
+//! liquidity provider, or imports the liquidity providers from a CSV config file in the original format.
+//! Any problem is reported with the file, line, liquidity provider and field it was found in.
+use crate::AppError;
+use crate::aggregator::MIN_SPREAD_PIPS;
+use crate::book_policy::{BookPolicyConfig, CrossedBookPolicy};
+use crate::last_look::LastLookConfig;
+use crate::price::{Price, pips_to_ticks, price_decimals};
+use crate::price_model::PriceModelConfig;
+use crate::replay::Replay;
+use crate::risk::RiskLimits;
+use crate::simulator::{self, Config, Tier};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use toml::Spanned;
+
+pub const DEFAULT_CONFIG_FILE: &str = "resources/config.toml";
+// number of quotes each liquidity provider sends when not given in the config file
+const DEFAULT_ITERATIONS: i32 = 20;
+// error message for pips and other values that must be 0 or above
+pub const NON_NEGATIVE: &str = "must be a finite number, not negative";
+// column names of the CSV config file, used to name the field in error messages
+const CSV_COLUMNS: [&str; 11] = [
+    "name",
+    "currency pair",
+    "1M buy price",
+    "spread",
+    "3M markup",
+    "5M markup",
+    "no. iterations",
+    "market data type",
+    "seed",
+    "price model",
+    "tiers",
+];
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub file: String,
+    pub line: Option<usize>,
+    pub liquidity_provider: Option<String>,
+    pub field: Option<String>,
+    pub message: String,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.file)?;
+        if let Some(line) = self.line {
+            write!(f, ", line {line}")?;
+        }
+        if let Some(liquidity_provider) = &self.liquidity_provider {
+            write!(f, ", liquidity provider {liquidity_provider}")?;
+        }
+        if let Some(field) = &self.field {
+            write!(f, ", field {field}")?;
+        }
+        write!(f, " - {}", self.message)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    pub general: GeneralConfig,
+    pub logging: LoggingConfig,
+    pub gui: GuiConfig,
+    // pre-trade risk limits orders are checked against
+    pub risk: RiskLimits,
+    // crossed book and minimum spread policy of each currency pair with its own
+    pub book_policies: BTreeMap<String, BookPolicyConfig>,
+    pub liquidity_providers: Vec<Config>,
+    // recorded FIX log to replay instead of simulating the liquidity providers
+    pub replay: Option<Replay>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GeneralConfig {
+    // seed for a reproducible simulation on a virtual clock, random if not given
+    pub seed: Option<u64>,
+    pub min_spread_pips: f64,
+    // print each updated book as a ladder to the terminal
+    pub print_ladder: bool,
+    // stop the simulation after this long, otherwise run until every liquidity provider is done
+    pub run_duration: Option<Duration>,
+    // quote time to live of liquidity providers without their own
+    pub quote_ttl: Option<Duration>,
+    // file of trades to keep positions and P&L for
+    pub blotter: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LoggingConfig {
+    // log4rs configuration file
+    pub log_config: String,
+    pub fix_log: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct GuiConfig {
+    pub title: String,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        AppConfig {
+            general: GeneralConfig {
+                seed: None,
+                min_spread_pips: MIN_SPREAD_PIPS,
+                print_ladder: true,
+                run_duration: None,
+                quote_ttl: None,
+                blotter: None,
+            },
+            logging: LoggingConfig {
+                log_config: String::from("logging_config.yaml"),
+                fix_log: String::from("logs/fix.log"),
+            },
+            gui: GuiConfig {
+                title: String::from("FX Aggregated Book"),
+                width: 590.,
+                height: 380.,
+            },
+            risk: RiskLimits::default(),
+            book_policies: BTreeMap::new(),
+            liquidity_providers: Vec::new(),
+            replay: None,
+        }
+    }
+}
+
+// layout of the TOML config file, with spans kept for the values that are validated so that
+// errors can give their line
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    general: GeneralSection,
+    #[serde(default)]
+    logging: LoggingSection,
+    #[serde(default)]
+    gui: GuiSection,
+    #[serde(default)]
+    risk: RiskSection,
+    #[serde(default, rename = "book_policy")]
+    book_policies: Vec<BookPolicySection>,
+    #[serde(default, rename = "liquidity_provider")]
+    liquidity_providers: Vec<LiquidityProviderSection>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct GeneralSection {
+    seed: Option<u64>,
+    min_spread_pips: Option<Spanned<f64>>,
+    print_ladder: Option<bool>,
+    run_duration_secs: Option<Spanned<f64>>,
+    quote_ttl_secs: Option<Spanned<f64>>,
+    blotter: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LoggingSection {
+    log_config: Option<String>,
+    fix_log: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct GuiSection {
+    title: Option<String>,
+    width: Option<Spanned<f32>>,
+    height: Option<Spanned<f32>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RiskSection {
+    max_order_volume: Option<Spanned<i32>>,
+    max_net_position: Option<Spanned<i64>>,
+    price_band_pips: Option<Spanned<f64>>,
+    credit_lines: BTreeMap<String, Spanned<i64>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BookPolicySection {
+    currency_pair: Spanned<String>,
+    #[serde(default)]
+    crossed: Option<Spanned<String>>,
+    #[serde(default)]
+    min_spread_pips: Option<Spanned<f64>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LiquidityProviderSection {
+    name: Spanned<String>,
+    currency_pair: Spanned<String>,
+    buy_price: Spanned<f64>,
+    spread_pips: Spanned<f64>,
+    #[serde(default)]
+    iterations: Option<Spanned<i32>>,
+    #[serde(default)]
+    market_data: Option<Spanned<String>>,
+    #[serde(default)]
+    seed: Option<u64>,
+    #[serde(default)]
+    price_model: Option<Spanned<String>>,
+    #[serde(default)]
+    tiers: Option<Spanned<Vec<TierSection>>>,
+    #[serde(default)]
+    quote_ttl_secs: Option<Spanned<f64>>,
+    #[serde(default)]
+    last_look: Option<Spanned<LastLookSection>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LastLookSection {
+    #[serde(default)]
+    hold_ms: u64,
+    #[serde(default)]
+    reject_probability: f64,
+    #[serde(default)]
+    max_price_move_pips: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TierSection {
+    volume: i32,
+    #[serde(default)]
+    bid_markup_pips: f64,
+    #[serde(default)]
+    offer_markup_pips: f64,
+}
+
+pub fn load_config(file: &str) -> Result<AppConfig, AppError> {
+    // TOML config files are read in full, any other file is imported as a CSV config file
+    let contents = fs::read_to_string(file).map_err(|e| {
+        AppError::Config(ConfigError {
+            file: String::from(file),
+            line: None,
+            liquidity_provider: None,
+            field: None,
+            message: e.to_string(),
+        })
+    })?;
+    let is_toml = Path::new(file)
+        .extension()
+        .is_some_and(|extension| extension == "toml");
+    if is_toml {
+        parse_toml(file, &contents)
+    } else {
+        Ok(AppConfig {
+            liquidity_providers: import_csv(file, &contents)?,
+            ..Default::default()
+        })
+    }
+}
+
+pub fn parse_toml(file: &str, contents: &str) -> Result<AppConfig, AppError> {
+    let source = Source { file, contents };
+    let config_file: ConfigFile = toml::from_str(contents).map_err(|e| {
+        source.error(
+            e.span().map(|span| source.line(span)),
+            None,
+            None,
+            e.message(),
+        )
+    })?;
+
+    let mut app_config = AppConfig::default();
+    let general = config_file.general;
+    app_config.general.seed = general.seed;
+    app_config.general.blotter = general.blotter;
+    if let Some(min_spread_pips) = general.min_spread_pips {
+        if !non_negative(*min_spread_pips.get_ref()) {
+            return Err(source.field_error(
+                min_spread_pips.span(),
+                None,
+                "min_spread_pips",
+                NON_NEGATIVE,
+            ));
+        }
+        app_config.general.min_spread_pips = min_spread_pips.into_inner();
+    }
+    if let Some(print_ladder) = general.print_ladder {
+        app_config.general.print_ladder = print_ladder;
+    }
+    if let Some(run_duration_secs) = general.run_duration_secs {
+        let run_duration = source.duration(&run_duration_secs, None, "run_duration_secs")?;
+        app_config.general.run_duration = Some(run_duration);
+    }
+    if let Some(quote_ttl_secs) = general.quote_ttl_secs {
+        let quote_ttl = source.duration(&quote_ttl_secs, None, "quote_ttl_secs")?;
+        app_config.general.quote_ttl = Some(quote_ttl);
+    }
+    if let Some(log_config) = config_file.logging.log_config {
+        app_config.logging.log_config = log_config;
+    }
+    if let Some(fix_log) = config_file.logging.fix_log {
+        app_config.logging.fix_log = fix_log;
+    }
+    let gui = config_file.gui;
+    if let Some(title) = gui.title {
+        app_config.gui.title = title;
+    }
+    for (field, size, value) in [
+        ("width", gui.width, &mut app_config.gui.width),
+        ("height", gui.height, &mut app_config.gui.height),
+    ] {
+        if let Some(size) = size {
+            if !(*size.get_ref() > 0.0 && size.get_ref().is_finite()) {
+                return Err(source.field_error(size.span(), None, field, "must be positive"));
+            }
+            *value = size.into_inner();
+        }
+    }
+    app_config.risk = risk_limits(&source, config_file.risk)?;
+    for section in config_file.book_policies {
+        let currency_pair = section.currency_pair.get_ref().clone();
+        if app_config.book_policies.contains_key(&currency_pair) {
+            return Err(source.field_error(
+                section.currency_pair.span(),
+                None,
+                "currency_pair",
+                format!("{currency_pair} already has a book policy"),
+            ));
+        }
+        let book_policy = book_policy(&source, section, app_config.general.min_spread_pips)?;
+        app_config.book_policies.insert(currency_pair, book_policy);
+    }
+
+    for section in config_file.liquidity_providers {
+        let config = lp_config(
+            &source,
+            section,
+            &app_config.liquidity_providers,
+            app_config.general.quote_ttl,
+        )?;
+        app_config.liquidity_providers.push(config);
+    }
+    if app_config.liquidity_providers.is_empty() {
+        return Err(source.error(None, None, None, "no [[liquidity_provider]] sections found"));
+    }
+    Ok(app_config)
+}
+
+fn book_policy(
+    source: &Source,
+    section: BookPolicySection,
+    min_spread_pips: f64,
+) -> Result<BookPolicyConfig, AppError> {
+    // the minimum spread defaults to the [general] min_spread_pips
+    let crossed = match section.crossed {
+        Some(crossed) => CrossedBookPolicy::parse(crossed.get_ref())
+            .map_err(|e| source.field_error(crossed.span(), None, "crossed", e))?,
+        None => CrossedBookPolicy::default(),
+    };
+    let min_spread_pips = match section.min_spread_pips {
+        Some(min_spread_pips) if !non_negative(*min_spread_pips.get_ref()) => {
+            return Err(source.field_error(
+                min_spread_pips.span(),
+                None,
+                "min_spread_pips",
+                NON_NEGATIVE,
+            ));
+        }
+        Some(min_spread_pips) => min_spread_pips.into_inner(),
+        None => min_spread_pips,
+    };
+    Ok(BookPolicyConfig::new(crossed, Some(min_spread_pips)))
+}
+
+fn risk_limits(source: &Source, section: RiskSection) -> Result<RiskLimits, AppError> {
+    let mut risk_limits = RiskLimits::default();
+    if let Some(max_order_volume) = section.max_order_volume {
+        if *max_order_volume.get_ref() <= 0 {
+            return Err(source.field_error(
+                max_order_volume.span(),
+                None,
+                "max_order_volume",
+                "must be positive",
+            ));
+        }
+        risk_limits.max_order_volume = Some(max_order_volume.into_inner());
+    }
+    if let Some(max_net_position) = section.max_net_position {
+        if *max_net_position.get_ref() < 0 {
+            return Err(source.field_error(
+                max_net_position.span(),
+                None,
+                "max_net_position",
+                "must not be negative",
+            ));
+        }
+        risk_limits.max_net_position = Some(max_net_position.into_inner());
+    }
+    if let Some(price_band_pips) = section.price_band_pips {
+        if !non_negative(*price_band_pips.get_ref()) {
+            return Err(source.field_error(
+                price_band_pips.span(),
+                None,
+                "price_band_pips",
+                NON_NEGATIVE,
+            ));
+        }
+        risk_limits.price_band = Some(pips_to_ticks(price_band_pips.into_inner()));
+    }
+    for (liquidity_provider, credit_line) in section.credit_lines {
+        if *credit_line.get_ref() < 0 {
+            return Err(source.field_error(
+                credit_line.span(),
+                Some(&liquidity_provider),
+                "credit_lines",
+                "must not be negative",
+            ));
+        }
+        risk_limits
+            .credit_lines
+            .insert(liquidity_provider, credit_line.into_inner());
+    }
+    Ok(risk_limits)
+}
+
+fn lp_config(
+    source: &Source,
+    section: LiquidityProviderSection,
+    configs: &[Config],
+    default_quote_ttl: Option<Duration>,
+) -> Result<Config, AppError> {
+    let name_span = section.name.span();
+    let liquidity_provider = section.name.into_inner().trim().to_string();
+    let lp = Some(liquidity_provider.as_str());
+    if liquidity_provider.is_empty() {
+        return Err(source.field_error(name_span, None, "name", "must not be empty"));
+    }
+    let currency_pair = section.currency_pair.get_ref().trim().to_string();
+    let decimals = price_decimals(&currency_pair);
+    let buy_price = Price::parse(&section.buy_price.get_ref().to_string(), decimals)
+        .map_err(|e| source.field_error(section.buy_price.span(), lp, "buy_price", e))?;
+    if !non_negative(*section.spread_pips.get_ref()) {
+        return Err(source.field_error(
+            section.spread_pips.span(),
+            lp,
+            "spread_pips",
+            NON_NEGATIVE,
+        ));
+    }
+    let incremental = match &section.market_data {
+        None => false,
+        Some(market_data) => parse_market_data(market_data.get_ref())
+            .map_err(|e| source.field_error(market_data.span(), lp, "market_data", e))?,
+    };
+    let price_model = match &section.price_model {
+        None => PriceModelConfig::default(),
+        Some(spec) => PriceModelConfig::parse(spec.get_ref())
+            .map_err(|e| source.field_error(spec.span(), lp, "price_model", e))?,
+    };
+    let tiers = match &section.tiers {
+        None => simulator::legacy_tiers(0.0, 0.0),
+        Some(tiers)
+            if !tiers.get_ref().iter().all(|tier| {
+                non_negative(tier.bid_markup_pips) && non_negative(tier.offer_markup_pips)
+            }) =>
+        {
+            return Err(source.field_error(
+                tiers.span(),
+                lp,
+                "tiers",
+                format!("markups {NON_NEGATIVE}"),
+            ));
+        }
+        Some(tiers) => tiers
+            .get_ref()
+            .iter()
+            .map(|tier| Tier {
+                volume: tier.volume,
+                bid_markup: pips_to_ticks(tier.bid_markup_pips),
+                offer_markup: pips_to_ticks(tier.offer_markup_pips),
+            })
+            .collect(),
+    };
+    let quote_ttl = match &section.quote_ttl_secs {
+        None => default_quote_ttl,
+        Some(quote_ttl_secs) => Some(source.duration(quote_ttl_secs, lp, "quote_ttl_secs")?),
+    };
+
+    if let Some(last_look) = &section.last_look
+        && last_look
+            .get_ref()
+            .max_price_move_pips
+            .is_some_and(|pips| !non_negative(pips))
+    {
+        return Err(source.field_error(
+            last_look.span(),
+            lp,
+            "last_look",
+            format!("max_price_move_pips {NON_NEGATIVE}"),
+        ));
+    }
+    let last_look = section
+        .last_look
+        .as_ref()
+        .map_or_else(LastLookConfig::default, |last_look| {
+            let last_look = last_look.get_ref();
+            LastLookConfig::new(
+                last_look.hold_ms,
+                last_look.reject_probability,
+                last_look.max_price_move_pips,
+            )
+        });
+
+    let config = Config {
+        liquidity_provider: liquidity_provider.clone(),
+        currency_pair,
+        buy_price,
+        spread: pips_to_ticks(*section.spread_pips.get_ref()),
+        tiers,
+        run_iterations: section
+            .iterations
+            .as_ref()
+            .map_or(DEFAULT_ITERATIONS, |iterations| *iterations.get_ref()),
+        incremental,
+        seed: section.seed,
+        price_model,
+        quote_ttl,
+        last_look,
+    };
+    if let Err((field, message)) = validate_config(&config, configs) {
+        let (span, field) = match field {
+            ConfigField::Name => (name_span, "name"),
+            ConfigField::CurrencyPair => (section.currency_pair.span(), "currency_pair"),
+            ConfigField::BuyPrice => (section.buy_price.span(), "buy_price"),
+            ConfigField::Spread => (section.spread_pips.span(), "spread_pips"),
+            ConfigField::Iterations => (
+                section
+                    .iterations
+                    .as_ref()
+                    .map_or(name_span, |iterations| iterations.span()),
+                "iterations",
+            ),
+            ConfigField::Tiers => (
+                section
+                    .tiers
+                    .as_ref()
+                    .map_or(name_span, |tiers| tiers.span()),
+                "tiers",
+            ),
+            ConfigField::LastLook => (
+                section
+                    .last_look
+                    .as_ref()
+                    .map_or(name_span, |last_look| last_look.span()),
+                "last_look",
+            ),
+        };
+        return Err(source.field_error(span, lp, field, message));
+    }
+    Ok(config)
+}
+
+pub fn import_csv(file: &str, contents: &str) -> Result<Vec<Config>, AppError> {
+    // liquidity provider configs by position in the original CSV format
+    let mut configs: Vec<Config> = Vec::new();
+    // ignore header line in config file
+    for (index, line) in contents.lines().enumerate().skip(1) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut row = CsvRow {
+            file,
+            line: index + 1,
+            fields: line.split(',').map(str::trim).collect(),
+            liquidity_provider: None,
+        };
+        let config = csv_config(&mut row)?;
+        if let Err((field, message)) = validate_config(&config, &configs) {
+            let column = match field {
+                ConfigField::Name => 0,
+                ConfigField::CurrencyPair => 1,
+                ConfigField::BuyPrice => 2,
+                ConfigField::Spread => 3,
+                ConfigField::Iterations => 6,
+                ConfigField::Tiers => {
+                    if row.field(10).is_empty() {
+                        4
+                    } else {
+                        10
+                    }
+                }
+                // last look is not configured in CSV config files, so it can only be the
+                // default being invalid
+                ConfigField::LastLook => {
+                    return Err(row.named_error("last look", message));
+                }
+            };
+            return Err(row.error(column, message));
+        }
+        configs.push(config);
+    }
+    Ok(configs)
+}
+
+fn csv_config(row: &mut CsvRow) -> Result<Config, AppError> {
+    let liquidity_provider = row.field(0).to_string();
+    if liquidity_provider.is_empty() {
+        return Err(row.error(0, "must not be empty"));
+    }
+    row.liquidity_provider = Some(liquidity_provider.clone());
+    let currency_pair = row.field(1).to_string();
+    let decimals = price_decimals(&currency_pair);
+    let buy_price = Price::parse(row.field(2), decimals).map_err(|e| row.error(2, e))?;
+    // spread and markups are given in pips in the config file
+    let spread: f64 = row.parse(3)?;
+    let three_mill_markup: f64 = row.parse(4)?;
+    let five_mill_markup: f64 = row.parse(5)?;
+    for (column, pips) in [(3, spread), (4, three_mill_markup), (5, five_mill_markup)] {
+        if !non_negative(pips) {
+            return Err(row.error(column, NON_NEGATIVE));
+        }
+    }
+    let run_iterations: i32 = row.parse(6)?;
+    // optional market data type column, defaults to snapshots
+    let incremental = parse_market_data(row.field(7)).map_err(|e| row.error(7, e))?;
+    // optional seed column
+    let seed: Option<u64> = match row.field(8) {
+        "" => None,
+        _ => Some(row.parse(8)?),
+    };
+    if seed.is_some_and(|seed| seed > simulator::MAX_SEED) {
+        return Err(row.error(8, format!("must be at most {}", simulator::MAX_SEED)));
+    }
+    // optional price model column, defaults to a bull market
+    let price_model = match row.field(9) {
+        "" => PriceModelConfig::default(),
+        spec => PriceModelConfig::parse(spec).map_err(|e| row.error(9, e))?,
+    };
+    // optional tiers column, replacing the 1M, 3M and 5M tiers
+    let tiers = match row.field(10) {
+        "" => simulator::legacy_tiers(three_mill_markup, five_mill_markup),
+        spec => simulator::parse_tiers(spec).map_err(|e| row.error(10, e))?,
+    };
+
+    Ok(Config {
+        liquidity_provider,
+        currency_pair,
+        buy_price,
+        spread: pips_to_ticks(spread),
+        tiers,
+        run_iterations,
+        incremental,
+        seed,
+        price_model,
+        quote_ttl: None,
+        last_look: LastLookConfig::default(),
+    })
+}
+
+fn parse_market_data(market_data: &str) -> Result<bool, String> {
+    // true for incremental refreshes, false for snapshots (the default)
+    match market_data.trim() {
+        "" | "snapshot" => Ok(false),
+        "incremental" => Ok(true),
+        other => Err(format!(
+            "market data type must be snapshot or incremental, not {other}"
+        )),
+    }
+}
+
+enum ConfigField {
+    Name,
+    CurrencyPair,
+    BuyPrice,
+    Spread,
+    Iterations,
+    Tiers,
+    LastLook,
+}
+
+fn validate_config(config: &Config, configs: &[Config]) -> Result<(), (ConfigField, String)> {
+    // checks shared by the TOML and CSV config files, configs holds the liquidity providers
+    // already read from the file
+    let currency_pair: Vec<&str> = config.currency_pair.split('/').collect();
+    if currency_pair.len() != 2
+        || currency_pair.iter().any(|currency| {
+            currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_uppercase())
+        })
+    {
+        return Err((
+            ConfigField::CurrencyPair,
+            format!(
+                "currency pair {} must be two currency codes e.g. USD/EUR",
+                config.currency_pair
+            ),
+        ));
+    }
+    if configs.iter().any(|other| {
+        other.liquidity_provider == config.liquidity_provider
+            && other.currency_pair == config.currency_pair
+    }) {
+        return Err((
+            ConfigField::Name,
+            format!("already configured for {}", config.currency_pair),
+        ));
+    }
+    if config.buy_price.ticks() <= 0 {
+        return Err((ConfigField::BuyPrice, String::from("must be positive")));
+    }
+    if config.spread < 0 {
+        return Err((ConfigField::Spread, String::from("must not be negative")));
+    }
+    if config.run_iterations <= 0 {
+        return Err((ConfigField::Iterations, String::from("must be positive")));
+    }
+    simulator::validate_tiers(&config.liquidity_provider, &config.tiers)
+        .map_err(|e| (ConfigField::Tiers, config_message(e)))?;
+    config
+        .last_look
+        .validate()
+        .map_err(|e| (ConfigField::LastLook, config_message(e)))
+}
+
+pub fn non_negative(value: f64) -> bool {
+    // NaN fails every comparison, so is rejected along with infinity and negative values
+    value >= 0.0 && value.is_finite()
+}
+
+fn config_message(error: AppError) -> String {
+    // the config error already names the file and field so drop the "invalid config" prefix
+    match error {
+        AppError::InvalidConfig(message) => message,
+        error => error.to_string(),
+    }
+}
+
+struct Source<'a> {
+    file: &'a str,
+    contents: &'a str,
+}
+
+impl Source<'_> {
+    fn line(&self, span: Range<usize>) -> usize {
+        let offset = span.start.min(self.contents.len());
+        self.contents[..offset].matches('\n').count() + 1
+    }
+
+    fn error(
+        &self,
+        line: Option<usize>,
+        liquidity_provider: Option<&str>,
+        field: Option<&str>,
+        message: impl Display,
+    ) -> AppError {
+        AppError::Config(ConfigError {
+            file: String::from(self.file),
+            line,
+            liquidity_provider: liquidity_provider.map(String::from),
+            field: field.map(String::from),
+            message: message.to_string(),
+        })
+    }
+
+    fn duration(
+        &self,
+        seconds: &Spanned<f64>,
+        liquidity_provider: Option<&str>,
+        field: &str,
+    ) -> Result<Duration, AppError> {
+        Duration::try_from_secs_f64(*seconds.get_ref())
+            .ok()
+            .filter(|duration| !duration.is_zero())
+            .ok_or_else(|| {
+                self.field_error(
+                    seconds.span(),
+                    liquidity_provider,
+                    field,
+                    "must be a positive number of seconds",
+                )
+            })
+    }
+
+    fn field_error(
+        &self,
+        span: Range<usize>,
+        liquidity_provider: Option<&str>,
+        field: &str,
+        message: impl ToConfigMessage,
+    ) -> AppError {
+        self.error(
+            Some(self.line(span)),
+            liquidity_provider,
+            Some(field),
+            message.to_config_message(),
+        )
+    }
+}
+
+struct CsvRow<'a> {
+    file: &'a str,
+    line: usize,
+    fields: Vec<&'a str>,
+    liquidity_provider: Option<String>,
+}
+
+impl CsvRow<'_> {
+    fn field(&self, column: usize) -> &str {
+        self.fields.get(column).copied().unwrap_or("")
+    }
+
+    fn parse<T: FromStr>(&self, column: usize) -> Result<T, AppError>
+    where
+        T::Err: Display,
+    {
+        let value = self.field(column);
+        if value.is_empty() {
+            return Err(self.error(column, "missing value"));
+        }
+        value
+            .parse()
+            .map_err(|e: T::Err| self.error(column, format!("{value} - {e}")))
+    }
+
+    fn error(&self, column: usize, message: impl ToConfigMessage) -> AppError {
+        self.named_error(CSV_COLUMNS[column], message)
+    }
+
+    fn named_error(&self, field: &str, message: impl ToConfigMessage) -> AppError {
+        AppError::Config(ConfigError {
+            file: String::from(self.file),
+            line: Some(self.line),
+            liquidity_provider: self.liquidity_provider.clone(),
+            field: Some(String::from(field)),
+            message: message.to_config_message(),
+        })
+    }
+}
+
+trait ToConfigMessage {
+    fn to_config_message(self) -> String;
+}
+
+impl ToConfigMessage for AppError {
+    fn to_config_message(self) -> String {
+        config_message(self)
+    }
+}
+
+impl ToConfigMessage for String {
+    fn to_config_message(self) -> String {
+        self
+    }
+}
+
+impl ToConfigMessage for &str {
+    fn to_config_message(self) -> String {
+        String::from(self)
+    }
+}
@@ -0,0 +1,636 @@
+//! # FX Simulator and Aggregator - fx_sim_agg
+//!
+//! `fix.rs` encodes and decodes the FIX 4.4 market data messages sent by the simulated liquidity providers.
+//! Quotes are sent as MarketDataSnapshotFullRefresh (35=W) messages with a NoMDEntries (268) repeating group
+//! holding a bid (269=0) and offer (269=1) entry for each quoted volume, or as MarketDataIncrementalRefresh (35=X)
+//! messages where each entry carries a New, Change or Delete MDUpdateAction (279) for a previously sent MDEntryID (278).
+//! The `QuoteEncoder` and `QuoteDecoder` convert between these messages and the typed `QuoteUpdate`s the aggregator
+//! consumes, keeping track of the entries sent by each liquidity provider.
+use crate::AppError;
+use crate::price::{Price, price_decimals};
+use crate::quote::{QuoteTier, QuoteUpdate};
+use crate::simulator::Config;
+use chrono::prelude::DateTime;
+use chrono::{NaiveDateTime, Utc};
+use log::error;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, UNIX_EPOCH};
+
+pub const SOH: char = '\x01';
+pub const BEGIN_STRING: &str = "FIX.4.4";
+pub const TARGET_COMP_ID: &str = "FXSIMAGG";
+pub const MSG_TYPE_SNAPSHOT: &str = "W";
+pub const MSG_TYPE_INCREMENTAL: &str = "X";
+
+// FIX tags used in market data messages
+pub const TAG_BEGIN_STRING: u32 = 8;
+pub const TAG_BODY_LENGTH: u32 = 9;
+pub const TAG_CHECKSUM: u32 = 10;
+pub const TAG_MSG_SEQ_NUM: u32 = 34;
+pub const TAG_MSG_TYPE: u32 = 35;
+pub const TAG_SENDER_COMP_ID: u32 = 49;
+pub const TAG_SENDING_TIME: u32 = 52;
+pub const TAG_SYMBOL: u32 = 55;
+pub const TAG_TARGET_COMP_ID: u32 = 56;
+pub const TAG_NO_MD_ENTRIES: u32 = 268;
+pub const TAG_MD_ENTRY_TYPE: u32 = 269;
+pub const TAG_MD_ENTRY_PX: u32 = 270;
+pub const TAG_MD_ENTRY_SIZE: u32 = 271;
+pub const TAG_MD_ENTRY_ID: u32 = 278;
+pub const TAG_MD_UPDATE_ACTION: u32 = 279;
+
+// MDEntrySize is sent in units of the base currency whereas the book holds volumes in millions
+const VOLUME_UNITS: i64 = 1_000_000;
+const SENDING_TIME_FORMAT: &str = "%Y%m%d-%H:%M:%S%.3f";
+// accept any number of fractional second digits when reading messages
+const SENDING_TIME_PARSE_FORMAT: &str = "%Y%m%d-%H:%M:%S%.f";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MdEntry {
+    // MDEntryID (278) identifies the entry so later incremental updates can change or delete it
+    pub entry_id: Option<String>,
+    pub side: String,
+    pub price: Price,
+    pub volume: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpdateAction {
+    New,
+    Change,
+    Delete,
+}
+
+impl UpdateAction {
+    pub fn to_fix(self) -> &'static str {
+        match self {
+            UpdateAction::New => "0",
+            UpdateAction::Change => "1",
+            UpdateAction::Delete => "2",
+        }
+    }
+
+    pub fn from_fix(value: &str) -> Result<Self, AppError> {
+        match value {
+            "0" => Ok(UpdateAction::New),
+            "1" => Ok(UpdateAction::Change),
+            "2" => Ok(UpdateAction::Delete),
+            _ => Err(AppError::Fix(format!("unsupported MDUpdateAction {value}"))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MdUpdate {
+    pub action: UpdateAction,
+    // price and volume are not used when deleting an entry
+    pub entry: MdEntry,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarketDataSnapshot {
+    // SenderCompID identifies the liquidity provider that sent the quotes
+    pub liquidity_provider: String,
+    pub msg_seq_num: u64,
+    // SendingTime as nanoseconds since the unix epoch (sent to millisecond precision)
+    pub sending_time: u64,
+    pub currency_pair: String,
+    pub entries: Vec<MdEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarketDataIncremental {
+    pub liquidity_provider: String,
+    pub msg_seq_num: u64,
+    pub sending_time: u64,
+    pub currency_pair: String,
+    pub updates: Vec<MdUpdate>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MarketData {
+    Snapshot(MarketDataSnapshot),
+    Incremental(MarketDataIncremental),
+}
+
+impl MarketData {
+    pub fn decode(message: &str) -> Result<Self, AppError> {
+        let fields = parse_fields(message)?;
+        match get_field(&fields, TAG_MSG_TYPE)? {
+            MSG_TYPE_SNAPSHOT => Ok(MarketData::Snapshot(MarketDataSnapshot::from_fields(
+                &fields,
+            )?)),
+            MSG_TYPE_INCREMENTAL => Ok(MarketData::Incremental(
+                MarketDataIncremental::from_fields(&fields)?,
+            )),
+            msg_type => Err(AppError::Fix(format!(
+                "unsupported message type 35={msg_type}"
+            ))),
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        match self {
+            MarketData::Snapshot(snapshot) => snapshot.encode(),
+            MarketData::Incremental(incremental) => incremental.encode(),
+        }
+    }
+}
+
+impl MarketDataSnapshot {
+    pub fn encode(&self) -> String {
+        let mut body = String::new();
+        push_header(
+            &mut body,
+            MSG_TYPE_SNAPSHOT,
+            &self.liquidity_provider,
+            self.msg_seq_num,
+            self.sending_time,
+        );
+        push_field(&mut body, TAG_SYMBOL, &self.currency_pair);
+        push_field(
+            &mut body,
+            TAG_NO_MD_ENTRIES,
+            &self.entries.len().to_string(),
+        );
+        for entry in &self.entries {
+            push_md_entry(&mut body, entry);
+        }
+        frame_message(&body)
+    }
+
+    fn from_fields(fields: &[(u32, String)]) -> Result<Self, AppError> {
+        let currency_pair = String::from(get_field(fields, TAG_SYMBOL)?);
+        let decimals = price_decimals(&currency_pair);
+        // each repeating group entry starts with MDEntryType (269)
+        let mut entries: Vec<MdEntry> = Vec::new();
+        for group in get_groups(fields, TAG_MD_ENTRY_TYPE)? {
+            entries.push(parse_md_entry(&group, decimals, true)?);
+        }
+
+        Ok(MarketDataSnapshot {
+            liquidity_provider: String::from(get_field(fields, TAG_SENDER_COMP_ID)?),
+            msg_seq_num: get_field(fields, TAG_MSG_SEQ_NUM)?.parse()?,
+            sending_time: parse_sending_time(get_field(fields, TAG_SENDING_TIME)?)?,
+            currency_pair,
+            entries,
+        })
+    }
+}
+
+impl MarketDataIncremental {
+    pub fn encode(&self) -> String {
+        let mut body = String::new();
+        push_header(
+            &mut body,
+            MSG_TYPE_INCREMENTAL,
+            &self.liquidity_provider,
+            self.msg_seq_num,
+            self.sending_time,
+        );
+        push_field(
+            &mut body,
+            TAG_NO_MD_ENTRIES,
+            &self.updates.len().to_string(),
+        );
+        for update in &self.updates {
+            // the instrument is part of each entry in an incremental refresh
+            push_field(&mut body, TAG_MD_UPDATE_ACTION, update.action.to_fix());
+            push_field(&mut body, TAG_SYMBOL, &self.currency_pair);
+            if update.action == UpdateAction::Delete {
+                push_field(
+                    &mut body,
+                    TAG_MD_ENTRY_TYPE,
+                    side_to_entry_type(&update.entry.side),
+                );
+                if let Some(entry_id) = &update.entry.entry_id {
+                    push_field(&mut body, TAG_MD_ENTRY_ID, entry_id);
+                }
+            } else {
+                push_md_entry(&mut body, &update.entry);
+            }
+        }
+        frame_message(&body)
+    }
+
+    fn from_fields(fields: &[(u32, String)]) -> Result<Self, AppError> {
+        // each repeating group entry starts with MDUpdateAction (279)
+        let groups = get_groups(fields, TAG_MD_UPDATE_ACTION)?;
+        let currency_pair = match groups.first() {
+            Some(group) => String::from(get_field(group, TAG_SYMBOL)?),
+            None => String::new(),
+        };
+        let decimals = price_decimals(&currency_pair);
+        let mut updates: Vec<MdUpdate> = Vec::new();
+        for group in groups {
+            let action = UpdateAction::from_fix(get_field(&group, TAG_MD_UPDATE_ACTION)?)?;
+            let entry = parse_md_entry(&group, decimals, action != UpdateAction::Delete)?;
+            if entry.entry_id.is_none() && action != UpdateAction::New {
+                return Err(AppError::Fix(String::from(
+                    "MDEntryID is required to change or delete an entry",
+                )));
+            }
+            updates.push(MdUpdate { action, entry });
+        }
+
+        Ok(MarketDataIncremental {
+            liquidity_provider: String::from(get_field(fields, TAG_SENDER_COMP_ID)?),
+            msg_seq_num: get_field(fields, TAG_MSG_SEQ_NUM)?.parse()?,
+            sending_time: parse_sending_time(get_field(fields, TAG_SENDING_TIME)?)?,
+            currency_pair,
+            updates,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct QuoteEncoder {
+    // liquidity provider and currency pairs that send incremental refreshes after their first snapshot
+    incremental: HashSet<(String, String)>,
+    // entries last sent by each liquidity provider for each currency pair
+    sent_entries: HashMap<(String, String), Vec<MdEntry>>,
+}
+
+impl QuoteEncoder {
+    pub fn new(config: &[Config]) -> Self {
+        let incremental = config
+            .iter()
+            .filter(|config| config.incremental)
+            .map(|config| {
+                (
+                    config.liquidity_provider.clone(),
+                    config.currency_pair.clone(),
+                )
+            })
+            .collect();
+        QuoteEncoder {
+            incremental,
+            sent_entries: HashMap::new(),
+        }
+    }
+
+    pub fn encode(&mut self, quote: &QuoteUpdate) -> Option<MarketData> {
+        // encode the quotes as a snapshot, or as an incremental refresh of the entries that have
+        // changed since the last message. None if an incremental refresh has nothing to send
+        let key = (
+            quote.liquidity_provider.clone(),
+            quote.currency_pair.clone(),
+        );
+        let entries = quote_entries(quote);
+        let sent_entries = self.sent_entries.insert(key.clone(), entries.clone());
+        match sent_entries {
+            Some(sent_entries) if self.incremental.contains(&key) => {
+                let updates = get_md_updates(&sent_entries, &entries);
+                if updates.is_empty() {
+                    return None;
+                }
+                Some(MarketData::Incremental(MarketDataIncremental {
+                    liquidity_provider: key.0,
+                    msg_seq_num: quote.sequence_number,
+                    sending_time: quote.source_time,
+                    currency_pair: key.1,
+                    updates,
+                }))
+            }
+            _ => Some(MarketData::Snapshot(MarketDataSnapshot {
+                liquidity_provider: key.0,
+                msg_seq_num: quote.sequence_number,
+                sending_time: quote.source_time,
+                currency_pair: key.1,
+                entries,
+            })),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct QuoteDecoder {
+    // entries each liquidity provider currently quotes for each currency pair
+    entries: HashMap<(String, String), Vec<MdEntry>>,
+}
+
+impl QuoteDecoder {
+    pub fn decode(&mut self, message: &str) -> Result<QuoteUpdate, AppError> {
+        Ok(self.apply(MarketData::decode(message)?))
+    }
+
+    pub fn apply(&mut self, market_data: MarketData) -> QuoteUpdate {
+        // a snapshot replaces all the liquidity provider's entries, an incremental refresh adds,
+        // changes or deletes single entries
+        match market_data {
+            MarketData::Snapshot(snapshot) => {
+                let key = (snapshot.liquidity_provider, snapshot.currency_pair);
+                let quote = entries_quote(
+                    &key,
+                    &snapshot.entries,
+                    snapshot.msg_seq_num,
+                    snapshot.sending_time,
+                );
+                self.entries.insert(key, snapshot.entries);
+                quote
+            }
+            MarketData::Incremental(incremental) => {
+                let key = (incremental.liquidity_provider, incremental.currency_pair);
+                let entries = self.entries.entry(key.clone()).or_default();
+                for update in incremental.updates {
+                    let position = entries
+                        .iter()
+                        .position(|entry| entry.entry_id == update.entry.entry_id);
+                    match (update.action, position) {
+                        (UpdateAction::Delete, Some(position)) => {
+                            entries.remove(position);
+                        }
+                        (UpdateAction::New | UpdateAction::Change, Some(position)) => {
+                            entries[position] = update.entry;
+                        }
+                        (UpdateAction::New, None) => entries.push(update.entry),
+                        (action, None) => error!(
+                            "{} MDEntryID {} not found for {:?} update",
+                            key.0,
+                            update.entry.entry_id.unwrap_or_default(),
+                            action
+                        ),
+                    }
+                }
+                entries_quote(
+                    &key,
+                    entries,
+                    incremental.msg_seq_num,
+                    incremental.sending_time,
+                )
+            }
+        }
+    }
+}
+
+pub fn quote_entries(quote: &QuoteUpdate) -> Vec<MdEntry> {
+    // a bid and offer entry for each tier. Entry ids are unique per liquidity provider and
+    // currency pair e.g. B1M for the 1M bid
+    quote
+        .tiers()
+        .map(|(side, tier)| {
+            let side_id = if side == "Buy" { "B" } else { "S" };
+            MdEntry {
+                entry_id: Some(format!("{side_id}{}M", tier.volume)),
+                side: String::from(side),
+                price: tier.price,
+                volume: tier.volume,
+            }
+        })
+        .collect()
+}
+
+fn entries_quote(
+    key: &(String, String),
+    entries: &[MdEntry],
+    sequence_number: u64,
+    source_time: u64,
+) -> QuoteUpdate {
+    let side_tiers = |side: &str| {
+        entries
+            .iter()
+            .filter(|entry| entry.side == side)
+            .map(|entry| QuoteTier {
+                price: entry.price,
+                volume: entry.volume,
+            })
+            .collect()
+    };
+    QuoteUpdate {
+        liquidity_provider: key.0.clone(),
+        currency_pair: key.1.clone(),
+        bids: side_tiers("Buy"),
+        offers: side_tiers("Sell"),
+        source_time,
+        sequence_number,
+    }
+}
+
+pub fn get_md_updates(sent_entries: &[MdEntry], entries: &[MdEntry]) -> Vec<MdUpdate> {
+    // compare the new entries against the entries already sent to work out which entries are
+    // new, which have changed and which have been withdrawn and need deleting
+    let mut updates: Vec<MdUpdate> = Vec::new();
+    for entry in entries {
+        let sent_entry = sent_entries
+            .iter()
+            .find(|sent_entry| sent_entry.entry_id == entry.entry_id);
+        let action = match sent_entry {
+            None => UpdateAction::New,
+            Some(sent_entry) if sent_entry != entry => UpdateAction::Change,
+            Some(_) => continue,
+        };
+        updates.push(MdUpdate {
+            action,
+            entry: entry.clone(),
+        });
+    }
+    for sent_entry in sent_entries {
+        if !entries
+            .iter()
+            .any(|entry| entry.entry_id == sent_entry.entry_id)
+        {
+            updates.push(MdUpdate {
+                action: UpdateAction::Delete,
+                entry: sent_entry.clone(),
+            });
+        }
+    }
+    updates
+}
+
+fn push_header(
+    body: &mut String,
+    msg_type: &str,
+    liquidity_provider: &str,
+    msg_seq_num: u64,
+    sending_time: u64,
+) {
+    push_field(body, TAG_MSG_TYPE, msg_type);
+    push_field(body, TAG_SENDER_COMP_ID, liquidity_provider);
+    push_field(body, TAG_TARGET_COMP_ID, TARGET_COMP_ID);
+    push_field(body, TAG_MSG_SEQ_NUM, &msg_seq_num.to_string());
+    push_field(body, TAG_SENDING_TIME, &format_sending_time(sending_time));
+}
+
+fn push_md_entry(body: &mut String, entry: &MdEntry) {
+    push_field(body, TAG_MD_ENTRY_TYPE, side_to_entry_type(&entry.side));
+    if let Some(entry_id) = &entry.entry_id {
+        push_field(body, TAG_MD_ENTRY_ID, entry_id);
+    }
+    push_field(body, TAG_MD_ENTRY_PX, &entry.price.to_string());
+    push_field(
+        body,
+        TAG_MD_ENTRY_SIZE,
+        &(entry.volume as i64 * VOLUME_UNITS).to_string(),
+    );
+}
+
+fn parse_md_entry(
+    group: &[(u32, String)],
+    decimals: u32,
+    price_required: bool,
+) -> Result<MdEntry, AppError> {
+    let side = String::from(entry_type_to_side(get_field(group, TAG_MD_ENTRY_TYPE)?)?);
+    let entry_id = get_field(group, TAG_MD_ENTRY_ID).ok().map(String::from);
+    let (price, volume) = if price_required {
+        // volumes are whole millions, anything else would be truncated or wrap
+        let size = get_field(group, TAG_MD_ENTRY_SIZE)?;
+        let volume = size
+            .parse::<i64>()
+            .ok()
+            .filter(|size| *size > 0 && size % VOLUME_UNITS == 0)
+            .and_then(|size| i32::try_from(size / VOLUME_UNITS).ok())
+            .ok_or_else(|| {
+                AppError::Fix(format!(
+                    "MDEntrySize ({TAG_MD_ENTRY_SIZE}) {size} must be a positive whole number of millions"
+                ))
+            })?;
+        (
+            Price::parse(get_field(group, TAG_MD_ENTRY_PX)?, decimals)?,
+            volume,
+        )
+    } else {
+        (Price::new(0, decimals), 0)
+    };
+    Ok(MdEntry {
+        entry_id,
+        side,
+        price,
+        volume,
+    })
+}
+
+pub fn get_groups(
+    fields: &[(u32, String)],
+    first_tag: u32,
+) -> Result<Vec<Vec<(u32, String)>>, AppError> {
+    // split the NoMDEntries (268) repeating group into its entries. Each entry starts with
+    // first_tag and the group ends at the CheckSum trailer
+    let no_md_entries: usize = get_field(fields, TAG_NO_MD_ENTRIES)?.parse()?;
+    let mut groups: Vec<Vec<(u32, String)>> = Vec::new();
+    let start = fields
+        .iter()
+        .position(|field| field.0 == TAG_NO_MD_ENTRIES)
+        .unwrap_or(fields.len());
+    for field in &fields[start + 1..] {
+        if field.0 == TAG_CHECKSUM {
+            break;
+        }
+        if field.0 == first_tag {
+            groups.push(Vec::new());
+        }
+        match groups.last_mut() {
+            Some(group) => group.push(field.clone()),
+            None => {
+                return Err(AppError::Fix(format!(
+                    "repeating group must start with tag {first_tag}"
+                )));
+            }
+        }
+    }
+    if groups.len() != no_md_entries {
+        return Err(AppError::Fix(format!(
+            "NoMDEntries is {} but {} entries found",
+            no_md_entries,
+            groups.len()
+        )));
+    }
+    Ok(groups)
+}
+
+pub fn push_field(message: &mut String, tag: u32, value: &str) {
+    message.push_str(&format!("{tag}={value}{SOH}"));
+}
+
+pub fn frame_message(body: &str) -> String {
+    // add the BeginString and BodyLength header fields and the CheckSum trailer to a message body
+    let mut message = String::new();
+    push_field(&mut message, TAG_BEGIN_STRING, BEGIN_STRING);
+    push_field(&mut message, TAG_BODY_LENGTH, &body.len().to_string());
+    message.push_str(body);
+    let checksum = checksum(&message);
+    push_field(&mut message, TAG_CHECKSUM, &format!("{checksum:03}"));
+    message
+}
+
+pub fn checksum(message: &str) -> u32 {
+    message.bytes().map(|b| b as u32).sum::<u32>() % 256
+}
+
+pub fn parse_fields(message: &str) -> Result<Vec<(u32, String)>, AppError> {
+    // split a message into tag=value fields, validating the header and trailer
+    let message = message.trim_end_matches(['\r', '\n']);
+    let mut fields: Vec<(u32, String)> = Vec::new();
+    for field in message.split(SOH).filter(|field| !field.is_empty()) {
+        let (tag, value) = field
+            .split_once('=')
+            .ok_or_else(|| AppError::Fix(format!("malformed field {field}")))?;
+        fields.push((tag.parse()?, String::from(value)));
+    }
+
+    if fields.len() < 3
+        || fields[0] != (TAG_BEGIN_STRING, String::from(BEGIN_STRING))
+        || fields[1].0 != TAG_BODY_LENGTH
+        || fields[fields.len() - 1].0 != TAG_CHECKSUM
+    {
+        return Err(AppError::Fix(String::from(
+            "message must start with 8=FIX.4.4|9= and end with 10=",
+        )));
+    }
+
+    // BodyLength counts the characters after the BodyLength field up to and including the SOH before CheckSum
+    let checksum_start = message
+        .rfind(&format!("{SOH}{TAG_CHECKSUM}="))
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    let body_start = message
+        .find(&format!("{SOH}{TAG_BODY_LENGTH}="))
+        .and_then(|i| message[i + 1..].find(SOH).map(|j| i + 1 + j + 1))
+        .unwrap_or(0);
+    let body_length: usize = fields[1].1.parse()?;
+    if checksum_start < body_start || body_length != checksum_start - body_start {
+        return Err(AppError::Fix(format!("incorrect BodyLength {body_length}")));
+    }
+    let expected: u32 = fields[fields.len() - 1].1.parse()?;
+    if checksum(&message[..checksum_start]) != expected {
+        return Err(AppError::Fix(format!("incorrect CheckSum {expected:03}")));
+    }
+
+    Ok(fields)
+}
+
+pub fn get_field(fields: &[(u32, String)], tag: u32) -> Result<&str, AppError> {
+    fields
+        .iter()
+        .find(|field| field.0 == tag)
+        .map(|field| field.1.as_str())
+        .ok_or_else(|| AppError::Fix(format!("missing tag {tag}")))
+}
+
+pub fn side_to_entry_type(side: &str) -> &'static str {
+    // bids are the prices the liquidity provider buys at, offers the prices it sells at
+    if side == "Buy" { "0" } else { "1" }
+}
+
+pub fn entry_type_to_side(entry_type: &str) -> Result<&'static str, AppError> {
+    match entry_type {
+        "0" => Ok("Buy"),
+        "1" => Ok("Sell"),
+        _ => Err(AppError::Fix(format!(
+            "unsupported MDEntryType {entry_type}"
+        ))),
+    }
+}
+
+pub fn format_sending_time(timestamp: u64) -> String {
+    let datetime = DateTime::<Utc>::from(UNIX_EPOCH + Duration::from_nanos(timestamp));
+    datetime.format(SENDING_TIME_FORMAT).to_string()
+}
+
+pub fn parse_sending_time(sending_time: &str) -> Result<u64, AppError> {
+    NaiveDateTime::parse_from_str(sending_time, SENDING_TIME_PARSE_FORMAT)
+        .ok()
+        .and_then(|datetime| datetime.and_utc().timestamp_nanos_opt())
+        .and_then(|nanos| u64::try_from(nanos).ok())
+        .ok_or_else(|| AppError::Fix(format!("invalid SendingTime {sending_time}")))
+}
@@ -1,12 +1,68 @@
 use crate::FxViewerApp;
-use crate::aggregator;
+use crate::book_side::BookSide;
+use crate::price::{Price, price_decimals};
+use crate::price_history::PriceSample;
 use eframe::egui;
 use egui::{Color32, Label, Layout, RichText};
 use egui_extras::{TableBody, TableBuilder, TableRow};
+use egui_plot::{Legend, Line, LineStyle, Plot, PlotUi};
+use std::time::Duration;
 
-pub fn render_top_panel(ctx: &egui::Context) {
+// time windows the price chart shows, back from the latest quote update
+pub const CHART_WINDOWS: [(&str, Duration); 3] = [
+    ("1 min", Duration::from_secs(60)),
+    ("5 min", Duration::from_secs(5 * 60)),
+    ("15 min", Duration::from_secs(15 * 60)),
+];
+// colours of the liquidity providers' quotes on the price chart, in liquidity provider order
+const LP_COLOURS: [Color32; 6] = [
+    Color32::LIGHT_BLUE,
+    Color32::GOLD,
+    Color32::LIGHT_RED,
+    Color32::LIGHT_GREEN,
+    Color32::KHAKI,
+    Color32::LIGHT_GRAY,
+];
+
+pub fn render_top_panel(fx_viewer_app: &mut FxViewerApp, ctx: &egui::Context) {
+    let currency_pairs = fx_viewer_app.snapshots.currency_pairs();
+    // show the first currency pair until the selected one has a book e.g. when replaying a FIX log
+    if !currency_pairs.contains(&fx_viewer_app.selected_pair)
+        && let Some(currency_pair) = currency_pairs.first()
+    {
+        fx_viewer_app.selected_pair = currency_pair.clone();
+    }
+    // liquidity providers whose quotes have expired from the selected book, and whether a book
+    // policy allowing crosses has left it crossed
+    let fx_book = fx_viewer_app.snapshots.book(&fx_viewer_app.selected_pair);
+    let stale_lps: Vec<String> = fx_book
+        .as_ref()
+        .map(|fx_book| fx_book.stale_lps.iter().cloned().collect())
+        .unwrap_or_default();
+    let crossed = fx_book.is_some_and(|fx_book| fx_book.crossed);
     egui::TopBottomPanel::top("my_panel").show(ctx, |ui| {
         ctx.set_visuals(egui::Visuals::dark());
+        ui.horizontal(|ui| {
+            // switch between the aggregated books of each currency pair
+            egui::ComboBox::from_label("Currency pair")
+                .selected_text(fx_viewer_app.selected_pair.as_str())
+                .show_ui(ui, |ui| {
+                    for currency_pair in currency_pairs {
+                        let text = currency_pair.clone();
+                        ui.selectable_value(&mut fx_viewer_app.selected_pair, currency_pair, text);
+                    }
+                });
+            if !stale_lps.is_empty() {
+                ui.add_space(20.);
+                ui.colored_label(Color32::RED, format!("Stale: {}", stale_lps.join(", ")));
+            }
+            if crossed {
+                ui.add_space(20.);
+                ui.colored_label(Color32::RED, "Crossed");
+            }
+            ui.add_space(20.);
+            ui.checkbox(&mut fx_viewer_app.show_chart, "Chart");
+        });
         ui.with_layout(Layout::left_to_right(eframe::emath::Align::Center), |ui| {
             ui.add_space(180.);
             ui.add(Label::new(
@@ -21,7 +77,10 @@ pub fn render_top_panel(ctx: &egui::Context) {
 }
 
 pub fn render_fx_book(fx_viewer_app: &mut FxViewerApp, ctx: &egui::Context) {
-    let fx_book = fx_viewer_app.fx_book_mutex.lock().unwrap(); // panic if can't get lock
+    // the snapshot stays unchanged while it is rendered, however often the book is updated
+    let Some(fx_book) = fx_viewer_app.snapshots.book(&fx_viewer_app.selected_pair) else {
+        return;
+    };
     egui::CentralPanel::default().show(ctx, |ui| {
         ui.with_layout(Layout::left_to_right(eframe::emath::Align::Center), |ui| {
             ui.with_layout(Layout::top_down(eframe::emath::Align::Center), |ui| {
@@ -57,7 +116,7 @@ pub fn render_fx_book(fx_viewer_app: &mut FxViewerApp, ctx: &egui::Context) {
             });
         });
     });
-} // mutex lock released here
+}
 
 fn render_sell_table_header(mut header: TableRow<'_, '_>) {
     header.col(|ui| {
@@ -83,7 +142,7 @@ fn render_buy_table_header(mut header: TableRow<'_, '_>) {
     });
 }
 
-fn render_buy_table_body(mut body: TableBody<'_>, buy_book: &Vec<aggregator::FxAggBookEntry>) {
+fn render_buy_table_body(mut body: TableBody<'_>, buy_book: &BookSide) {
     for entry in buy_book {
         let lp_vol_vec = &entry.lp_vol;
         body.row(30.0, |mut row| {
@@ -96,37 +155,26 @@ fn render_buy_table_body(mut body: TableBody<'_>, buy_book: &Vec<aggregator::FxA
                 ui.label(format!("{:?}", entry.volume));
             });
             row.col(|ui| {
-                ui.label(RichText::new(format!("{:?}", entry.price)).color(Color32::GREEN));
+                ui.label(RichText::new(entry.price.to_string()).color(Color32::GREEN));
             });
         });
     }
 }
 
-fn create_lp_agg_string(lp_vol_vec: &Vec<(String, i32)>) -> String {
-    let mut lp_vol = String::from("(");
-    let len = lp_vol_vec.len() - 1;
-    let mut index = 0;
-    for val in lp_vol_vec {
-        if index == 0 && len == 0 {
-            lp_vol = format!("{}{}: {})", lp_vol, val.0, val.1);
-        } else if index == 0 {
-            lp_vol = format!("{}{}: {},", lp_vol, val.0, val.1);
-        } else if index == len {
-            lp_vol = format!("{} {}: {})", lp_vol, val.0, val.1);
-        } else {
-            lp_vol = format!("{} {}: {},", lp_vol, val.0, val.1);
-        }
-        index += 1;
-    }
-    lp_vol
+fn create_lp_agg_string(lp_vol_vec: &[(String, i32)]) -> String {
+    let lp_vol: Vec<String> = lp_vol_vec
+        .iter()
+        .map(|val| format!("{}: {}", val.0, val.1))
+        .collect();
+    format!("({})", lp_vol.join(", "))
 }
 
-fn render_sell_table_body(mut body: TableBody<'_>, sell_book: &Vec<aggregator::FxAggBookEntry>) {
+fn render_sell_table_body(mut body: TableBody<'_>, sell_book: &BookSide) {
     for entry in sell_book {
         let lp_vol_vec = &entry.lp_vol;
         body.row(30.0, |mut row| {
             row.col(|ui| {
-                ui.label(RichText::new(format!("{:?}", entry.price)).color(Color32::GREEN));
+                ui.label(RichText::new(entry.price.to_string()).color(Color32::GREEN));
             });
 
             row.col(|ui| {
@@ -140,3 +188,176 @@ fn render_sell_table_body(mut body: TableBody<'_>, sell_book: &Vec<aggregator::F
         });
     }
 }
+
+pub fn render_positions(fx_viewer_app: &mut FxViewerApp, ctx: &egui::Context) {
+    let position_keeper = fx_viewer_app.snapshots.positions();
+    let currency_pair = &fx_viewer_app.selected_pair;
+    let Some(position) = position_keeper.position(currency_pair) else {
+        return;
+    };
+    // the position in the selected currency pair followed by the position with each liquidity provider
+    let mut rows = vec![("Total", position)];
+    rows.extend(
+        position_keeper
+            .lp_positions
+            .iter()
+            .filter(|((pair, _), _)| pair == currency_pair)
+            .map(|((_, liquidity_provider), position)| (liquidity_provider.as_str(), position)),
+    );
+    let decimals = price_decimals(currency_pair) as usize;
+    egui::TopBottomPanel::bottom("positions").show(ctx, |ui| {
+        TableBuilder::new(ui)
+            .id_salt(3)
+            .striped(true)
+            .columns(egui_extras::Column::auto().resizable(true), 6)
+            .header(20.0, |mut header| {
+                for heading in [
+                    "Position",
+                    "Net (M)",
+                    "Avg cost",
+                    "Mark",
+                    "Realised",
+                    "Unrealised",
+                ] {
+                    header.col(|ui| {
+                        ui.strong(heading);
+                    });
+                }
+            })
+            .body(|mut body| {
+                for (name, position) in rows {
+                    let price = |price: Option<f64>| {
+                        price.map_or(String::from("-"), |price| format!("{price:.decimals$}"))
+                    };
+                    body.row(18.0, |mut row| {
+                        row.col(|ui| {
+                            ui.label(name);
+                        });
+                        row.col(|ui| {
+                            ui.label(position.net_volume.to_string());
+                        });
+                        row.col(|ui| {
+                            ui.label(price(position.average_cost));
+                        });
+                        row.col(|ui| {
+                            ui.label(price(position.mark_price));
+                        });
+                        row.col(|ui| {
+                            render_pnl(ui, position.realised_pnl);
+                        });
+                        row.col(|ui| {
+                            render_pnl(ui, position.unrealised_pnl);
+                        });
+                    });
+                }
+            });
+    });
+}
+
+pub fn render_price_chart(fx_viewer_app: &mut FxViewerApp, ctx: &egui::Context) {
+    // best bid, best offer and mid of the selected book over the chosen time window, stepping
+    // at each change, with each liquidity provider's best quotes dashed behind them
+    if !fx_viewer_app.show_chart {
+        return;
+    }
+    let Some(fx_book) = fx_viewer_app.snapshots.book(&fx_viewer_app.selected_pair) else {
+        return;
+    };
+    let decimals = price_decimals(&fx_book.currency_pair) as usize;
+    egui::TopBottomPanel::bottom("price_chart")
+        .resizable(true)
+        .default_height(160.)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let selected_window = CHART_WINDOWS
+                    .iter()
+                    .find(|(_, window)| *window == fx_viewer_app.chart_window)
+                    .map_or("", |(label, _)| label);
+                egui::ComboBox::from_label("Window")
+                    .selected_text(selected_window)
+                    .show_ui(ui, |ui| {
+                        for (label, window) in CHART_WINDOWS {
+                            ui.selectable_value(&mut fx_viewer_app.chart_window, window, label);
+                        }
+                    });
+                ui.checkbox(&mut fx_viewer_app.show_lp_quotes, "LP quotes");
+            });
+            let history = &fx_book.history;
+            let window = fx_viewer_app.chart_window;
+            let (from, to) = (
+                history.latest.saturating_sub(window.as_nanos() as u64),
+                history.latest,
+            );
+            Plot::new("price_chart")
+                .legend(Legend::default())
+                .allow_drag(false)
+                .allow_zoom(false)
+                .allow_scroll(false)
+                .include_x(-window.as_secs_f64())
+                .include_x(0.0)
+                .x_axis_formatter(|mark, _| format!("{}s", mark.value))
+                .y_axis_formatter(move |mark, _| format!("{:.decimals$}", mark.value))
+                .label_formatter(move |name, point| {
+                    format!("{name}\n{:.1}s {:.decimals$}", point.x, point.y)
+                })
+                .show(ui, |plot_ui| {
+                    if fx_viewer_app.show_lp_quotes {
+                        for ((liquidity_provider, lp_quotes), colour) in
+                            history.lp_quotes.iter().zip(LP_COLOURS.iter().cycle())
+                        {
+                            for price in [bid_price, offer_price] {
+                                let lines = lp_quotes.steps(from, to, price);
+                                plot_lines(plot_ui, liquidity_provider, *colour, true, lines);
+                            }
+                        }
+                    }
+                    for (name, colour, price) in [
+                        (
+                            "Bid",
+                            Color32::GREEN,
+                            bid_price as fn(&PriceSample) -> Option<f64>,
+                        ),
+                        ("Offer", Color32::RED, offer_price),
+                        ("Mid", Color32::WHITE, PriceSample::mid),
+                    ] {
+                        let lines = history.top_of_book.steps(from, to, price);
+                        plot_lines(plot_ui, name, colour, false, lines);
+                    }
+                });
+        });
+}
+
+fn bid_price(sample: &PriceSample) -> Option<f64> {
+    sample.bid.map(Price::to_f64)
+}
+
+fn offer_price(sample: &PriceSample) -> Option<f64> {
+    sample.offer.map(Price::to_f64)
+}
+
+fn plot_lines(
+    plot_ui: &mut PlotUi<'_>,
+    name: &str,
+    colour: Color32,
+    dashed: bool,
+    lines: Vec<Vec<[f64; 2]>>,
+) {
+    // lines of the same name share a legend entry
+    let style = if dashed {
+        LineStyle::dashed_dense()
+    } else {
+        LineStyle::Solid
+    };
+    for points in lines {
+        plot_ui.line(Line::new(name, points).color(colour).style(style));
+    }
+}
+
+fn render_pnl(ui: &mut egui::Ui, pnl: f64) {
+    let colour = if pnl < 0.0 {
+        Color32::RED
+    } else {
+        Color32::GREEN
+    };
+    ui.colored_label(colour, format!("{pnl:.2}"));
+}
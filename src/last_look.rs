@@ -0,0 +1,273 @@
+//! # FX Simulator and Aggregator - fx_sim_agg
+//!
+//! `last_look.rs` lets the simulated liquidity providers respond to trade requests against the quotes they stream.
+//! Each liquidity provider holds a request for its last look hold time, then rejects it at random, rejects it if its
+//! price has moved against it by more than its tolerance since the quote, or fills it in full or in part at the
+//! requested price.
+use crate::AppError;
+use crate::order::{ExecutionReport, Side};
+use crate::price::{Price, pips_to_ticks};
+use crate::quote::{QuoteTier, QuoteUpdate};
+use crate::simulator::{self, Config};
+use crate::snapshot::Snapshots;
+use futures::future;
+use log::info;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::sleep;
+
+// added to each liquidity provider's seed so last look decisions don't share its price moves
+const LAST_LOOK_SEED_OFFSET: u64 = 0x1A57_100C;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LastLookConfig {
+    // time a trade request is held before the liquidity provider decides
+    pub hold_time: Duration,
+    // chance of rejecting a trade request whatever the price
+    pub reject_probability: f64,
+    // ticks the price may move against the liquidity provider during the hold before it rejects
+    // the request, None to accept any price move
+    pub max_price_move: Option<i64>,
+}
+
+impl LastLookConfig {
+    pub fn new(hold_ms: u64, reject_probability: f64, max_price_move_pips: Option<f64>) -> Self {
+        LastLookConfig {
+            hold_time: Duration::from_millis(hold_ms),
+            reject_probability,
+            max_price_move: max_price_move_pips.map(pips_to_ticks),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), AppError> {
+        if !(0.0..=1.0).contains(&self.reject_probability) {
+            return Err(AppError::InvalidConfig(format!(
+                "reject probability {} must be between 0 and 1",
+                self.reject_probability
+            )));
+        }
+        if self.max_price_move.is_some_and(|ticks| ticks < 0) {
+            return Err(AppError::InvalidConfig(String::from(
+                "max price move must not be negative",
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TradeRequest {
+    pub liquidity_provider: String,
+    pub currency_pair: String,
+    // side of the client, a buy trades against the liquidity provider's offer
+    pub side: Side,
+    pub price: Price,
+    // volume in millions of the base currency
+    pub volume: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RejectReason {
+    // the liquidity provider isn't quoting this side
+    NoQuote,
+    // rejected at random during the last look
+    LastLook,
+    // the price moved against the liquidity provider by this many ticks during the hold
+    PriceMoved(i64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TradeResponse {
+    Filled {
+        price: Price,
+        volume: i32,
+    },
+    // the request was for more than the liquidity provider's largest quoted volume
+    PartiallyFilled {
+        price: Price,
+        volume: i32,
+        rejected_volume: i32,
+    },
+    Rejected(RejectReason),
+}
+
+impl TradeResponse {
+    pub fn filled_volume(&self) -> i32 {
+        match self {
+            TradeResponse::Filled { volume, .. } => *volume,
+            TradeResponse::PartiallyFilled { volume, .. } => *volume,
+            TradeResponse::Rejected(_) => 0,
+        }
+    }
+}
+
+impl Display for TradeResponse {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TradeResponse::Filled { price, volume } => write!(f, "filled {volume}M at {price}"),
+            TradeResponse::PartiallyFilled {
+                price,
+                volume,
+                rejected_volume,
+            } => write!(
+                f,
+                "filled {volume}M at {price}, rejected {rejected_volume}M"
+            ),
+            TradeResponse::Rejected(reason) => write!(f, "rejected - {reason:?}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Dealer {
+    last_look: LastLookConfig,
+    rng: ChaCha8Rng,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LastLookDesk {
+    // a dealer for each liquidity provider and currency pair, shared so that trade requests can
+    // be held at the same time
+    dealers: Arc<Mutex<HashMap<(String, String), Dealer>>>,
+    // books published by the aggregator, holding each liquidity provider's latest quote update
+    // as of the last publish interval
+    snapshots: Arc<Snapshots>,
+}
+
+impl LastLookDesk {
+    pub fn new(config: &[Config], seed: Option<u64>, snapshots: Arc<Snapshots>) -> Self {
+        let dealers = (0..)
+            .zip(config)
+            .map(|(index, config)| {
+                let seed = simulator::lp_seed(config, seed, index);
+                let dealer = Dealer {
+                    last_look: config.last_look.clone(),
+                    rng: ChaCha8Rng::seed_from_u64(seed.wrapping_add(LAST_LOOK_SEED_OFFSET)),
+                };
+                let key = (
+                    config.liquidity_provider.clone(),
+                    config.currency_pair.clone(),
+                );
+                (key, dealer)
+            })
+            .collect();
+        LastLookDesk {
+            dealers: Arc::new(Mutex::new(dealers)),
+            snapshots,
+        }
+    }
+
+    pub async fn request_trade(&self, request: &TradeRequest) -> Result<TradeResponse, AppError> {
+        // hold the request for the liquidity provider's last look before deciding against its
+        // latest published quote at the end of the hold
+        let key = (
+            request.liquidity_provider.clone(),
+            request.currency_pair.clone(),
+        );
+        let hold_time = self
+            .dealers
+            .lock()
+            .unwrap() // panic if can't get lock
+            .get(&key)
+            .map(|dealer| dealer.last_look.hold_time)
+            .ok_or_else(|| {
+                AppError::InvalidOrder(format!(
+                    "{} does not quote {}",
+                    request.liquidity_provider, request.currency_pair
+                ))
+            })?;
+        if !hold_time.is_zero() {
+            sleep(hold_time).await;
+        }
+        let fx_book = self.snapshots.book(&request.currency_pair);
+        let quote = fx_book
+            .as_ref()
+            .and_then(|fx_book| fx_book.quotes.get(&request.liquidity_provider));
+        let mut dealers = self.dealers.lock().unwrap(); // panic if can't get lock
+        let dealer = dealers.get_mut(&key).expect("dealers are never removed");
+        let response = last_look(dealer, quote, request);
+        info!(
+            "{} {} {}M {} at {} {response}",
+            request.liquidity_provider,
+            request.side,
+            request.volume,
+            request.currency_pair,
+            request.price
+        );
+        Ok(response)
+    }
+
+    pub async fn request_fills(
+        &self,
+        execution_report: &ExecutionReport,
+    ) -> Vec<Result<TradeResponse, AppError>> {
+        // send a trade request to the liquidity provider of each fill, all held at the same time
+        let requests: Vec<TradeRequest> = execution_report
+            .fills
+            .iter()
+            .map(|fill| TradeRequest {
+                liquidity_provider: fill.liquidity_provider.clone(),
+                currency_pair: execution_report.order.currency_pair.clone(),
+                side: execution_report.order.side,
+                price: fill.price,
+                volume: fill.volume,
+            })
+            .collect();
+        future::join_all(requests.iter().map(|request| self.request_trade(request))).await
+    }
+}
+
+fn last_look(
+    dealer: &mut Dealer,
+    quote: Option<&QuoteUpdate>,
+    request: &TradeRequest,
+) -> TradeResponse {
+    let tiers: &[QuoteTier] = match (quote, request.side) {
+        (Some(quote), Side::Buy) => &quote.offers,
+        (Some(quote), Side::Sell) => &quote.bids,
+        (None, _) => &[],
+    };
+    // the tier the request is priced from is the smallest that covers its volume, or the largest
+    // tier, which limits the volume filled
+    let Some(tier) = tiers
+        .iter()
+        .filter(|tier| tier.volume >= request.volume)
+        .min_by_key(|tier| tier.volume)
+        .or_else(|| tiers.iter().max_by_key(|tier| tier.volume))
+        .copied()
+    else {
+        return TradeResponse::Rejected(RejectReason::NoQuote);
+    };
+    if dealer.rng.random_bool(dealer.last_look.reject_probability) {
+        return TradeResponse::Rejected(RejectReason::LastLook);
+    }
+    // the price has moved against the liquidity provider if its offer has risen above, or its
+    // bid fallen below, the requested price
+    let price_move = match request.side {
+        Side::Buy => tier.price.diff_ticks(request.price),
+        Side::Sell => request.price.diff_ticks(tier.price),
+    };
+    if let Some(max_price_move) = dealer.last_look.max_price_move
+        && price_move > max_price_move
+    {
+        return TradeResponse::Rejected(RejectReason::PriceMoved(price_move));
+    }
+    if tier.volume < request.volume {
+        TradeResponse::PartiallyFilled {
+            price: request.price,
+            volume: tier.volume,
+            rejected_volume: request.volume - tier.volume,
+        }
+    } else {
+        TradeResponse::Filled {
+            price: request.price,
+            volume: request.volume,
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! # FX Simulator and Aggregator - fx_sim_agg
+//!
+//! `latency.rs` records the tick-to-book latency of the running aggregator - the time from a quote update being sent
+//! by its source to the updated book being published to readers - in a histogram, and summarises its percentiles.
+use hdrhistogram::Histogram;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::time::Duration;
+
+// latencies are recorded in nanoseconds to 3 significant figures, up to a minute
+const SIGNIFICANT_FIGURES: u8 = 3;
+const MAX_LATENCY_NANOS: u64 = 60_000_000_000;
+
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+    histogram: Histogram<u64>,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            histogram: Histogram::new_with_bounds(1, MAX_LATENCY_NANOS, SIGNIFICANT_FIGURES)
+                .unwrap(), // panic if the constant bounds are invalid
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        // latencies beyond the highest trackable value are recorded as the highest
+        let nanos = (latency.as_nanos() as u64).clamp(1, MAX_LATENCY_NANOS);
+        self.histogram.saturating_record(nanos);
+    }
+
+    pub fn len(&self) -> u64 {
+        self.histogram.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.histogram.is_empty()
+    }
+
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        Duration::from_nanos(self.histogram.value_at_quantile(percentile / 100.0))
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.histogram.max())
+    }
+
+    pub fn reset(&mut self) {
+        self.histogram.reset();
+    }
+}
+
+impl Display for LatencyHistogram {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let micros = |latency: Duration| latency.as_secs_f64() * 1e6;
+        write!(
+            f,
+            "{} updates, p50 {:.1}us, p90 {:.1}us, p99 {:.1}us, p99.9 {:.1}us, max {:.1}us",
+            self.len(),
+            micros(self.percentile(50.0)),
+            micros(self.percentile(90.0)),
+            micros(self.percentile(99.0)),
+            micros(self.percentile(99.9)),
+            micros(self.max())
+        )
+    }
+}
@@ -6,17 +6,73 @@
 //! - `main.rs`  Defines and initiates the UI runtime (which in turn intiates the asynchronous fx simulation and aggregation runtime). Also initiates log4rs logging framework
 //! - `simulator.rs` generates simulated FX market data and sends the data as asynchronous market data streams
 //! - `aggregator.rs` updates and aggregates the asynchronous data streams into a real-time FX book of buys and sells
+//!   for each currency pair, with the `BookManager` routing each quote to the book for its currency pair
 //! - `lib.rs` Includes the thread which combines all the individual asynchronous market data streams from each liquidity provider into a single merged stream
-//! that yields values in the order they arrive from the source market data streams. Also incudes the FxViewerApp structure which initiates and updates the GUI.
-//! Various utilities used by the other modules are also in this library.
+//!   that yields values in the order they arrive from the source market data streams. Also incudes the FxViewerApp structure which initiates and updates the GUI.
+//!   Various utilities used by the other modules are also in this library.
+//! - `book_event.rs` Defines the typed events an aggregated book broadcasts to its subscribers as it changes.
+//! - `book_side.rs` Defines `BookSide`, one side of an aggregated book keyed by price with each liquidity provider
+//!   indexed to the levels it quotes, so quote updates don't sort or scan the whole book.
+//! - `book_policy.rs` Defines the `BookPolicy` trait and the crossed book and minimum spread policies configured per
+//!   currency pair.
+//! - `cli.rs` Defines the command line arguments of the binary, which override the config file settings.
+//! - `config.rs` Loads the TOML config file (or imports a CSV config file) and reports any problems with the file, line,
+//!   liquidity provider and field.
 //! - `gui.rs` Contains the definition of the GUI components and how to render them.
-mod aggregator;
+//! - `fix.rs` Encodes and decodes the FIX 4.4 snapshot (35=W) and incremental refresh (35=X) market data messages of the
+//!   FIX log, converting them to and from quote updates.
+//! - `latency.rs` Records the tick-to-book latency of the running aggregator in a histogram and summarises its
+//!   percentiles.
+//! - `last_look.rs` Lets the simulated liquidity providers fill, partially fill or reject trade requests against their
+//!   last quote after a last look hold.
+//! - `order.rs` Executes market and limit, immediate or cancel and fill or kill orders against the aggregated book,
+//!   allocating the fills across the liquidity providers of each price level.
+//! - `position.rs` Keeps the net position, average cost and realised and unrealised P&L of each currency pair, overall
+//!   and per liquidity provider, from a blotter file of trades or executed orders.
+//! - `quote.rs` Defines the typed `QuoteUpdate` every market data source sends to the aggregator, only encoded as FIX
+//!   messages at the FIX log and replay edges.
+//! - `price.rs` Defines the fixed-point `Price` type used for all FX prices so that price comparisons are exact.
+//! - `price_history.rs` Keeps a bounded history of the top of each aggregated book and each liquidity provider's best
+//!   quotes for the GUI's price chart.
+//! - `replay.rs` Streams the market data messages of a recorded FIX log back to the aggregator at their original pace,
+//!   accelerated or as fast as possible.
+//! - `snapshot.rs` Publishes immutable snapshots of the aggregated books and positions that the GUI and other readers
+//!   load without ever blocking the aggregator.
+//! - `risk.rs` Checks orders against the pre-trade risk limits - maximum order size, maximum net position, a fat finger
+//!   price band and a credit line per liquidity provider - rejecting or capping those that breach them.
+//! - `source.rs` Defines the `MarketDataSource` trait for simulated liquidity providers, FIX log replays and other feeds,
+//!   and merges the market data of any mix of sources.
+//! - `synthetic.rs` Generates synthetic quote streams with any number of liquidity providers, tiers and price
+//!   volatility for benchmarking the aggregator.
+//! - `price_model.rs` Defines the `PriceModel` trait and the price processes the simulator can use for each liquidity provider.
+pub mod aggregator;
+pub mod book_event;
+pub mod book_policy;
+pub mod book_side;
+pub mod cli;
+pub mod config;
+pub mod fix;
 mod gui;
+pub mod last_look;
+pub mod latency;
+pub mod order;
+pub mod position;
+pub mod price;
+pub mod price_history;
+mod price_model;
+pub mod quote;
+pub mod replay;
+pub mod risk;
 mod simulator;
+pub mod snapshot;
+pub mod source;
+pub mod synthetic;
 mod tests;
 use eframe::egui;
 use egui::Context;
-use log::error;
+use futures::future;
+use log::{error, info};
+use std::cell::RefCell;
 use std::fmt;
 use std::fmt::Display;
 use std::fmt::Formatter;
@@ -27,17 +83,30 @@ use std::io::prelude::*;
 use std::num::ParseFloatError;
 use std::num::ParseIntError;
 use std::path::Path;
+use std::pin::pin;
 use std::process::exit;
-use std::sync::{Arc, Mutex, mpsc};
+use std::sync::{Arc, mpsc};
 use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::runtime::Runtime;
+use tokio::time::{interval, timeout};
 use tokio_stream::StreamExt;
 
+// how often the books are checked for quotes older than their liquidity provider's time to live
+const QUOTE_EXPIRY_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+// how often the tick-to-book latency histogram is logged while market data arrives
+const LATENCY_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
 #[derive(Debug)]
 #[non_exhaustive]
 pub enum AppError {
-    NumParams,
-    IsEmpty,
+    InvalidPrice(String),
+    Fix(String),
+    InvalidConfig(String),
+    Config(config::ConfigError),
+    InvalidOrder(String),
+    InvalidTrade(String),
+    RiskLimit(risk::RiskBreach),
     ParseFloat(ParseFloatError),
     ParseInt(ParseIntError),
     Io(io::Error),
@@ -64,8 +133,13 @@ impl From<io::Error> for AppError {
 impl Display for AppError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
-            Self::IsEmpty => f.write_str("empty data field"),
-            Self::NumParams => f.write_str("missing market data fields"),
+            Self::InvalidPrice(price) => write!(f, "invalid price {price}"),
+            Self::Fix(e) => write!(f, "invalid FIX message - {e}"),
+            Self::InvalidConfig(e) => write!(f, "invalid config - {e}"),
+            Self::Config(e) => write!(f, "invalid config - {e}"),
+            Self::InvalidOrder(e) => write!(f, "invalid order - {e}"),
+            Self::InvalidTrade(e) => write!(f, "invalid trade - {e}"),
+            Self::RiskLimit(e) => write!(f, "risk limit breached - {e}"),
             Self::ParseFloat(e) => Display::fmt(e, f),
             Self::ParseInt(e) => Display::fmt(e, f),
             Self::Io(e) => Display::fmt(e, f),
@@ -82,37 +156,76 @@ pub fn run<F: Future>(future: F) -> F::Output {
 
 #[derive(Default, Debug)]
 pub struct FxViewerApp {
-    pub fx_book_mutex: Arc<Mutex<aggregator::FxBook>>,
+    // latest aggregated books, and positions and P&L of the trades in the blotter marked to
+    // them, published by the aggregation thread
+    pub snapshots: Arc<snapshot::Snapshots>,
+    // the liquidity providers' last look at trade requests against their quotes in the published
+    // books, so orders can be routed against the running simulation
+    pub last_look_desk: last_look::LastLookDesk,
+    // currency pair of the aggregated book shown in the GUI
+    pub selected_pair: String,
+    // settings and liquidity providers read from the config file
+    pub config: config::AppConfig,
+    // whether the price chart is shown, how far back it goes and whether it includes each
+    // liquidity provider's best quotes
+    pub show_chart: bool,
+    pub chart_window: Duration,
+    pub show_lp_quotes: bool,
 }
 
 impl FxViewerApp {
     pub fn init(&mut self, cc: &eframe::CreationContext<'_>) -> Self {
         let ctx = cc.egui_ctx.clone();
         let (ctx_tx, ctx_rx) = mpsc::channel();
-        // Create "FIX" log file
-        let writer = match create_log_file("logs/fix.log") {
+        // Create FIX log file
+        let writer = match create_fix_log(&self.config) {
             Ok(writer) => writer,
             Err(e) => {
                 error!("problem creating log file - {e}");
                 exit(1);
             }
         };
-        // read config file to get configs for each liquidity provider source
-        let mut configs: Vec<simulator::Config> = Vec::new();
-        if let Err(e) = simulator::get_configs(&mut configs) {
-            error!("config input file not processed - {e}");
-            exit(1);
-        }
-        // Create aggregated FX Book
-        let fx_book = aggregator::FxBook::new(&configs);
+        // Read the positions from the blotter
+        let position_keeper = match create_position_keeper(&self.config) {
+            Ok(position_keeper) => position_keeper,
+            Err(e) => {
+                error!("problem reading blotter - {e}");
+                exit(1);
+            }
+        };
+        // Create an aggregated FX Book for each currency pair, showing the first configured pair in the GUI
+        let config = self.config.clone();
+        let book_manager = aggregator::BookManager::new(
+            &config.liquidity_providers,
+            config.general.min_spread_pips,
+        )
+        .with_book_policies(&config.book_policies);
+        let selected_pair = config
+            .liquidity_providers
+            .first()
+            .map(|config| config.currency_pair.clone())
+            .unwrap_or_default();
 
-        let fx_book_mutex = Arc::new(Mutex::new(fx_book));
-        let fx_book_mutex_ui_clone = Arc::clone(&fx_book_mutex);
-        let fx_book_mutex_fx_clone = Arc::clone(&fx_book_mutex);
+        // the fx thread owns the books and positions, the GUI reads the snapshots it publishes
+        let snapshots = Arc::new(snapshot::Snapshots::new(&book_manager, &position_keeper));
+        let snapshots_fx_clone = Arc::clone(&snapshots);
+        let last_look_desk = last_look::LastLookDesk::new(
+            &config.liquidity_providers,
+            config.general.seed,
+            Arc::clone(&snapshots),
+        );
+        let fx_config = config.clone();
         thread::spawn(move || {
             // start fx thread
             let rec_ctx: Context = ctx_rx.recv().unwrap();
-            run_async_fx_sim_agg(rec_ctx, writer, fx_book_mutex_fx_clone, &configs);
+            run_async_fx_sim_agg(
+                Some(rec_ctx),
+                writer,
+                book_manager,
+                position_keeper,
+                &snapshots_fx_clone,
+                &fx_config,
+            );
         }); // end of fx thread 
 
         if let Err(e) = ctx_tx.send(ctx) {
@@ -121,23 +234,74 @@ impl FxViewerApp {
         }
 
         Self {
-            fx_book_mutex: fx_book_mutex_ui_clone,
+            snapshots,
+            last_look_desk,
+            selected_pair,
+            config,
+            show_chart: true,
+            chart_window: gui::CHART_WINDOWS[0].1,
+            show_lp_quotes: false,
         }
     }
 }
 
 impl eframe::App for FxViewerApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        gui::render_top_panel(ctx);
+        gui::render_top_panel(self, ctx);
+        gui::render_positions(self, ctx);
+        gui::render_price_chart(self, ctx);
         gui::render_fx_book(self, ctx);
     }
 }
 
+pub fn run_headless(config: &config::AppConfig) -> Result<(), AppError> {
+    // run the simulation and aggregation without a GUI, returning once every liquidity provider
+    // has sent all its quotes or the run duration is reached
+    let writer = create_fix_log(config)?;
+    let position_keeper = create_position_keeper(config)?;
+    let book_manager =
+        aggregator::BookManager::new(&config.liquidity_providers, config.general.min_spread_pips)
+            .with_book_policies(&config.book_policies);
+    let snapshots = snapshot::Snapshots::new(&book_manager, &position_keeper);
+    run_async_fx_sim_agg(
+        None,
+        writer,
+        book_manager,
+        position_keeper,
+        &snapshots,
+        config,
+    );
+
+    // log the top of each book at the end of the run
+    for (currency_pair, fx_book) in snapshots.books().iter() {
+        match (fx_book.buy_book.first(), fx_book.sell_book.first()) {
+            (Some(buy), Some(sell)) => info!(
+                "{currency_pair} finished with best buy {} ({}M) and best sell {} ({}M)",
+                buy.price, buy.volume, sell.price, sell.volume
+            ),
+            _ => info!("{currency_pair} finished with a one sided or empty book"),
+        }
+    }
+    // and the positions marked to them
+    for (currency_pair, position) in &snapshots.positions().positions {
+        info!(
+            "{currency_pair} finished with a position of {}M, realised P&L {:.2} and unrealised P&L {:.2}",
+            position.net_volume, position.realised_pnl, position.unrealised_pnl
+        );
+    }
+    Ok(())
+}
+
 pub fn run_async_fx_sim_agg(
-    rec_ctx: Context,
-    mut writer: BufWriter<File>,
-    fx_book_mutex_fx_clone: Arc<Mutex<aggregator::FxBook>>,
-    configs: &Vec<simulator::Config>,
+    // GUI context to repaint on each update, None when running headless
+    rec_ctx: Option<Context>,
+    // FIX log of the market data, None when replaying a recorded FIX log
+    mut writer: Option<BufWriter<File>>,
+    book_manager: aggregator::BookManager,
+    position_keeper: position::PositionKeeper,
+    // snapshots of the books and positions published to readers as they change
+    snapshots: &snapshot::Snapshots,
+    config: &config::AppConfig,
 ) {
     run(async {
         /*  async returns a future rather than blocking current thread
@@ -145,66 +309,223 @@ pub fn run_async_fx_sim_agg(
         is the signature future argument of run! Note: everything inside the async code avoids blocking
         but any code outside run will block on the run function returning */
 
-        // Combine all individual market data streams from each liquidity provider into a single merged stream
-        // that yields values in the order they arrive from the source market data streams
-        let mut merged_streams_map = simulator::start_streams(configs);
+        // Combine all individual market data streams from each source (simulated liquidity providers
+        // or a recorded FIX log) into a single merged stream that yields values in the order they
+        // arrive from the source market data streams
+        let sources: Vec<Box<dyn source::MarketDataSource>> = match &config.replay {
+            Some(replay) => vec![Box::new(replay.clone())],
+            None => simulator::sources(&config.liquidity_providers, config.general.seed),
+        };
+        let mut merged_streams_map = source::merge_sources(sources);
+        // quotes are only encoded as FIX messages for the FIX log
+        let mut quote_encoder = fix::QuoteEncoder::new(&config.liquidity_providers);
+        // shared by the merge, publish and expire_quotes futures of this thread, none holds a
+        // borrow across an await so they can never conflict
+        let book_manager = RefCell::new(book_manager);
+        let position_keeper = RefCell::new(position_keeper);
+        // books and positions changed since they were last published to readers
+        let pending = RefCell::new(snapshot::PendingSnapshots::default());
+        let publish_pending = || {
+            let published = pending.borrow_mut().publish(
+                snapshots,
+                &book_manager.borrow(),
+                &position_keeper.borrow(),
+            );
+            // update GUI - send repaint request
+            if published && let Some(rec_ctx) = &rec_ctx {
+                rec_ctx.request_repaint();
+            }
+        };
+        // live market data is timestamped by the wall clock when it is sent, seeded and replayed
+        // market data isn't so its latency is measured from when the aggregator receives it
+        let live = config.replay.is_none()
+            && !simulator::virtual_clock(&config.liquidity_providers, config.general.seed);
+        let mut latency = latency::LatencyHistogram::default();
+        let mut last_latency_report = Instant::now();
+        // quotes expire on the clock of the market data, checked on a timer against the wall
+        // clock for live market data and against the latest source time on each seeded or
+        // replayed quote update, so those runs expire the same quotes however fast they run
+        let quote_ttls = config
+            .liquidity_providers
+            .iter()
+            .any(|config| config.quote_ttl.is_some());
+        let mut source_clock: u64 = 0;
 
-        while let Some(val) = merged_streams_map.next().await {
-            // await polls the future until future returns Ready.
-            // If future still pending then control is handed to the runtime
-            let (_key, market_data) = val;
+        let merge = async {
+            while let Some(val) = merged_streams_map.next().await {
+                // await polls the future until future returns Ready.
+                // If future still pending then control is handed to the runtime
+                let (source, quote) = val;
+                let received = Instant::now();
+                let source_time = quote.source_time;
 
-            // write market data to a "FIX" log
-            if let Err(e) = write_to_fix_log(&mut writer, &market_data) {
-                error!("problem writing to FIX log - {e}");
+                // write the quote update to the FIX log
+                if let Some(writer) = &mut writer
+                    && let Some(market_data) = quote_encoder.encode(&quote)
+                    && let Err(e) = write_to_fix_log(writer, &market_data.encode())
+                {
+                    error!("problem writing to FIX log - {e}");
+                }
+
+                // Update the Fx Book for the currency pair of the new quote update
+                {
+                    let mut book_manager = book_manager.borrow_mut();
+                    let mut position_keeper = position_keeper.borrow_mut();
+                    let mut pending = pending.borrow_mut();
+                    match book_manager.update(quote) {
+                        Err(e) => {
+                            //print/log error and continuing processing next market data values
+                            error!("market data from {source} not processed - {e}");
+                        }
+                        Ok(fx_book) => {
+                            // mark the positions to the updated book, both are published with
+                            // the next snapshots
+                            position_keeper.mark_to_market(fx_book);
+                            pending.book_changed(&fx_book.currency_pair);
+                            if position_keeper.position(&fx_book.currency_pair).is_some() {
+                                pending.positions_changed();
+                            }
+                            latency.record(tick_to_book(live, source_time, received));
+                            // print FX book as ladder to console
+                            if config.general.print_ladder {
+                                aggregator::print_fxbook_as_ladder(fx_book);
+                            }
+                        }
+                    }
+                    if quote_ttls && !live {
+                        source_clock = source_clock.max(source_time);
+                        let expired = book_manager.expire_quotes(source_clock);
+                        mark_expired(&expired, &book_manager, &mut position_keeper, &mut pending);
+                    }
+                }
+                // publish the changed books at most once a publish interval, the publish future
+                // catches up with any left when the market data pauses
+                if pending.borrow().is_due() {
+                    publish_pending();
+                }
+                if last_latency_report.elapsed() >= LATENCY_REPORT_INTERVAL {
+                    info!("tick-to-book latency - {latency}");
+                    last_latency_report = Instant::now();
+                }
             }
+        };
 
-            // Update the Fx Book with the new market data
-            let mut fx_book = fx_book_mutex_fx_clone.lock().unwrap(); // panic if can't get lock
-            if let Err(e) = fx_book.update(market_data) {
-                //print/log error and continuing processing next market data values
-                error!("market data not processed - {e}");
+        // publish the books and positions still pending on a timer, for as long as market data
+        // arrives
+        let publish = async {
+            let mut publish_check = interval(snapshot::PUBLISH_INTERVAL);
+            loop {
+                publish_check.tick().await;
+                publish_pending();
+            }
+        };
+        // purge live quotes older than their time to live on a timer, for as long as market data
+        // arrives
+        let expire_quotes = async {
+            let mut expiry_check = interval(QUOTE_EXPIRY_CHECK_INTERVAL);
+            loop {
+                expiry_check.tick().await;
+                let mut book_manager = book_manager.borrow_mut();
+                let expired = book_manager.expire_quotes(wall_clock_nanos());
+                mark_expired(
+                    &expired,
+                    &book_manager,
+                    &mut position_keeper.borrow_mut(),
+                    &mut pending.borrow_mut(),
+                );
+            }
+        };
+        let timers = async {
+            if quote_ttls && live {
+                future::join(publish, expire_quotes).await;
             } else {
-                // update GUI - send repaint request
-                rec_ctx.request_repaint();
-                // print FX book as ladder to console
-                aggregator::print_fxbook_as_ladder(&mut fx_book);
+                publish.await;
+            }
+        };
+        let aggregate = async {
+            future::select(pin!(merge), pin!(timers)).await;
+        };
+
+        // stop the simulation and close the GUI once the run duration is reached
+        if let Some(run_duration) = config.general.run_duration {
+            if timeout(run_duration, aggregate).await.is_err() {
+                info!("run duration of {run_duration:?} reached, stopping simulation");
+                if let Some(rec_ctx) = &rec_ctx {
+                    rec_ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                }
             }
-        } // mutex lock released here
+        } else {
+            aggregate.await;
+        }
+        // the final books and positions
+        publish_pending();
+        if !latency.is_empty() {
+            info!("tick-to-book latency at the end of the run - {latency}");
+        }
     });
 }
 
+fn tick_to_book(live: bool, source_time: u64, received: Instant) -> Duration {
+    // time from the quote update being sent, or received when it isn't timestamped by the wall
+    // clock, to the updated book being published
+    if !live {
+        return received.elapsed();
+    }
+    Duration::from_nanos(wall_clock_nanos().saturating_sub(source_time))
+}
+
+fn wall_clock_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+fn mark_expired(
+    expired: &[(String, String)],
+    book_manager: &aggregator::BookManager,
+    position_keeper: &mut position::PositionKeeper,
+    pending: &mut snapshot::PendingSnapshots,
+) {
+    // mark the positions to the books that quotes have expired from, both are published with the
+    // next snapshots
+    for (currency_pair, _) in expired {
+        if let Some(fx_book) = book_manager.book(currency_pair) {
+            position_keeper.mark_to_market(fx_book);
+            pending.book_changed(currency_pair);
+            pending.positions_changed();
+        }
+    }
+}
+
 pub fn create_log_file(file_path: &str) -> Result<BufWriter<File>, AppError> {
     let path = Path::new(file_path);
 
     // Open a file in write-only mode, returns `io::Result<File>`
-    let file = File::create(&path)?;
+    let file = File::create(path)?;
 
     Ok(BufWriter::new(file))
 }
 
-pub fn write_to_fix_log(
-    writer: &mut BufWriter<File>,
-    market_data: &String,
-) -> Result<(), AppError> {
-    writeln!(writer, "{}", market_data)?;
-    Ok(())
+pub fn create_fix_log(config: &config::AppConfig) -> Result<Option<BufWriter<File>>, AppError> {
+    // replayed market data is already recorded, and the FIX log may be the file being replayed
+    if config.replay.is_some() {
+        return Ok(None);
+    }
+    Ok(Some(create_log_file(&config.logging.fix_log)?))
 }
 
-pub fn get_params(data: &str, number: usize) -> Result<std::str::Split<'_, &str>, AppError> {
-    let value = data.split("|");
-    if value.clone().count() < number {
-        return Err(AppError::NumParams);
-    } else {
-        Ok(data.split("|"))
+pub fn create_position_keeper(
+    config: &config::AppConfig,
+) -> Result<position::PositionKeeper, AppError> {
+    // start from the trades in the blotter, or flat without one
+    match &config.general.blotter {
+        Some(blotter) => position::PositionKeeper::from_blotter(blotter),
+        None => Ok(position::PositionKeeper::default()),
     }
 }
 
-pub fn get_str_field(field: Option<&str>) -> Result<&str, AppError> {
-    let value = field.unwrap_or("");
-    if value.trim().is_empty() {
-        return Err(AppError::IsEmpty);
-    } else {
-        Ok(value.trim())
-    }
+pub fn write_to_fix_log(writer: &mut BufWriter<File>, market_data: &str) -> Result<(), AppError> {
+    writeln!(writer, "{market_data}")?;
+    Ok(())
 }
@@ -4,32 +4,60 @@
 //! A separate thread renders the FX updates in real-time to a GUI.
 //!
 //! - `main.rs`  Defines and initiates the UI runtime (which in turn intiates the asynchronous fx simulation and aggregation runtime). Also initiates log4rs logging framework
-//! - `simulator.rs` generates simulated FX market data and sends the data as asynchronous market data streams
-//! - `aggregator.rs` updates and aggregates the asynchronous data streams into a real-time FX book of buys and sells
-//! - `lib.rs` Includes the thread which combines all the individual asynchronous market data streams from each liquidity provider into a single merged stream
-//! that yields values in the order they arrive from the source market data streams. Also incudes the FxViewerApp structure which initiates and updates the GUI.
-//! Various utilities used by the other modules are also in this library.
-//! - `gui.rs` Contains the definition of the GUI components and how to render them.
+//!
+//! The other modules are listed in the `fx_sim_agg` library documentation in `lib.rs`.
 use std::process::exit;
 //use log::{debug, error, info, trace, warn};
+use clap::Parser;
 use egui::Vec2;
 use fx_sim_agg::FxViewerApp;
+use fx_sim_agg::cli::Cli;
+use fx_sim_agg::config;
 use log::error;
 
 fn main() {
+    let cli = Cli::parse();
+    // read the config file before logging starts as it names the log4rs config file
+    let mut app_config = match config::load_config(&cli.config) {
+        Ok(app_config) => app_config,
+        Err(e) => {
+            eprintln!("config file not processed - {e}");
+            exit(1);
+        }
+    };
+    // command line arguments override the config file
+    if let Err(e) = cli.apply(&mut app_config) {
+        eprintln!("command line arguments not processed - {e}");
+        exit(1);
+    }
+
     // start log4rs logging framework
-    if let Err(e) = log4rs::init_file("logging_config.yaml", Default::default()) {
+    if let Err(e) = log4rs::init_file(&app_config.logging.log_config, Default::default()) {
         eprintln!("error initialising log4rs - {e}");
         exit(1);
     }
 
-    let mut fx_viewer_app = FxViewerApp::default();
+    // no display is needed when running headless
+    if cli.headless {
+        if let Err(e) = fx_sim_agg::run_headless(&app_config) {
+            error!("headless run failed - {e}");
+            exit(1);
+        }
+        return;
+    }
+
+    let mut fx_viewer_app = FxViewerApp {
+        config: app_config,
+        ..Default::default()
+    };
+    let gui = fx_viewer_app.config.gui.clone();
     let win_option = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default().with_inner_size(Vec2::new(590., 350.)),
+        viewport: egui::ViewportBuilder::default()
+            .with_inner_size(Vec2::new(gui.width, gui.height)),
         ..Default::default()
     };
     if let Err(e) = eframe::run_native(
-        "USD/EUR Aggregated Book",
+        &gui.title,
         win_option,
         Box::new(|cc| Ok(Box::new(fx_viewer_app.init(cc)))),
     ) {
@@ -0,0 +1,247 @@
+//! # FX Simulator and Aggregator - fx_sim_agg
+//!
+//! `order.rs` executes market and limit orders, immediate or cancel or fill or kill, against the aggregated book
+//! of a currency pair. An order sweeps the book's price levels from the top, allocating fills across the liquidity
+//! providers quoting each level in the order their quotes arrived, and the filled volume is taken out of the book.
+use crate::AppError;
+use crate::aggregator::{FxBook, get_book_side};
+use crate::book_side::BookSide;
+use crate::price::Price;
+use log::info;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+impl Side {
+    pub fn book_side(self) -> &'static str {
+        // buy orders are filled from the liquidity providers' offers, sell orders from their bids
+        match self {
+            Side::Buy => "Sell",
+            Side::Sell => "Buy",
+        }
+    }
+}
+
+impl Display for Side {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Side::Buy => f.write_str("Buy"),
+            Side::Sell => f.write_str("Sell"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrderType {
+    // fill at any price
+    Market,
+    // fill at the limit price or better
+    Limit(Price),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeInForce {
+    // fill as much as possible straight away and cancel the rest
+    ImmediateOrCancel,
+    // fill the whole volume straight away or nothing at all
+    FillOrKill,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Order {
+    pub currency_pair: String,
+    pub side: Side,
+    // volume in millions of the base currency
+    pub volume: i32,
+    pub order_type: OrderType,
+    pub time_in_force: TimeInForce,
+}
+
+impl Order {
+    pub fn market(currency_pair: &str, side: Side, volume: i32) -> Self {
+        Order {
+            currency_pair: String::from(currency_pair),
+            side,
+            volume,
+            order_type: OrderType::Market,
+            time_in_force: TimeInForce::ImmediateOrCancel,
+        }
+    }
+
+    pub fn limit(currency_pair: &str, side: Side, volume: i32, price: Price) -> Self {
+        Order {
+            order_type: OrderType::Limit(price),
+            ..Order::market(currency_pair, side, volume)
+        }
+    }
+
+    pub fn fill_or_kill(self) -> Self {
+        Order {
+            time_in_force: TimeInForce::FillOrKill,
+            ..self
+        }
+    }
+
+    fn accepts(&self, price: Price) -> bool {
+        // whether the order can be filled at this price
+        match (self.order_type, self.side) {
+            (OrderType::Market, _) => true,
+            (OrderType::Limit(limit), Side::Buy) => price <= limit,
+            (OrderType::Limit(limit), Side::Sell) => price >= limit,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fill {
+    pub liquidity_provider: String,
+    pub price: Price,
+    pub volume: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrderStatus {
+    Filled,
+    PartiallyFilled,
+    // nothing was filled, or a fill or kill order could not be filled in full
+    Cancelled,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutionReport {
+    pub order: Order,
+    pub status: OrderStatus,
+    pub fills: Vec<Fill>,
+    pub filled_volume: i32,
+    // volume cancelled because the book couldn't fill it
+    pub cancelled_volume: i32,
+    // volume weighted average price of the fills, None if nothing was filled
+    pub average_price: Option<f64>,
+}
+
+impl Display for ExecutionReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}M {} {:?} - {:?}, filled {}M",
+            self.order.side,
+            self.order.volume,
+            self.order.currency_pair,
+            self.order.order_type,
+            self.status,
+            self.filled_volume
+        )?;
+        if let Some(average_price) = self.average_price {
+            write!(f, " at an average price of {average_price}")?;
+        }
+        Ok(())
+    }
+}
+
+pub fn execute_order(fx_book: &mut FxBook, order: &Order) -> Result<ExecutionReport, AppError> {
+    execute_order_within(fx_book, order, &BTreeMap::new())
+}
+
+pub fn execute_order_within(
+    fx_book: &mut FxBook,
+    order: &Order,
+    lp_limits: &BTreeMap<String, i32>,
+) -> Result<ExecutionReport, AppError> {
+    // execute the order filling no more than its limit from each liquidity provider in lp_limits,
+    // and any volume from the others
+    if order.volume <= 0 {
+        return Err(AppError::InvalidOrder(format!(
+            "volume {}M must be positive",
+            order.volume
+        )));
+    }
+    if order.currency_pair != fx_book.currency_pair {
+        return Err(AppError::InvalidOrder(format!(
+            "{} order sent to the {} book",
+            order.currency_pair, fx_book.currency_pair
+        )));
+    }
+    let fx_book_side = get_book_side(fx_book, order.side.book_side());
+
+    // the fills of sweeping the book side are taken from it unless a fill or kill order can't
+    // be filled in full
+    let mut fills = sweep(fx_book_side, order, lp_limits);
+    let mut filled_volume: i32 = fills.iter().map(|fill| fill.volume).sum();
+    if order.time_in_force == TimeInForce::FillOrKill && filled_volume < order.volume {
+        fills.clear();
+        filled_volume = 0;
+    }
+    // the filled volume is no longer available until the liquidity provider quotes again
+    for fill in &fills {
+        fx_book_side.fill(&fill.liquidity_provider, fill.price, fill.volume);
+    }
+
+    let status = if filled_volume == order.volume {
+        OrderStatus::Filled
+    } else if filled_volume > 0 {
+        OrderStatus::PartiallyFilled
+    } else {
+        OrderStatus::Cancelled
+    };
+    let execution_report = ExecutionReport {
+        order: order.clone(),
+        status,
+        average_price: average_price(&fills),
+        fills,
+        filled_volume,
+        cancelled_volume: order.volume - filled_volume,
+    };
+    info!("executed {execution_report}");
+    Ok(execution_report)
+}
+
+fn sweep(fx_book_side: &BookSide, order: &Order, lp_limits: &BTreeMap<String, i32>) -> Vec<Fill> {
+    // fill from the top of the book down, taking each level's liquidity providers in the order
+    // their quotes arrived, until the order is filled or the next level is outside its limit
+    let mut remaining = order.volume;
+    let mut lp_limits = lp_limits.clone();
+    let mut fills: Vec<Fill> = Vec::new();
+    for entry in fx_book_side {
+        if remaining == 0 || !order.accepts(entry.price) {
+            break;
+        }
+        for lp_vol in &entry.lp_vol {
+            let lp_limit = lp_limits.get_mut(&lp_vol.0);
+            let volume = remaining
+                .min(lp_vol.1)
+                .min(lp_limit.as_deref().copied().unwrap_or(i32::MAX));
+            if volume == 0 {
+                continue;
+            }
+            if let Some(lp_limit) = lp_limit {
+                *lp_limit -= volume;
+            }
+            fills.push(Fill {
+                liquidity_provider: lp_vol.0.clone(),
+                price: entry.price,
+                volume,
+            });
+            remaining -= volume;
+        }
+    }
+    fills
+}
+
+pub fn average_price(fills: &[Fill]) -> Option<f64> {
+    let volume: i64 = fills.iter().map(|fill| fill.volume as i64).sum();
+    if volume == 0 {
+        return None;
+    }
+    let notional: f64 = fills
+        .iter()
+        .map(|fill| fill.price.to_f64() * fill.volume as f64)
+        .sum();
+    Some(notional / volume as f64)
+}
@@ -0,0 +1,228 @@
+//! # FX Simulator and Aggregator - fx_sim_agg
+//!
+//! `position.rs` keeps the net position of each currency pair, overall and with each liquidity provider, from the
+//! trades read from a blotter file or added through the library. Each position tracks its average cost and realised
+//! P&L, and its unrealised P&L marked to the mid of the aggregated book every time the book changes. P&L is in
+//! the quote currency of the currency pair.
+use crate::AppError;
+use crate::aggregator::FxBook;
+use crate::order::{ExecutionReport, Side};
+use crate::price::{Price, price_decimals};
+use log::info;
+use std::collections::BTreeMap;
+use std::fs;
+
+// volumes are held in millions of the base currency
+const VOLUME_UNITS: f64 = 1_000_000.0;
+// column names of the blotter file, used to name the field in error messages
+const BLOTTER_COLUMNS: [&str; 5] = [
+    "currency pair",
+    "liquidity provider",
+    "side",
+    "price",
+    "volume",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trade {
+    pub currency_pair: String,
+    pub liquidity_provider: String,
+    pub side: Side,
+    pub price: Price,
+    // volume in millions of the base currency
+    pub volume: i32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Position {
+    // net volume in millions, positive when long the base currency
+    pub net_volume: i64,
+    // volume weighted average price of the open position, None when flat
+    pub average_cost: Option<f64>,
+    pub realised_pnl: f64,
+    // mid of the aggregated book the position was last marked to
+    pub mark_price: Option<f64>,
+    pub unrealised_pnl: f64,
+}
+
+impl Position {
+    pub fn add_trade(&mut self, side: Side, price: Price, volume: i32) {
+        let price = price.to_f64();
+        let volume = volume as i64;
+        let signed_volume = match side {
+            Side::Buy => volume,
+            Side::Sell => -volume,
+        };
+        match self.average_cost {
+            // closing all or part of the open position realises the P&L on the closed volume
+            Some(average_cost) if self.net_volume.signum() == -signed_volume.signum() => {
+                let closed_volume = self.net_volume.abs().min(volume);
+                self.realised_pnl += (price - average_cost)
+                    * (closed_volume * self.net_volume.signum()) as f64
+                    * VOLUME_UNITS;
+                self.net_volume += signed_volume;
+                // any volume left over after closing opens a position the other way
+                self.average_cost = match self.net_volume.signum() {
+                    0 => None,
+                    sign if sign == signed_volume.signum() => Some(price),
+                    _ => Some(average_cost),
+                };
+            }
+            average_cost => {
+                let open_volume = self.net_volume.abs() as f64;
+                let cost = average_cost.unwrap_or(price) * open_volume + price * volume as f64;
+                self.net_volume += signed_volume;
+                self.average_cost = Some(cost / (open_volume + volume as f64));
+            }
+        }
+        self.revalue();
+    }
+
+    pub fn mark_to_market(&mut self, mark_price: f64) {
+        self.mark_price = Some(mark_price);
+        self.revalue();
+    }
+
+    fn revalue(&mut self) {
+        self.unrealised_pnl = match (self.average_cost, self.mark_price) {
+            (Some(average_cost), Some(mark_price)) => {
+                (mark_price - average_cost) * self.net_volume as f64 * VOLUME_UNITS
+            }
+            _ => 0.0,
+        };
+    }
+
+    pub fn total_pnl(&self) -> f64 {
+        self.realised_pnl + self.unrealised_pnl
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PositionKeeper {
+    // net position of each currency pair across all liquidity providers
+    pub positions: BTreeMap<String, Position>,
+    // net position of each currency pair with each liquidity provider
+    pub lp_positions: BTreeMap<(String, String), Position>,
+}
+
+impl PositionKeeper {
+    pub fn from_blotter(file: &str) -> Result<Self, AppError> {
+        let mut position_keeper = PositionKeeper::default();
+        let trades = read_blotter(file)?;
+        info!("{} trades read from blotter {file}", trades.len());
+        for trade in &trades {
+            position_keeper.add_trade(trade);
+        }
+        Ok(position_keeper)
+    }
+
+    pub fn add_trade(&mut self, trade: &Trade) {
+        let mark_price = self
+            .position(&trade.currency_pair)
+            .and_then(|p| p.mark_price);
+        let lp_key = (
+            trade.currency_pair.clone(),
+            trade.liquidity_provider.clone(),
+        );
+        for position in [
+            self.positions
+                .entry(trade.currency_pair.clone())
+                .or_default(),
+            self.lp_positions.entry(lp_key).or_default(),
+        ] {
+            if let Some(mark_price) = mark_price {
+                position.mark_price = Some(mark_price);
+            }
+            position.add_trade(trade.side, trade.price, trade.volume);
+        }
+    }
+
+    pub fn add_execution_report(&mut self, execution_report: &ExecutionReport) {
+        // book every fill of an executed order as a trade with its liquidity provider
+        for fill in &execution_report.fills {
+            self.add_trade(&Trade {
+                currency_pair: execution_report.order.currency_pair.clone(),
+                liquidity_provider: fill.liquidity_provider.clone(),
+                side: execution_report.order.side,
+                price: fill.price,
+                volume: fill.volume,
+            });
+        }
+    }
+
+    pub fn mark_to_market(&mut self, fx_book: &FxBook) {
+        // mark the currency pair's positions to the mid of the top of the book, keeping the last
+        // mark while the book is one sided or empty
+        let Some(mid) = fx_book.mid() else {
+            return;
+        };
+        if let Some(position) = self.positions.get_mut(&fx_book.currency_pair) {
+            position.mark_to_market(mid);
+        }
+        for ((currency_pair, _), position) in self.lp_positions.iter_mut() {
+            if *currency_pair == fx_book.currency_pair {
+                position.mark_to_market(mid);
+            }
+        }
+    }
+
+    pub fn position(&self, currency_pair: &str) -> Option<&Position> {
+        self.positions.get(currency_pair)
+    }
+
+    pub fn lp_position(&self, currency_pair: &str, liquidity_provider: &str) -> Option<&Position> {
+        self.lp_positions.get(&(
+            String::from(currency_pair),
+            String::from(liquidity_provider),
+        ))
+    }
+}
+
+pub fn read_blotter(file: &str) -> Result<Vec<Trade>, AppError> {
+    let contents = fs::read_to_string(file)?;
+    parse_blotter(file, &contents)
+}
+
+pub fn parse_blotter(file: &str, contents: &str) -> Result<Vec<Trade>, AppError> {
+    // one trade per line after a header line: currency pair, liquidity provider, side (Buy or
+    // Sell), price and volume in millions
+    let mut trades: Vec<Trade> = Vec::new();
+    for (index, line) in contents.lines().enumerate().skip(1) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let error = |column: usize, message: &str| {
+            AppError::InvalidTrade(format!(
+                "{file}, line {}, field {} - {message}",
+                index + 1,
+                BLOTTER_COLUMNS[column]
+            ))
+        };
+        let field = |column: usize| match fields.get(column) {
+            Some(value) if !value.is_empty() => Ok(*value),
+            _ => Err(error(column, "missing value")),
+        };
+        let currency_pair = field(0)?;
+        let side = match field(2)? {
+            "Buy" => Side::Buy,
+            "Sell" => Side::Sell,
+            side => return Err(error(2, &format!("{side} must be Buy or Sell"))),
+        };
+        let price = Price::parse(field(3)?, price_decimals(currency_pair))
+            .map_err(|e| error(3, &e.to_string()))?;
+        let volume: i32 = field(4)?
+            .parse()
+            .ok()
+            .filter(|volume| *volume > 0)
+            .ok_or_else(|| error(4, "must be a positive number of millions"))?;
+        trades.push(Trade {
+            currency_pair: String::from(currency_pair),
+            liquidity_provider: String::from(field(1)?),
+            side,
+            price,
+            volume,
+        });
+    }
+    Ok(trades)
+}
@@ -0,0 +1,171 @@
+//! # FX Simulator and Aggregator - fx_sim_agg
+//!
+//! `price.rs` defines the fixed-point `Price` type used for every FX price in the simulator,
+//! the aggregated book, the GUI and the FIX log. Prices are held as an integer number of ticks
+//! with a per currency pair precision so that comparing, merging and spreading prices is exact.
+use crate::AppError;
+use std::cmp::Ordering;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+
+/// Number of ticks in one pip. Pairs are quoted to a tenth of a pip (a pipette), so 5 decimal
+/// places for most pairs (pip = 0.0001) and 3 decimal places for JPY pairs (pip = 0.01)
+pub const PIP_TICKS: i64 = 10;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Price {
+    ticks: i64,
+    decimals: u32,
+}
+
+impl Price {
+    pub fn new(ticks: i64, decimals: u32) -> Self {
+        Price { ticks, decimals }
+    }
+
+    pub fn from_f64(value: f64, decimals: u32) -> Result<Self, AppError> {
+        // round to the nearest tick so that binary floating point noise never leaks into the book,
+        // rejecting NaN, infinity and prices too large to hold rather than saturating them
+        let ticks = (value * 10_f64.powi(decimals as i32)).round();
+        if !ticks.is_finite() || ticks.abs() >= i64::MAX as f64 {
+            return Err(AppError::InvalidPrice(value.to_string()));
+        }
+        Ok(Price {
+            ticks: ticks as i64,
+            decimals,
+        })
+    }
+
+    pub fn parse(value: &str, decimals: u32) -> Result<Self, AppError> {
+        // parse a decimal string exactly, without going through f64
+        let value = value.trim();
+        let invalid = || AppError::InvalidPrice(String::from(value));
+        let (negative, digits) = match value.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, value),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if (whole.is_empty() && fraction.is_empty())
+            || fraction.len() > decimals as usize
+            || !whole
+                .chars()
+                .chain(fraction.chars())
+                .all(|c| c.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+        let whole: i64 = if whole.is_empty() { 0 } else { whole.parse()? };
+        let mut fraction_ticks: i64 = if fraction.is_empty() {
+            0
+        } else {
+            fraction.parse()?
+        };
+        // pad the fractional part out to the full precision e.g. "5552" -> 55520 for 5 decimals
+        fraction_ticks *= 10_i64.pow(decimals - fraction.len() as u32);
+        let ticks = whole
+            .checked_mul(10_i64.pow(decimals))
+            .and_then(|t| t.checked_add(fraction_ticks))
+            .ok_or_else(invalid)?;
+        Ok(Price {
+            ticks: if negative { -ticks } else { ticks },
+            decimals,
+        })
+    }
+
+    pub fn ticks(self) -> i64 {
+        self.ticks
+    }
+
+    pub fn decimals(self) -> u32 {
+        self.decimals
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.ticks as f64 / 10_f64.powi(self.decimals as i32)
+    }
+
+    pub fn add_ticks(self, ticks: i64) -> Self {
+        // saturate rather than overflow, so an out of range markup or price move can't panic
+        Price {
+            ticks: self.ticks.saturating_add(ticks),
+            decimals: self.decimals,
+        }
+    }
+
+    pub fn add_pips(self, pips: f64) -> Self {
+        self.add_ticks(pips_to_ticks(pips))
+    }
+
+    pub fn diff_ticks(self, other: Price) -> i64 {
+        // number of ticks self is above other, in the finer of the two precisions
+        let (a, b) = normalise(&self, &other);
+        (a - b) as i64
+    }
+}
+
+/// Precision used to quote a currency pair: JPY pairs are quoted to 3 decimal places and all
+/// other pairs to 5 decimal places
+pub fn price_decimals(currency_pair: &str) -> u32 {
+    if currency_pair.to_uppercase().contains("JPY") {
+        3
+    } else {
+        5
+    }
+}
+
+pub fn pips_to_ticks(pips: f64) -> i64 {
+    (pips * PIP_TICKS as f64).round() as i64
+}
+
+fn normalise(a: &Price, b: &Price) -> (i128, i128) {
+    // bring both prices to the same precision so prices quoted to different decimals compare exactly
+    let decimals = a.decimals.max(b.decimals);
+    (
+        a.ticks as i128 * 10_i128.pow(decimals - a.decimals),
+        b.ticks as i128 * 10_i128.pow(decimals - b.decimals),
+    )
+}
+
+impl PartialEq for Price {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Price {}
+
+impl PartialOrd for Price {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Price {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // prices of the same currency pair share their precision, so compare them directly
+        if self.decimals == other.decimals {
+            return self.ticks.cmp(&other.ticks);
+        }
+        let (a, b) = normalise(self, other);
+        a.cmp(&b)
+    }
+}
+
+impl Display for Price {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let sign = if self.ticks < 0 { "-" } else { "" };
+        let ticks = self.ticks.unsigned_abs();
+        if self.decimals == 0 {
+            return write!(f, "{sign}{ticks}");
+        }
+        let scale = 10_u64.pow(self.decimals);
+        write!(
+            f,
+            "{sign}{}.{:0width$}",
+            ticks / scale,
+            ticks % scale,
+            width = self.decimals as usize
+        )
+    }
+}
@@ -0,0 +1,194 @@
+//! # FX Simulator and Aggregator - fx_sim_agg
+//!
+//! `price_history.rs` keeps a bounded history of the top of an aggregated book - its best bid and best offer - and of
+//! each liquidity provider's best quotes, sampled at every change that can move them, so the GUI can chart the prices over time.
+use crate::price::Price;
+use crate::quote::QuoteUpdate;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+// the history covers the longest time window charted, and holds no more samples than this for
+// the top of the book or any liquidity provider
+pub const HISTORY_DURATION: Duration = Duration::from_secs(15 * 60);
+pub const MAX_SAMPLES: usize = 16_384;
+// samples are kept in shared chunks, so cloning the history for a book snapshot only copies the
+// chunk still being filled
+const CHUNK_SAMPLES: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PriceSample {
+    // source time of the change in nanoseconds
+    pub time: u64,
+    pub bid: Option<Price>,
+    pub offer: Option<Price>,
+}
+
+impl PriceSample {
+    pub fn mid(&self) -> Option<f64> {
+        Some((self.bid?.to_f64() + self.offer?.to_f64()) / 2.0)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PriceSeries {
+    chunks: VecDeque<Arc<Vec<PriceSample>>>,
+}
+
+impl PriceSeries {
+    pub fn len(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    pub fn last(&self) -> Option<&PriceSample> {
+        self.chunks.back()?.last()
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &PriceSample> {
+        self.chunks.iter().flat_map(|chunk| chunk.iter())
+    }
+
+    pub fn steps(
+        &self,
+        from: u64,
+        to: u64,
+        price: impl Fn(&PriceSample) -> Option<f64>,
+    ) -> Vec<Vec<[f64; 2]>> {
+        // the price between from and to as lines of [seconds before to, price] points stepping
+        // at each change, broken wherever the price is missing e.g. while the book is one sided
+        let seconds = |time: u64| -((to - time.clamp(from, to)) as f64) / 1e9;
+        let at_from = self
+            .iter()
+            .take_while(|sample| sample.time <= from)
+            .last()
+            .and_then(&price);
+        let changes = self
+            .iter()
+            .skip_while(|sample| sample.time <= from)
+            .take_while(|sample| sample.time <= to)
+            .map(|sample| (sample.time, price(sample)));
+        let mut lines: Vec<Vec<[f64; 2]>> = Vec::new();
+        let mut last: Option<f64> = None;
+        for (time, value) in [(from, at_from)].into_iter().chain(changes) {
+            if let (Some(last), Some(line)) = (last, lines.last_mut()) {
+                line.push([seconds(time), last]);
+            }
+            if let Some(value) = value {
+                if last.is_none() {
+                    lines.push(Vec::new());
+                }
+                if let Some(line) = lines.last_mut() {
+                    line.push([seconds(time), value]);
+                }
+            }
+            last = value;
+        }
+        if let (Some(last), Some(line)) = (last, lines.last_mut()) {
+            line.push([0.0, last]);
+        }
+        lines
+    }
+
+    fn push(&mut self, sample: PriceSample) {
+        // only changes of price are kept
+        if self
+            .last()
+            .is_some_and(|last| last.bid == sample.bid && last.offer == sample.offer)
+        {
+            return;
+        }
+        match self.chunks.back_mut() {
+            Some(chunk) if chunk.len() < CHUNK_SAMPLES => Arc::make_mut(chunk).push(sample),
+            _ => {
+                let mut chunk = Vec::with_capacity(CHUNK_SAMPLES);
+                chunk.push(sample);
+                self.chunks.push_back(Arc::new(chunk));
+            }
+        }
+        while self.chunks.len() > MAX_SAMPLES / CHUNK_SAMPLES {
+            self.chunks.pop_front();
+        }
+    }
+
+    fn prune(&mut self, cutoff: u64) {
+        // drop the oldest chunks while the next still starts before the cutoff, keeping the
+        // price in effect at the cutoff
+        while self
+            .chunks
+            .get(1)
+            .is_some_and(|chunk| chunk[0].time <= cutoff)
+        {
+            self.chunks.pop_front();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PriceHistory {
+    pub top_of_book: PriceSeries,
+    // best bid and offer of each liquidity provider's quote updates
+    pub lp_quotes: BTreeMap<String, PriceSeries>,
+    // source time of the latest sample
+    pub latest: u64,
+}
+
+impl PriceHistory {
+    pub fn record(&mut self, quote: &QuoteUpdate, bid: Option<Price>, offer: Option<Price>) {
+        // record the liquidity provider's best quotes and the top of the book after the quote
+        // update
+        let lp_sample = PriceSample {
+            time: quote.source_time,
+            bid: quote.bids.iter().map(|tier| tier.price).max(),
+            offer: quote.offers.iter().map(|tier| tier.price).min(),
+        };
+        self.push_lp(&quote.liquidity_provider, lp_sample);
+        self.record_top(quote.source_time, bid, offer);
+    }
+
+    pub fn record_removed(&mut self, liquidity_provider: &str, time: u64) {
+        // record that the liquidity provider's quotes have been removed from the book, breaking
+        // its line until it quotes again, the top of the book is recorded after all the removals
+        self.push_lp(
+            liquidity_provider,
+            PriceSample {
+                time,
+                bid: None,
+                offer: None,
+            },
+        );
+    }
+
+    pub fn record_top(&mut self, time: u64, bid: Option<Price>, offer: Option<Price>) {
+        // record the top of the book after any change, dropping whatever has fallen out of the
+        // history duration
+        self.latest = self.latest.max(time);
+        self.top_of_book.push(PriceSample { time, bid, offer });
+        let cutoff = self
+            .latest
+            .saturating_sub(HISTORY_DURATION.as_nanos() as u64);
+        self.top_of_book.prune(cutoff);
+        for lp_quotes in self.lp_quotes.values_mut() {
+            lp_quotes.prune(cutoff);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.top_of_book.is_empty()
+    }
+
+    fn push_lp(&mut self, liquidity_provider: &str, sample: PriceSample) {
+        match self.lp_quotes.get_mut(liquidity_provider) {
+            Some(lp_quotes) => lp_quotes.push(sample),
+            None => {
+                let mut lp_quotes = PriceSeries::default();
+                lp_quotes.push(sample);
+                self.lp_quotes
+                    .insert(String::from(liquidity_provider), lp_quotes);
+            }
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! # FX Simulator and Aggregator - fx_sim_agg
+//!
+//! `price_model.rs` defines the `PriceModel` trait used by the simulator to move each liquidity provider's
+//! 1M buy price on between quotes, and the price processes that can be selected per liquidity provider.
+use crate::AppError;
+use crate::price::{PIP_TICKS, Price};
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+use std::fmt::Debug;
+
+pub trait PriceModel: Debug + Send {
+    // returns the next price given the current price and the time in seconds since that price
+    fn next_price(&mut self, price: Price, dt: f64, rng: &mut ChaCha8Rng) -> Price;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PriceModelConfig {
+    // prices only trend up by 0 to max_pips pips each quote
+    BullMarket {
+        max_pips: f64,
+    },
+    // symmetric random walk with a volatility of sigma_pips pips per root second
+    RandomWalk {
+        sigma_pips: f64,
+    },
+    // geometric Brownian motion with drift mu and volatility sigma per second
+    Gbm {
+        mu: f64,
+        sigma: f64,
+    },
+    // Ornstein-Uhlenbeck mean reversion towards the reference rate at speed theta per second.
+    // The reference rate defaults to the liquidity provider's starting price
+    MeanReversion {
+        theta: f64,
+        sigma_pips: f64,
+        mean: Option<f64>,
+    },
+    // random walk with jumps arriving at jump_intensity per second with a jump_pips pip standard deviation
+    JumpDiffusion {
+        sigma_pips: f64,
+        jump_intensity: f64,
+        jump_pips: f64,
+    },
+}
+
+impl Default for PriceModelConfig {
+    fn default() -> Self {
+        PriceModelConfig::BullMarket { max_pips: 2.0 }
+    }
+}
+
+impl PriceModelConfig {
+    pub fn parse(spec: &str) -> Result<Self, AppError> {
+        // a model name optionally followed by space separated parameters
+        // e.g. "mean_reversion theta=0.05 sigma_pips=0.8 mean=1.5555"
+        let mut words = spec.split_whitespace();
+        let name = words.next().unwrap_or("bull");
+        let mut params: Vec<(&str, f64)> = Vec::new();
+        for word in words {
+            let (key, value) = word.split_once('=').ok_or_else(|| {
+                AppError::InvalidConfig(format!("price model parameter {word} must be key=value"))
+            })?;
+            params.push((key, value.parse()?));
+        }
+        let param = |key: &str, default: f64| -> f64 {
+            params
+                .iter()
+                .find(|param| param.0 == key)
+                .map_or(default, |param| param.1)
+        };
+
+        let (model, keys): (PriceModelConfig, &[&str]) = match name {
+            "bull" => (
+                PriceModelConfig::BullMarket {
+                    max_pips: param("max_pips", 2.0),
+                },
+                &["max_pips"],
+            ),
+            "random_walk" => (
+                PriceModelConfig::RandomWalk {
+                    sigma_pips: param("sigma_pips", 0.8),
+                },
+                &["sigma_pips"],
+            ),
+            "gbm" => (
+                PriceModelConfig::Gbm {
+                    mu: param("mu", 0.0),
+                    sigma: param("sigma", 0.00005),
+                },
+                &["mu", "sigma"],
+            ),
+            "mean_reversion" => (
+                PriceModelConfig::MeanReversion {
+                    theta: param("theta", 0.05),
+                    sigma_pips: param("sigma_pips", 0.8),
+                    mean: params
+                        .iter()
+                        .find(|param| param.0 == "mean")
+                        .map(|param| param.1),
+                },
+                &["theta", "sigma_pips", "mean"],
+            ),
+            "jump_diffusion" => (
+                PriceModelConfig::JumpDiffusion {
+                    sigma_pips: param("sigma_pips", 0.5),
+                    jump_intensity: param("jump_intensity", 0.02),
+                    jump_pips: param("jump_pips", 5.0),
+                },
+                &["sigma_pips", "jump_intensity", "jump_pips"],
+            ),
+            _ => {
+                return Err(AppError::InvalidConfig(format!(
+                    "unknown price model {name}, expected bull, random_walk, gbm, mean_reversion or jump_diffusion"
+                )));
+            }
+        };
+        if let Some(param) = params.iter().find(|param| !keys.contains(&param.0)) {
+            return Err(AppError::InvalidConfig(format!(
+                "unknown {name} price model parameter {}",
+                param.0
+            )));
+        }
+        // a bull market needs a positive range to draw from, mu may drift either way and every
+        // other parameter is a rate, volatility or price that can't be negative
+        for (key, value) in &params {
+            let (valid, expected) = match *key {
+                "mu" => (value.is_finite(), "a finite number"),
+                "max_pips" | "mean" => (value.is_finite() && *value > 0.0, "above 0"),
+                _ => (value.is_finite() && *value >= 0.0, "0 or above"),
+            };
+            if !valid {
+                return Err(AppError::InvalidConfig(format!(
+                    "{name} price model parameter {key} must be {expected}, not {value}"
+                )));
+            }
+        }
+        Ok(model)
+    }
+
+    pub fn create(&self, start_price: Price) -> Box<dyn PriceModel> {
+        match *self {
+            PriceModelConfig::BullMarket { max_pips } => Box::new(BullMarket { max_pips }),
+            PriceModelConfig::RandomWalk { sigma_pips } => Box::new(RandomWalk { sigma_pips }),
+            PriceModelConfig::Gbm { mu, sigma } => Box::new(Gbm { mu, sigma }),
+            PriceModelConfig::MeanReversion {
+                theta,
+                sigma_pips,
+                mean,
+            } => Box::new(MeanReversion {
+                theta,
+                sigma_pips,
+                mean: mean.unwrap_or(start_price.to_f64()),
+            }),
+            PriceModelConfig::JumpDiffusion {
+                sigma_pips,
+                jump_intensity,
+                jump_pips,
+            } => Box::new(JumpDiffusion {
+                sigma_pips,
+                jump_intensity,
+                jump_pips,
+            }),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BullMarket {
+    pub max_pips: f64,
+}
+
+impl PriceModel for BullMarket {
+    fn next_price(&mut self, price: Price, _dt: f64, rng: &mut ChaCha8Rng) -> Price {
+        // let's say it is a bull market and prices are trending up
+        let pip_change: f64 = rng.random_range(0.0..self.max_pips);
+        price.add_pips(pip_change)
+    }
+}
+
+#[derive(Debug)]
+pub struct RandomWalk {
+    pub sigma_pips: f64,
+}
+
+impl PriceModel for RandomWalk {
+    fn next_price(&mut self, price: Price, dt: f64, rng: &mut ChaCha8Rng) -> Price {
+        let pip_change = self.sigma_pips * dt.sqrt() * standard_normal(rng);
+        floor_price(price.add_pips(pip_change))
+    }
+}
+
+#[derive(Debug)]
+pub struct Gbm {
+    pub mu: f64,
+    pub sigma: f64,
+}
+
+impl PriceModel for Gbm {
+    fn next_price(&mut self, price: Price, dt: f64, rng: &mut ChaCha8Rng) -> Price {
+        let exponent = (self.mu - self.sigma * self.sigma / 2.0) * dt
+            + self.sigma * dt.sqrt() * standard_normal(rng);
+        // a move too large to price leaves the price where it is
+        floor_price(
+            Price::from_f64(price.to_f64() * exponent.exp(), price.decimals()).unwrap_or(price),
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct MeanReversion {
+    pub theta: f64,
+    pub sigma_pips: f64,
+    pub mean: f64,
+}
+
+impl PriceModel for MeanReversion {
+    fn next_price(&mut self, price: Price, dt: f64, rng: &mut ChaCha8Rng) -> Price {
+        // pull the price back towards the mean, plus random noise
+        let pip_size = pip_size(price);
+        let reversion_pips = self.theta * (self.mean - price.to_f64()) * dt / pip_size;
+        let noise_pips = self.sigma_pips * dt.sqrt() * standard_normal(rng);
+        floor_price(price.add_pips(reversion_pips + noise_pips))
+    }
+}
+
+#[derive(Debug)]
+pub struct JumpDiffusion {
+    pub sigma_pips: f64,
+    pub jump_intensity: f64,
+    pub jump_pips: f64,
+}
+
+impl PriceModel for JumpDiffusion {
+    fn next_price(&mut self, price: Price, dt: f64, rng: &mut ChaCha8Rng) -> Price {
+        let mut pip_change = self.sigma_pips * dt.sqrt() * standard_normal(rng);
+        // a jump arrives in this interval with probability intensity * dt
+        if rng.random_bool((self.jump_intensity * dt).clamp(0.0, 1.0)) {
+            pip_change += self.jump_pips * standard_normal(rng);
+        }
+        floor_price(price.add_pips(pip_change))
+    }
+}
+
+pub fn standard_normal(rng: &mut ChaCha8Rng) -> f64 {
+    // Box-Muller transform of two uniform random numbers
+    let u1: f64 = 1.0 - rng.random::<f64>();
+    let u2: f64 = rng.random::<f64>();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+fn pip_size(price: Price) -> f64 {
+    Price::new(PIP_TICKS, price.decimals()).to_f64()
+}
+
+fn floor_price(price: Price) -> Price {
+    // prices can never fall to zero or below
+    if price.ticks() < 1 {
+        Price::new(1, price.decimals())
+    } else {
+        price
+    }
+}
@@ -0,0 +1,40 @@
+//! # FX Simulator and Aggregator - fx_sim_agg
+//!
+//! `quote.rs` defines `QuoteUpdate`, the typed quotes of a single liquidity provider for a single currency pair
+//! that every market data source sends to the aggregator. Quotes are only encoded as FIX messages at the edges -
+//! when logging them to the FIX log and when reading a FIX log back.
+use crate::price::Price;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuoteTier {
+    pub price: Price,
+    // volume in millions of the base currency
+    pub volume: i32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuoteUpdate {
+    pub liquidity_provider: String,
+    pub currency_pair: String,
+    // every tier the liquidity provider currently quotes, replacing its previous quotes. A tier that is
+    // no longer included has been withdrawn
+    pub bids: Vec<QuoteTier>,
+    pub offers: Vec<QuoteTier>,
+    // time the source sent the quotes as nanoseconds since the unix epoch
+    pub source_time: u64,
+    // increases by one with each update from the liquidity provider
+    pub sequence_number: u64,
+}
+
+impl QuoteUpdate {
+    pub fn tiers(&self) -> impl Iterator<Item = (&'static str, &QuoteTier)> {
+        // bid and offer tiers with the side of the book they belong on, bids first
+        let bids = self.bids.iter().map(|tier| ("Buy", tier));
+        let offers = self.offers.iter().map(|tier| ("Sell", tier));
+        bids.chain(offers)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bids.is_empty() && self.offers.is_empty()
+    }
+}
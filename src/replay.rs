@@ -0,0 +1,131 @@
+//! # FX Simulator and Aggregator - fx_sim_agg
+//!
+//! `replay.rs` reads back a FIX log recorded by a previous run and streams its market data messages to the
+//! aggregator as quote updates, paced by their original SendingTime (52) in real time, accelerated, or as fast
+//! as possible.
+use crate::AppError;
+use crate::fix::QuoteDecoder;
+use crate::quote::QuoteUpdate;
+use crate::source::MarketDataSource;
+use futures::stream::{self, BoxStream, StreamExt};
+use log::{error, info};
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    // original gaps between messages divided by the multiplier, 1.0 is real time
+    Multiplier(f64),
+    AsFastAsPossible,
+}
+
+impl ReplaySpeed {
+    pub fn parse(speed: &str) -> Result<Self, AppError> {
+        // "realtime", "max" or a multiplier such as "10x" or "0.5"
+        let invalid = || {
+            AppError::InvalidConfig(format!(
+                "replay speed {speed} must be realtime, max or a multiplier such as 10x"
+            ))
+        };
+        match speed.trim() {
+            "realtime" => Ok(ReplaySpeed::Multiplier(1.0)),
+            "max" => Ok(ReplaySpeed::AsFastAsPossible),
+            multiplier => {
+                let multiplier: f64 = multiplier
+                    .strip_suffix('x')
+                    .unwrap_or(multiplier)
+                    .parse()
+                    .map_err(|_| invalid())?;
+                if multiplier > 0.0 && multiplier.is_finite() {
+                    Ok(ReplaySpeed::Multiplier(multiplier))
+                } else {
+                    Err(invalid())
+                }
+            }
+        }
+    }
+
+    pub fn delay(self, previous_time: u64, sending_time: u64) -> Duration {
+        // pause before replaying a message sent at sending_time after one sent at previous_time
+        match self {
+            ReplaySpeed::AsFastAsPossible => Duration::ZERO,
+            ReplaySpeed::Multiplier(multiplier) => {
+                Duration::from_nanos(sending_time.saturating_sub(previous_time)).div_f64(multiplier)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Replay {
+    pub file: String,
+    pub speed: ReplaySpeed,
+    // recorded FIX messages, one per line of the log
+    pub messages: Arc<[String]>,
+}
+
+impl Replay {
+    pub fn load(file: &str, speed: ReplaySpeed) -> Result<Self, AppError> {
+        // read the whole log up front so the FIX log of this run can never overwrite it
+        let contents = fs::read_to_string(file)?;
+        let messages: Arc<[String]> = contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(String::from)
+            .collect();
+        Ok(Replay {
+            file: String::from(file),
+            speed,
+            messages,
+        })
+    }
+}
+
+impl MarketDataSource for Replay {
+    fn name(&self) -> &str {
+        &self.file
+    }
+
+    fn into_stream(self: Box<Self>) -> BoxStream<'static, QuoteUpdate> {
+        replay_stream(&self)
+    }
+}
+
+pub fn replay_stream(replay: &Replay) -> BoxStream<'static, QuoteUpdate> {
+    // messages that aren't valid FIX market data are logged and skipped. Incremental refreshes are
+    // applied to the entries of earlier messages to give each liquidity provider's current quotes
+    info!(
+        "replaying {} market data messages from {} at {:?}",
+        replay.messages.len(),
+        replay.file,
+        replay.speed
+    );
+    let speed = replay.speed;
+    let messages = Arc::clone(&replay.messages);
+    let mut previous_time: Option<u64> = None;
+    let mut decoder = QuoteDecoder::default();
+    stream::iter(0..messages.len())
+        .filter_map(move |index| {
+            let quote = decoder.decode(&messages[index]);
+            if let Err(e) = &quote {
+                error!("replay message {} not processed - {e}", index + 1);
+            }
+            async move { quote.ok() }
+        })
+        .then(move |quote| {
+            let sending_time = quote.source_time;
+            let pause = previous_time.map_or(Duration::ZERO, |previous| {
+                speed.delay(previous, sending_time)
+            });
+            previous_time = Some(sending_time);
+            async move {
+                if !pause.is_zero() {
+                    sleep(pause).await;
+                }
+                quote
+            }
+        })
+        .boxed()
+}
@@ -0,0 +1,260 @@
+//! # FX Simulator and Aggregator - fx_sim_agg
+//!
+//! `risk.rs` checks orders against pre-trade risk limits before they are executed against the aggregated book: a
+//! maximum order size, a maximum net open position per currency pair, a fat finger price band around the top of the
+//! book and a credit line with each liquidity provider that is used up by its fills. Orders breaching a limit are
+//! rejected, or capped where part of the order can still be executed within the limits.
+use crate::AppError;
+use crate::aggregator::{BookManager, FxBook};
+use crate::order::{ExecutionReport, Order, OrderType, Side, TimeInForce};
+use crate::position::PositionKeeper;
+use crate::price::{PIP_TICKS, Price};
+use log::warn;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RiskLimits {
+    // largest order volume in millions, None for no limit
+    pub max_order_volume: Option<i32>,
+    // largest net position in millions of any currency pair, long or short, None for no limit
+    pub max_net_position: Option<i64>,
+    // ticks an order may be priced through the top of the book, None for no limit
+    pub price_band: Option<i64>,
+    // millions that may be traded with each liquidity provider, those without a credit line
+    // aren't limited
+    pub credit_lines: BTreeMap<String, i64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RiskBreach {
+    // the order is larger than the maximum order volume
+    OrderSize {
+        volume: i32,
+        limit: i32,
+    },
+    // the order would take the net position beyond the maximum net position, with no room left
+    // or as a fill or kill order
+    NetPosition {
+        currency_pair: String,
+        net_volume: i64,
+        limit: i64,
+        // volume the order could add before the position reaches the limit
+        room: i64,
+        volume: i32,
+    },
+    // the order's limit price is further through the top of the book than the price band
+    PriceBand {
+        currency_pair: String,
+        price: Price,
+        top: Price,
+        price_band: i64,
+    },
+    // every liquidity provider quoting the side the order fills against has used its credit line
+    Credit {
+        currency_pair: String,
+        liquidity_providers: Vec<String>,
+    },
+}
+
+impl Display for RiskBreach {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RiskBreach::OrderSize { volume, limit } => {
+                write!(f, "order volume {volume}M is over the {limit}M limit")
+            }
+            RiskBreach::NetPosition {
+                currency_pair,
+                net_volume,
+                limit,
+                room,
+                volume,
+            } => write!(
+                f,
+                "{currency_pair} order for {volume}M is over the {room}M of room left by a net position of {net_volume}M under the {limit}M limit"
+            ),
+            RiskBreach::PriceBand {
+                currency_pair,
+                price,
+                top,
+                price_band,
+            } => write!(
+                f,
+                "{currency_pair} price {price} is more than {} pips through the top of the book at {top}",
+                *price_band as f64 / PIP_TICKS as f64
+            ),
+            RiskBreach::Credit {
+                currency_pair,
+                liquidity_providers,
+            } => write!(
+                f,
+                "no credit left with the liquidity providers quoting {currency_pair} - {}",
+                liquidity_providers.join(", ")
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RiskManager {
+    pub limits: RiskLimits,
+    // millions traded with each liquidity provider against its credit line
+    credit_used: BTreeMap<String, i64>,
+}
+
+impl RiskManager {
+    pub fn new(limits: RiskLimits) -> Self {
+        RiskManager {
+            limits,
+            credit_used: BTreeMap::new(),
+        }
+    }
+
+    pub fn check_order(
+        &self,
+        order: &Order,
+        fx_book: &FxBook,
+        position_keeper: &PositionKeeper,
+    ) -> Result<Order, AppError> {
+        // return the order if it is within the limits, capped to the volume or price it may be
+        // executed at, or the breach it is rejected for
+        let mut checked_order = order.clone();
+        if let Some(limit) = self.limits.max_order_volume
+            && order.volume > limit
+        {
+            return Err(breach(RiskBreach::OrderSize {
+                volume: order.volume,
+                limit,
+            }));
+        }
+
+        // only orders adding to the net position are limited, to the volume that takes the
+        // position to the limit
+        if let Some(limit) = self.limits.max_net_position {
+            let net_volume = position_keeper
+                .position(&order.currency_pair)
+                .map_or(0, |position| position.net_volume);
+            let room = match order.side {
+                Side::Buy => limit - net_volume,
+                Side::Sell => limit + net_volume,
+            };
+            if room < order.volume as i64 {
+                if room <= 0 || order.time_in_force == TimeInForce::FillOrKill {
+                    return Err(breach(RiskBreach::NetPosition {
+                        currency_pair: order.currency_pair.clone(),
+                        net_volume,
+                        limit,
+                        room: room.max(0),
+                        volume: order.volume,
+                    }));
+                }
+                checked_order.volume = room as i32;
+                warn!(
+                    "{} order capped from {}M to {}M by the {limit}M net position limit",
+                    order.currency_pair, order.volume, checked_order.volume
+                );
+            }
+        }
+
+        // limit orders priced too far through the top of the book are rejected and market orders
+        // become limit orders at the edge of the band
+        let top = fx_book
+            .fill_side(order.side)
+            .first()
+            .map(|entry| entry.price);
+        if let (Some(price_band), Some(top)) = (self.limits.price_band, top) {
+            let band_edge = match order.side {
+                Side::Buy => top.add_ticks(price_band),
+                Side::Sell => top.add_ticks(-price_band),
+            };
+            match order.order_type {
+                OrderType::Limit(price)
+                    if (order.side == Side::Buy && price > band_edge)
+                        || (order.side == Side::Sell && price < band_edge) =>
+                {
+                    return Err(breach(RiskBreach::PriceBand {
+                        currency_pair: order.currency_pair.clone(),
+                        price,
+                        top,
+                        price_band,
+                    }));
+                }
+                OrderType::Limit(_) => {}
+                OrderType::Market => checked_order.order_type = OrderType::Limit(band_edge),
+            }
+        }
+
+        // at least one liquidity provider quoting the side must have credit left
+        let mut liquidity_providers: Vec<String> = fx_book
+            .fill_side(order.side)
+            .iter()
+            .flat_map(|entry| entry.lp_vol.iter().map(|lp_vol| lp_vol.0.clone()))
+            .collect();
+        liquidity_providers.sort();
+        liquidity_providers.dedup();
+        if !liquidity_providers.is_empty()
+            && liquidity_providers
+                .iter()
+                .all(|liquidity_provider| self.available_credit(liquidity_provider) == Some(0))
+        {
+            return Err(breach(RiskBreach::Credit {
+                currency_pair: order.currency_pair.clone(),
+                liquidity_providers,
+            }));
+        }
+        Ok(checked_order)
+    }
+
+    pub fn available_credit(&self, liquidity_provider: &str) -> Option<i64> {
+        // millions that can still be traded with the liquidity provider, None without a credit line
+        let credit_line = self.limits.credit_lines.get(liquidity_provider)?;
+        let credit_used = self.credit_used.get(liquidity_provider).unwrap_or(&0);
+        Some((credit_line - credit_used).max(0))
+    }
+
+    pub fn use_credit(&mut self, execution_report: &ExecutionReport) {
+        // every fill uses up its volume of the liquidity provider's credit line
+        for fill in &execution_report.fills {
+            *self
+                .credit_used
+                .entry(fill.liquidity_provider.clone())
+                .or_default() += fill.volume as i64;
+        }
+    }
+
+    pub fn execute(
+        &mut self,
+        book_manager: &mut BookManager,
+        position_keeper: &PositionKeeper,
+        order: &Order,
+    ) -> Result<ExecutionReport, AppError> {
+        // check the order against the limits and execute what passes, filling no more from each
+        // liquidity provider than its credit left
+        let fx_book = book_manager.book(&order.currency_pair).ok_or_else(|| {
+            AppError::InvalidOrder(format!("no book for {}", order.currency_pair))
+        })?;
+        let checked_order = self.check_order(order, fx_book, position_keeper)?;
+        let lp_limits: BTreeMap<String, i32> = self
+            .limits
+            .credit_lines
+            .keys()
+            .filter_map(|liquidity_provider| {
+                let available_credit = self.available_credit(liquidity_provider)?;
+                Some((
+                    liquidity_provider.clone(),
+                    available_credit.min(i32::MAX as i64) as i32,
+                ))
+            })
+            .collect();
+        let execution_report = book_manager.execute_within(&checked_order, &lp_limits)?;
+        self.use_credit(&execution_report);
+        Ok(execution_report)
+    }
+}
+
+fn breach(risk_breach: RiskBreach) -> AppError {
+    warn!("order rejected - {risk_breach}");
+    AppError::RiskLimit(risk_breach)
+}
@@ -1,154 +1,423 @@
-use core::f64;
 //use log::{debug, error, info, trace, warn};
 use log::{error, info};
-use std::fs;
-use std::path::Path;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::{spawn, sync::mpsc::unbounded_channel, time::sleep};
-use tokio_stream::{Stream, StreamMap, wrappers::UnboundedReceiverStream};
+use tokio_stream::{Stream, wrappers::UnboundedReceiverStream};
 
-use crate::{AppError, get_str_field};
+use futures::stream::{self, BoxStream, StreamExt};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 
-#[derive(Debug)]
+use crate::AppError;
+use crate::config;
+use crate::last_look::LastLookConfig;
+use crate::price::{Price, pips_to_ticks};
+use crate::price_model::{PriceModel, PriceModelConfig};
+use crate::quote::{QuoteTier, QuoteUpdate};
+use crate::source::MarketDataSource;
+
+#[derive(Debug, Clone)]
 pub struct Config {
     pub liquidity_provider: String,
     pub currency_pair: String,
-    pub buy_price: f64,
-    pub spread: f64,
-    pub three_mill_markup: f64,
-    pub five_mill_markup: f64,
+    pub buy_price: Price,
+    // spread and markups are held in ticks (tenths of a pip) of the currency pair's price
+    pub spread: i64,
+    // volumes quoted by this liquidity provider, in the order they are sent
+    pub tiers: Vec<Tier>,
     pub run_iterations: i32,
+    // send MarketDataIncrementalRefresh (35=X) messages after the first snapshot instead of
+    // a MarketDataSnapshotFullRefresh (35=W) message every time
+    pub incremental: bool,
+    // seed for this liquidity provider's random number generator, otherwise derived from the
+    // global seed
+    pub seed: Option<u64>,
+    // price process used to move the 1M buy price on between quotes
+    pub price_model: PriceModelConfig,
+    // quotes not refreshed within this time are removed from the book and the liquidity provider
+    // flagged as stale, None to keep quotes until they are replaced
+    pub quote_ttl: Option<Duration>,
+    // how the liquidity provider responds to trade requests against its quotes
+    pub last_look: LastLookConfig,
 }
 
-pub fn get_configs(configs: &mut Vec<Config>) -> Result<(), AppError> {
-    let parameters = read_config_file("resources/config.txt")?;
-    let mut index = 0;
-    for i in &parameters {
-        // ignore header line in config file
-        if index > 0 {
-            let mut fx_params = i.split(",");
-            // let mut fx_params = fx_sim_agg_gui::get_params(i, 7)?;
-            let liquidity_provider = get_str_field(fx_params.next())?;
-            let currency_pair = get_str_field(fx_params.next())?;
-            let buy_price: f64 = fx_params.next().unwrap_or("").trim().parse()?;
-            let mut spread: f64 = fx_params.next().unwrap_or("").trim().parse()?;
-            spread = spread / 10000.0;
-            let mut three_mill_markup: f64 = fx_params.next().unwrap_or("").trim().parse()?;
-            three_mill_markup = three_mill_markup / 10000.0;
-            let mut five_mill_markup: f64 = fx_params.next().unwrap_or("").trim().parse()?;
-            five_mill_markup = five_mill_markup / 10000.0;
-            let run_iterations: i32 = fx_params.next().unwrap_or("").trim().parse()?;
-
-            let config = Config {
-                liquidity_provider: String::from(liquidity_provider),
-                currency_pair: String::from(currency_pair),
-                buy_price,
-                spread,
-                three_mill_markup,
-                five_mill_markup,
-                run_iterations,
-            };
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tier {
+    // volume in millions
+    pub volume: i32,
+    // ticks subtracted from the buy price and added to the sell price for this volume. Markups
+    // always widen the price away from the 1M buy and sell prices, whichever config format they
+    // come from
+    pub bid_markup: i64,
+    pub offer_markup: i64,
+}
+
+// chance of a liquidity provider withdrawing its largest quotes when sending incremental updates
+const QUOTE_WITHDRAW_PROBABILITY: f64 = 0.1;
+// virtual clock start time (2025-01-01 00:00:00 UTC in nanoseconds) used for seeded runs
+pub const VIRTUAL_CLOCK_START: u64 = 1_735_689_600_000_000_000;
+// seeds are kept to the range of a TOML integer, so any seed logged can be written to the config
+// file as well as given with --seed
+pub const MAX_SEED: u64 = i64::MAX as u64;
+
+pub fn legacy_tiers(three_mill_markup: f64, five_mill_markup: f64) -> Vec<Tier> {
+    // the 3M and 5M markup columns of a CSV config file, widening the 3M and 5M prices like any
+    // other tier markup
+    vec![
+        Tier {
+            volume: 1,
+            bid_markup: 0,
+            offer_markup: 0,
+        },
+        Tier {
+            volume: 3,
+            bid_markup: pips_to_ticks(three_mill_markup),
+            offer_markup: pips_to_ticks(three_mill_markup),
+        },
+        Tier {
+            volume: 5,
+            bid_markup: pips_to_ticks(five_mill_markup),
+            offer_markup: pips_to_ticks(five_mill_markup),
+        },
+    ]
+}
 
-            configs.push(config);
+pub fn parse_tiers(spec: &str) -> Result<Vec<Tier>, AppError> {
+    // space separated volume:bid markup:offer markup tiers, markups in pips
+    // e.g. "1:0:0 2:0.2:0.2 5:0.5:0.5 10:1:1 25:2:2.5"
+    let mut tiers: Vec<Tier> = Vec::new();
+    for tier in spec.split_whitespace() {
+        let fields: Vec<&str> = tier.split(':').collect();
+        if fields.len() != 3 {
+            return Err(AppError::InvalidConfig(format!(
+                "tier {tier} must be volume:bid markup:offer markup"
+            )));
+        }
+        let bid_markup: f64 = fields[1].parse()?;
+        let offer_markup: f64 = fields[2].parse()?;
+        if !(config::non_negative(bid_markup) && config::non_negative(offer_markup)) {
+            return Err(AppError::InvalidConfig(format!(
+                "tier {tier} markups {}",
+                config::NON_NEGATIVE
+            )));
         }
-        index += 1;
+        tiers.push(Tier {
+            volume: fields[0].parse()?,
+            bid_markup: pips_to_ticks(bid_markup),
+            offer_markup: pips_to_ticks(offer_markup),
+        });
     }
+    Ok(tiers)
+}
 
+pub fn validate_tiers(liquidity_provider: &str, tiers: &[Tier]) -> Result<(), AppError> {
+    // quotes are identified by liquidity provider, side and volume so volumes must be unique
+    if tiers.is_empty() {
+        return Err(AppError::InvalidConfig(format!(
+            "{liquidity_provider} must quote at least one tier"
+        )));
+    }
+    for (i, tier) in tiers.iter().enumerate() {
+        if tier.volume <= 0 {
+            return Err(AppError::InvalidConfig(format!(
+                "{liquidity_provider} tier volume {} must be positive",
+                tier.volume
+            )));
+        }
+        if tiers[..i].iter().any(|other| other.volume == tier.volume) {
+            return Err(AppError::InvalidConfig(format!(
+                "{liquidity_provider} quotes {}M more than once",
+                tier.volume
+            )));
+        }
+    }
     Ok(())
 }
 
-fn read_config_file<P: AsRef<Path>>(file_path: P) -> Result<Vec<String>, AppError> {
-    let contents: String = fs::read_to_string(file_path)?;
-    let results = contents.lines().map(String::from).collect();
-    Ok(results)
+pub struct LpSimulator {
+    // generates the quotes for a single liquidity provider from its own seeded random number
+    // generator so that the same seed always produces the same sequence of market data
+    pub liquidity_provider: String,
+    currency_pair: String,
+    buy_price: Price,
+    spread: i64,
+    tiers: Vec<Tier>,
+    iterations_left: i32,
+    incremental: bool,
+    sequence_number: u64,
+    // bid and offer tiers the aggregator currently holds for this liquidity provider
+    sent_tiers: Option<(Vec<QuoteTier>, Vec<QuoteTier>)>,
+    rng: ChaCha8Rng,
+    price_model: Box<dyn PriceModel>,
+    // milliseconds since the previous quote
+    interval: u64,
+}
+
+impl LpSimulator {
+    pub fn new(config: &Config, seed: u64) -> Self {
+        info!("{} simulation seed is {}", config.liquidity_provider, seed);
+        LpSimulator {
+            liquidity_provider: config.liquidity_provider.clone(),
+            currency_pair: config.currency_pair.clone(),
+            buy_price: config.buy_price,
+            spread: config.spread,
+            tiers: config.tiers.clone(),
+            iterations_left: config.run_iterations,
+            incremental: config.incremental,
+            sequence_number: 0,
+            sent_tiers: None,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            price_model: config.price_model.create(config.buy_price),
+            interval: 0,
+        }
+    }
+
+    pub fn next_interval(&mut self) -> Option<u64> {
+        // milliseconds to wait before sending the next market data, or None once all
+        // iterations have been sent
+        if self.iterations_left <= 0 {
+            return None;
+        }
+        self.iterations_left -= 1;
+        self.interval = self.rng.random_range(1000..5000);
+        Some(self.interval)
+    }
+
+    pub fn next_quote(&mut self, timestamp: u64) -> Option<QuoteUpdate> {
+        // move the price on using this liquidity provider's price model
+        let dt = self.interval as f64 / 1000.0;
+        self.buy_price = self
+            .price_model
+            .next_price(self.buy_price, dt, &mut self.rng);
+
+        // a bid and offer tier for each configured tier, marked up from the buy and sell prices
+        let buy_price = self.buy_price;
+        let sell_price = buy_price.add_ticks(self.spread);
+        let mut bids: Vec<QuoteTier> = Vec::new();
+        let mut offers: Vec<QuoteTier> = Vec::new();
+        for tier in &self.tiers {
+            bids.push(QuoteTier {
+                price: buy_price.add_ticks(-tier.bid_markup),
+                volume: tier.volume,
+            });
+            offers.push(QuoteTier {
+                price: sell_price.add_ticks(tier.offer_markup),
+                volume: tier.volume,
+            });
+        }
+
+        if self.incremental && self.sent_tiers.is_some() {
+            // liquidity providers sending incremental refreshes occasionally withdraw their
+            // largest quotes until the next update, and send nothing when no quote has changed
+            if self.rng.random_bool(QUOTE_WITHDRAW_PROBABILITY) {
+                let largest = self.tiers.iter().map(|tier| tier.volume).max();
+                bids.retain(|tier| Some(tier.volume) != largest);
+                offers.retain(|tier| Some(tier.volume) != largest);
+            }
+            if self.sent_tiers.as_ref() == Some(&(bids.clone(), offers.clone())) {
+                return None;
+            }
+        }
+
+        self.sequence_number += 1;
+        self.sent_tiers = Some((bids.clone(), offers.clone()));
+        Some(QuoteUpdate {
+            liquidity_provider: self.liquidity_provider.clone(),
+            currency_pair: self.currency_pair.clone(),
+            bids,
+            offers,
+            source_time: timestamp,
+            sequence_number: self.sequence_number,
+        })
+    }
 }
 
-pub fn get_marketdata(config: &Config) -> impl Stream<Item = String> {
-    // For this liqudity provider in config, create the new market data values
+pub fn get_marketdata(mut lp_simulator: LpSimulator) -> impl Stream<Item = QuoteUpdate> {
+    // For this liqudity provider, create the new market data values
     // and send them asynchronously (don't block and wait) every random 1000-5000 milliseconds
     let (tx, rx) = unbounded_channel();
 
-    // async block may outlive the current function, and the config reference only lives for the current function
-    // async blocks are not executed immediately and must either take a reference or ownership of outside variables they use
-    // can't take a reference of config values because config is a shared reference, hence left to take ownership of new variables
-    // from config and use them in the async block below. Also can't use lifetimes because Stream returned from the function can outlive the function
-    let mut buy_price = config.buy_price;
-    let spread = config.spread;
-    let three_mill_markup = config.three_mill_markup;
-    let five_mill_markup = config.five_mill_markup;
-    let number_iterations = config.run_iterations + 1;
-    let liquidity_provider = config.liquidity_provider.clone();
-    let currency_pair = config.currency_pair.clone();
-
     spawn(async move {
         // spawn a task to handle the async sleep calls
         // async returns a future rather than blocking current thread
-        // move is required to move tx into the async block so it gets ownership and
+        // move is required to move tx and the simulator into the async block so it gets ownership and
         // tx closes after last message is sent
-        for _number in 1..number_iterations {
-            let random_sleep = rand::random_range(1000..5000);
+        while let Some(random_sleep) = lp_simulator.next_interval() {
             //   println!("random sleep is {random_sleep}");
             // await polls the future until future returns Ready.
             // If future still pending then control is handed to the runtime
             sleep(Duration::from_millis(random_sleep)).await;
             // now future has returned ready state and so code below is now executed
-
-            // randomly determine whether this is a price rise or fall
-            // let pip_change: f64 = rand::random_range(1.0..5.0) / 10000.0;
-            let pip_change: f64 = rand::random_range(0.0..2.0) / 10000.0;
-
-            // let's say it is a bull market and prices are trending up
-            buy_price = ((buy_price + pip_change) * 10000.0).round() / 10000.0;
-
-            let sell_price = ((buy_price + spread) * 10000.0).round() / 10000.0;
-            let three_mill_buy_price =
-                ((buy_price + three_mill_markup) * 10000.0).round() / 10000.0;
-            let three_mill_sell_price =
-                ((sell_price - three_mill_markup) * 10000.0).round() / 10000.0;
-            let five_mill_buy_price = ((buy_price + five_mill_markup) * 10000.0).round() / 10000.0;
-            let five_mill_sell_price =
-                ((sell_price - five_mill_markup) * 10000.0).round() / 10000.0;
             let timestamp = SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
-                .as_nanos();
-
-            let marketdata = format!(
-                "{} | {} | {} | {} | {} | {} | {} | {} | {}",
-                liquidity_provider,
-                currency_pair,
-                buy_price,
-                sell_price,
-                three_mill_buy_price,
-                three_mill_sell_price,
-                five_mill_buy_price,
-                five_mill_sell_price,
-                timestamp
-            );
-
-            if let Err(send_error) = tx.send(format!("{marketdata}")) {
-                error!("could not send message {marketdata}: {send_error}");
+                .as_nanos() as u64;
+
+            let Some(quote) = lp_simulator.next_quote(timestamp) else {
+                continue;
+            };
+            if let Err(send_error) = tx.send(quote) {
+                error!(
+                    "could not send {} market data: {send_error}",
+                    lp_simulator.liquidity_provider
+                );
                 break;
             };
         }
 
         // number of iterations done so exit the program
-        info!("{} stream completed", liquidity_provider);
+        info!("{} stream completed", lp_simulator.liquidity_provider);
     });
 
     UnboundedReceiverStream::new(rx)
 }
 
-pub fn start_streams(config: &Vec<Config>) -> StreamMap<i32, impl Stream<Item = String>> {
-    let mut index = 0;
-    let mut map = StreamMap::new();
-    // start a market data simulated stream for each config (liquidity provider) value
-    // Combine all individual market data streams from each liquidity provider into a single merged stream map
-    for i in config {
-        let marketdata = get_marketdata(i);
+pub struct VirtualMarketData {
+    // merges the market data of all liquidity providers in virtual clock order. Each liquidity
+    // provider's next market data is due at its virtual time, ties broken by config order, so a
+    // seeded run always produces the same market data in the same order with the same timestamps
+    lp_simulators: Vec<(u64, i32, LpSimulator)>,
+}
 
-        map.insert(index, marketdata);
-        index += 1;
+impl VirtualMarketData {
+    pub fn new(lp_simulators: Vec<LpSimulator>, start_time: u64) -> Self {
+        let mut virtual_market_data = VirtualMarketData {
+            lp_simulators: Vec::new(),
+        };
+        for (index, lp_simulator) in (0..).zip(lp_simulators) {
+            virtual_market_data.schedule(start_time, index, lp_simulator);
+        }
+        virtual_market_data
     }
-    map
+
+    fn schedule(&mut self, virtual_time: u64, index: i32, mut lp_simulator: LpSimulator) {
+        if let Some(interval) = lp_simulator.next_interval() {
+            let due_time = virtual_time + interval * 1_000_000;
+            self.lp_simulators.push((due_time, index, lp_simulator));
+        } else {
+            info!("{} stream completed", lp_simulator.liquidity_provider);
+        }
+    }
+}
+
+impl Iterator for VirtualMarketData {
+    // virtual timestamp, liquidity provider index and quote update
+    type Item = (u64, i32, QuoteUpdate);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let next = (0..self.lp_simulators.len())
+                .min_by_key(|&i| (self.lp_simulators[i].0, self.lp_simulators[i].1))?;
+            let (virtual_time, index, mut lp_simulator) = self.lp_simulators.swap_remove(next);
+            let quote = lp_simulator.next_quote(virtual_time);
+            self.schedule(virtual_time, index, lp_simulator);
+            if let Some(quote) = quote {
+                return Some((virtual_time, index, quote));
+            }
+        }
+    }
+}
+
+pub fn lp_seed(config: &Config, seed: Option<u64>, index: u64) -> u64 {
+    // a liquidity provider's own seed takes precedence, otherwise derive one from the global
+    // seed, otherwise pick a random seed (which is logged so the run can be reproduced)
+    match (config.seed, seed) {
+        (Some(lp_seed), _) => lp_seed,
+        (None, Some(seed)) => seed.wrapping_add(index) & MAX_SEED,
+        (None, None) => random_seed(),
+    }
+}
+
+pub fn random_seed() -> u64 {
+    rand::random_range(0..=MAX_SEED)
+}
+
+pub fn virtual_clock(config: &[Config], seed: Option<u64>) -> bool {
+    // with a global seed, or a seed for every liquidity provider, the whole run is reproducible
+    // so it runs on the virtual clock
+    seed.is_some() || (!config.is_empty() && config.iter().all(|config| config.seed.is_some()))
+}
+
+pub struct SimulatedLp {
+    // a single simulated liquidity provider sending market data in real time
+    lp_simulator: LpSimulator,
+}
+
+impl SimulatedLp {
+    pub fn new(lp_simulator: LpSimulator) -> Self {
+        SimulatedLp { lp_simulator }
+    }
+}
+
+impl MarketDataSource for SimulatedLp {
+    fn name(&self) -> &str {
+        &self.lp_simulator.liquidity_provider
+    }
+
+    fn into_stream(self: Box<Self>) -> BoxStream<'static, QuoteUpdate> {
+        get_marketdata(self.lp_simulator).boxed()
+    }
+}
+
+pub struct SeededSimulation {
+    // all the simulated liquidity providers of a seeded run, sending market data in virtual clock
+    // order paced in real time by the gaps between virtual timestamps
+    virtual_market_data: VirtualMarketData,
+}
+
+impl SeededSimulation {
+    pub fn new(virtual_market_data: VirtualMarketData) -> Self {
+        SeededSimulation {
+            virtual_market_data,
+        }
+    }
+}
+
+impl MarketDataSource for SeededSimulation {
+    fn name(&self) -> &str {
+        "seeded simulation"
+    }
+
+    fn into_stream(self: Box<Self>) -> BoxStream<'static, QuoteUpdate> {
+        let mut last_time = VIRTUAL_CLOCK_START;
+        stream::iter(self.virtual_market_data)
+            .then(move |(virtual_time, _index, quote)| {
+                let pause = Duration::from_nanos(virtual_time - last_time);
+                last_time = virtual_time;
+                async move {
+                    sleep(pause).await;
+                    quote
+                }
+            })
+            .boxed()
+    }
+}
+
+pub fn sources(config: &[Config], seed: Option<u64>) -> Vec<Box<dyn MarketDataSource>> {
+    // a run without a global seed picks one to derive the seeds of liquidity providers without
+    // their own, so its prices can be reproduced with --seed
+    let global_seed = seed.unwrap_or_else(random_seed);
+    let lp_simulators: Vec<LpSimulator> = (0..)
+        .zip(config)
+        .map(|(index, i)| LpSimulator::new(i, lp_seed(i, Some(global_seed), index)))
+        .collect();
+
+    if virtual_clock(config, seed) {
+        // seeded runs use a virtual clock so the same seeds always give the same market data
+        match seed {
+            Some(seed) => info!("simulation seed is {seed}, using virtual clock"),
+            None => info!("every liquidity provider is seeded, using virtual clock"),
+        }
+        let virtual_market_data = VirtualMarketData::new(lp_simulators, VIRTUAL_CLOCK_START);
+        return vec![Box::new(SeededSimulation::new(virtual_market_data))];
+    }
+    info!(
+        "simulation seed is {global_seed}, run with --seed {global_seed} to reproduce the prices on a virtual clock"
+    );
+
+    // a market data source for each config (liquidity provider) value
+    lp_simulators
+        .into_iter()
+        .map(|lp_simulator| Box::new(SimulatedLp::new(lp_simulator)) as Box<dyn MarketDataSource>)
+        .collect()
 }
@@ -0,0 +1,148 @@
+//! # FX Simulator and Aggregator - fx_sim_agg
+//!
+//! `snapshot.rs` publishes immutable snapshots of the aggregated books and positions from the aggregation thread to
+//! the GUI and other readers. Each snapshot is swapped in atomically, so readers never block the aggregator and keep
+//! a consistent book for as long as they hold it. The aggregator publishes the books changed by a burst of quote
+//! updates together, at most once a publish interval, rather than copying a busy book on every update.
+use crate::aggregator::{BookManager, FxBook};
+use crate::position::PositionKeeper;
+use arc_swap::ArcSwap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// changed books and positions are published at most this often, about the frame rate of the GUI
+pub const PUBLISH_INTERVAL: Duration = Duration::from_millis(16);
+
+#[derive(Debug, Default)]
+pub struct Snapshots {
+    // latest snapshot of the aggregated book of each currency pair, ordered by currency pair
+    books: ArcSwap<BTreeMap<String, Arc<FxBook>>>,
+    // latest positions marked to the aggregated books
+    positions: ArcSwap<PositionKeeper>,
+}
+
+impl Snapshots {
+    pub fn new(book_manager: &BookManager, position_keeper: &PositionKeeper) -> Self {
+        // start from the books created for the configured currency pairs, before any quotes
+        let snapshots = Snapshots::default();
+        for fx_book in book_manager.books.values() {
+            snapshots.publish_book(fx_book);
+        }
+        snapshots.publish_positions(position_keeper);
+        snapshots
+    }
+
+    pub fn publish_book(&self, fx_book: &FxBook) {
+        self.publish_books([fx_book]);
+    }
+
+    pub fn publish_books<'a>(&self, fx_books: impl IntoIterator<Item = &'a FxBook>) {
+        // replace the snapshots of the books' currency pairs in a single swap, readers holding
+        // the previous snapshots keep them unchanged
+        let snapshots: Vec<Arc<FxBook>> = fx_books
+            .into_iter()
+            .map(|fx_book| Arc::new(fx_book.clone()))
+            .collect();
+        if snapshots.is_empty() {
+            return;
+        }
+        self.books.rcu(|books| {
+            let mut books = BTreeMap::clone(books);
+            for snapshot in &snapshots {
+                books.insert(snapshot.currency_pair.clone(), Arc::clone(snapshot));
+            }
+            books
+        });
+    }
+
+    pub fn publish_positions(&self, position_keeper: &PositionKeeper) {
+        self.positions.store(Arc::new(position_keeper.clone()));
+    }
+
+    pub fn book(&self, currency_pair: &str) -> Option<Arc<FxBook>> {
+        self.books.load().get(currency_pair).cloned()
+    }
+
+    pub fn books(&self) -> Arc<BTreeMap<String, Arc<FxBook>>> {
+        self.books.load_full()
+    }
+
+    pub fn currency_pairs(&self) -> Vec<String> {
+        self.books.load().keys().cloned().collect()
+    }
+
+    pub fn positions(&self) -> Arc<PositionKeeper> {
+        self.positions.load_full()
+    }
+}
+
+#[derive(Debug)]
+pub struct PendingSnapshots {
+    // currency pairs of the books changed since they were last published
+    books: BTreeSet<String>,
+    // whether the positions have changed since they were last published
+    positions: bool,
+    interval: Duration,
+    last_published: Instant,
+}
+
+impl Default for PendingSnapshots {
+    fn default() -> Self {
+        PendingSnapshots::new(PUBLISH_INTERVAL)
+    }
+}
+
+impl PendingSnapshots {
+    pub fn new(interval: Duration) -> Self {
+        PendingSnapshots {
+            books: BTreeSet::new(),
+            positions: false,
+            interval,
+            last_published: Instant::now(),
+        }
+    }
+
+    pub fn book_changed(&mut self, currency_pair: &str) {
+        if !self.books.contains(currency_pair) {
+            self.books.insert(String::from(currency_pair));
+        }
+    }
+
+    pub fn positions_changed(&mut self) {
+        self.positions = true;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.books.is_empty() && !self.positions
+    }
+
+    pub fn is_due(&self) -> bool {
+        !self.is_empty() && self.last_published.elapsed() >= self.interval
+    }
+
+    pub fn publish(
+        &mut self,
+        snapshots: &Snapshots,
+        book_manager: &BookManager,
+        position_keeper: &PositionKeeper,
+    ) -> bool {
+        // publish the changed books and positions, returning whether there was anything to
+        // publish
+        if self.is_empty() {
+            return false;
+        }
+        snapshots.publish_books(
+            self.books
+                .iter()
+                .filter_map(|currency_pair| book_manager.book(currency_pair)),
+        );
+        if self.positions {
+            snapshots.publish_positions(position_keeper);
+        }
+        self.books.clear();
+        self.positions = false;
+        self.last_published = Instant::now();
+        true
+    }
+}
@@ -0,0 +1,59 @@
+//! # FX Simulator and Aggregator - fx_sim_agg
+//!
+//! `source.rs` defines the `MarketDataSource` trait implemented by everything that feeds market data to the aggregator -
+//! simulated liquidity providers, recorded FIX log replays, venue feeds and test fixtures - and merges any mix of
+//! sources into the single stream of quote updates the aggregator consumes.
+use crate::quote::QuoteUpdate;
+use futures::stream::{self, BoxStream, StreamExt};
+use log::info;
+use std::sync::Arc;
+
+pub trait MarketDataSource: Send {
+    // name the source identifies itself by, the liquidity provider name for a single liquidity provider
+    fn name(&self) -> &str;
+
+    // start the source, returning its quote updates in the order they should be aggregated.
+    // The stream ends when the source has no more quotes
+    fn into_stream(self: Box<Self>) -> BoxStream<'static, QuoteUpdate>;
+}
+
+pub fn merge_sources(
+    sources: Vec<Box<dyn MarketDataSource>>,
+) -> BoxStream<'static, (Arc<str>, QuoteUpdate)> {
+    // merge the quote updates of every source in the order they arrive, tagged with the name of
+    // the source they came from
+    let streams = sources.into_iter().map(|source| {
+        let name: Arc<str> = Arc::from(source.name());
+        info!("starting market data source {name}");
+        source
+            .into_stream()
+            .map(move |quote| (Arc::clone(&name), quote))
+            .boxed()
+    });
+    stream::select_all(streams).boxed()
+}
+
+pub struct FixtureSource {
+    // fixed quote updates sent straight away, e.g. for tests
+    name: String,
+    quotes: Vec<QuoteUpdate>,
+}
+
+impl FixtureSource {
+    pub fn new(name: &str, quotes: Vec<QuoteUpdate>) -> Self {
+        FixtureSource {
+            name: String::from(name),
+            quotes,
+        }
+    }
+}
+
+impl MarketDataSource for FixtureSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn into_stream(self: Box<Self>) -> BoxStream<'static, QuoteUpdate> {
+        stream::iter(self.quotes).boxed()
+    }
+}
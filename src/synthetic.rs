@@ -0,0 +1,77 @@
+//! # FX Simulator and Aggregator - fx_sim_agg
+//!
+//! `synthetic.rs` generates synthetic streams of quote updates for benchmarking the aggregator at tick rates far above
+//! the simulator's. Liquidity providers take turns at random to quote tiers around a mid that follows a random walk,
+//! each skewing its quotes, so levels are continually added, merged, withdrawn and crossed.
+use crate::price::{PIP_TICKS, Price, pips_to_ticks, price_decimals};
+use crate::quote::{QuoteTier, QuoteUpdate};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+// volumes in millions quoted by the tiers, repeated for more tiers
+const TIER_VOLUMES: [i32; 5] = [1, 3, 5, 10, 20];
+
+#[derive(Debug, Clone)]
+pub struct SyntheticQuotes {
+    currency_pair: String,
+    liquidity_providers: Vec<String>,
+    tiers: usize,
+    // largest move of the mid in ticks between quote updates
+    volatility: i64,
+    mid: Price,
+    sequence_numbers: Vec<u64>,
+    source_time: u64,
+    rng: ChaCha8Rng,
+}
+
+impl SyntheticQuotes {
+    pub fn new(liquidity_providers: usize, tiers: usize, volatility_pips: f64, seed: u64) -> Self {
+        let currency_pair = String::from("USD/EUR");
+        let mid = Price::from_f64(1.1000, price_decimals(&currency_pair)).unwrap(); // panic if the constant mid is invalid
+        SyntheticQuotes {
+            currency_pair,
+            liquidity_providers: (1..=liquidity_providers)
+                .map(|i| format!("LP{i}"))
+                .collect(),
+            tiers,
+            volatility: pips_to_ticks(volatility_pips),
+            mid,
+            sequence_numbers: vec![0; liquidity_providers],
+            source_time: 0,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Iterator for SyntheticQuotes {
+    type Item = QuoteUpdate;
+
+    fn next(&mut self) -> Option<QuoteUpdate> {
+        // the liquidity provider skews its quotes up to 2 pips from the mid, quotes a half spread
+        // of up to a pip and widens each further tier by a pip
+        if self.liquidity_providers.is_empty() {
+            return None;
+        }
+        self.mid = self
+            .mid
+            .add_ticks(self.rng.random_range(-self.volatility..=self.volatility));
+        let index = self.rng.random_range(0..self.liquidity_providers.len());
+        self.sequence_numbers[index] += 1;
+        self.source_time += 1_000;
+        let skew = self.rng.random_range(-2 * PIP_TICKS..=2 * PIP_TICKS);
+        let half_spread = self.rng.random_range(2..=PIP_TICKS);
+        let mid = self.mid;
+        let tier = |i: usize, sign: i64| QuoteTier {
+            price: mid.add_ticks(skew + sign * (half_spread + PIP_TICKS * i as i64)),
+            volume: TIER_VOLUMES[i % TIER_VOLUMES.len()],
+        };
+        Some(QuoteUpdate {
+            liquidity_provider: self.liquidity_providers[index].clone(),
+            currency_pair: self.currency_pair.clone(),
+            bids: (0..self.tiers).map(|i| tier(i, -1)).collect(),
+            offers: (0..self.tiers).map(|i| tier(i, 1)).collect(),
+            source_time: self.source_time,
+            sequence_number: self.sequence_numbers[index],
+        })
+    }
+}
@@ -1,56 +1,95 @@
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
 
+    use crate::AppError;
     use crate::aggregator::FxAggBookEntry;
-    use crate::aggregator::{self, FxBook};
+    use crate::aggregator::{self, BookManager, FxBook};
+    use crate::book_event::{BookChange, BookEvent};
+    use crate::book_policy::{BookPolicyConfig, CrossedBookPolicy};
+    use crate::book_side::{BookSide, LpTier};
+    use crate::cli::Cli;
+    use crate::config;
+    use crate::fix::{
+        self, MarketData, MarketDataIncremental, MarketDataSnapshot, MdEntry, MdUpdate,
+        QuoteDecoder, QuoteEncoder, UpdateAction,
+    };
+    use crate::last_look::{
+        LastLookConfig, LastLookDesk, RejectReason, TradeRequest, TradeResponse,
+    };
+    use crate::latency::LatencyHistogram;
+    use crate::order::{self, Order, OrderStatus, OrderType, Side};
+    use crate::position::{self, Position, PositionKeeper, Trade};
+    use crate::price::{Price, pips_to_ticks, price_decimals};
+    use crate::price_history::{HISTORY_DURATION, PriceSample};
+    use crate::price_model::PriceModelConfig;
+    use crate::quote::{QuoteTier, QuoteUpdate};
+    use crate::replay::{self, Replay, ReplaySpeed};
+    use crate::risk::{RiskBreach, RiskLimits, RiskManager};
+    use crate::simulator;
+    use crate::snapshot::{PendingSnapshots, Snapshots};
+    use crate::source::{FixtureSource, MarketDataSource, merge_sources};
+    use crate::synthetic::SyntheticQuotes;
+    use clap::Parser;
+    use futures::StreamExt;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn price(value: f64) -> Price {
+        Price::from_f64(value, 5).unwrap()
+    }
 
     #[test]
     fn test_sort_by_price_reverse() {
-        let mut fx_buy_book: Vec<FxAggBookEntry> = vec![
-            FxAggBookEntry {
-                lp_vol: vec![
-                    (String::from("MS "), 1),
-                    (String::from("UBS "), 5),
-                    (String::from("CITI "), 3),
-                    (String::from("BARX "), 3),
-                ],
-                volume: 12,
-                price: 1.5555,
-                side: String::from("Buy"),
-            },
-            FxAggBookEntry {
-                lp_vol: vec![
-                    (String::from("MS "), 3),
-                    (String::from("JPMC "), 1),
-                    (String::from("CITI "), 5),
-                ],
-                volume: 9,
-                price: 1.5556,
-                side: String::from("Buy"),
-            },
-            FxAggBookEntry {
-                lp_vol: vec![(String::from("UBS "), 1)],
-                volume: 1,
-                price: 1.5553,
-                side: String::from("Buy"),
-            },
-            FxAggBookEntry {
-                lp_vol: vec![
-                    (String::from("UBS "), 3),
-                    (String::from("CITI "), 1),
-                    (String::from("BARX "), 1),
-                    (String::from("BARX "), 5),
-                ],
-                volume: 10,
-                price: 1.5554,
-                side: String::from("Buy"),
-            },
-        ];
-
-        aggregator::sort_buy_book(&mut fx_buy_book);
+        let fx_buy_book = BookSide::from_entries(
+            "Buy",
+            vec![
+                FxAggBookEntry {
+                    lp_vol: vec![
+                        (String::from("MS "), 1),
+                        (String::from("UBS "), 5),
+                        (String::from("CITI "), 3),
+                        (String::from("BARX "), 3),
+                    ],
+                    volume: 12,
+                    price: price(1.5555),
+                    side: String::from("Buy"),
+                },
+                FxAggBookEntry {
+                    lp_vol: vec![
+                        (String::from("MS "), 3),
+                        (String::from("JPMC "), 1),
+                        (String::from("CITI "), 5),
+                    ],
+                    volume: 9,
+                    price: price(1.5556),
+                    side: String::from("Buy"),
+                },
+                FxAggBookEntry {
+                    lp_vol: vec![(String::from("UBS "), 1)],
+                    volume: 1,
+                    price: price(1.5553),
+                    side: String::from("Buy"),
+                },
+                FxAggBookEntry {
+                    lp_vol: vec![
+                        (String::from("UBS "), 3),
+                        (String::from("CITI "), 1),
+                        (String::from("BARX "), 1),
+                        (String::from("BARX "), 5),
+                    ],
+                    volume: 10,
+                    price: price(1.5554),
+                    side: String::from("Buy"),
+                },
+            ],
+        );
 
+        // the levels are kept in price order, best first, as they are added
         assert_eq!(
-            fx_buy_book,
+            fx_buy_book.to_vec(),
             vec![
                 FxAggBookEntry {
                     lp_vol: vec![
@@ -59,7 +98,7 @@ mod tests {
                         (String::from("CITI "), 5),
                     ],
                     volume: 9,
-                    price: 1.5556,
+                    price: price(1.5556),
                     side: String::from("Buy"),
                 },
                 FxAggBookEntry {
@@ -70,7 +109,7 @@ mod tests {
                         (String::from("BARX "), 3),
                     ],
                     volume: 12,
-                    price: 1.5555,
+                    price: price(1.5555),
                     side: String::from("Buy"),
                 },
                 FxAggBookEntry {
@@ -81,13 +120,13 @@ mod tests {
                         (String::from("BARX "), 5),
                     ],
                     volume: 10,
-                    price: 1.5554,
+                    price: price(1.5554),
                     side: String::from("Buy"),
                 },
                 FxAggBookEntry {
                     lp_vol: vec![(String::from("UBS "), 1)],
                     volume: 1,
-                    price: 1.5553,
+                    price: price(1.5553),
                     side: String::from("Buy"),
                 },
             ]
@@ -96,52 +135,54 @@ mod tests {
 
     #[test]
     fn test_sort_by_price() {
-        let mut fx_sell_book: Vec<FxAggBookEntry> = vec![
-            FxAggBookEntry {
-                lp_vol: vec![(String::from("MS "), 3), (String::from("JPMC "), 5)],
-                volume: 8,
-                price: 1.5565,
-                side: String::from("Sell"),
-            },
-            FxAggBookEntry {
-                lp_vol: vec![
-                    (String::from("UBS "), 3),
-                    (String::from("CITI "), 3),
-                    (String::from("BARX "), 3),
-                ],
-                volume: 9,
-                price: 1.5563,
-                side: String::from("Sell"),
-            },
-            FxAggBookEntry {
-                lp_vol: vec![(String::from("JPMC "), 1)],
-                volume: 1,
-                price: 1.5567,
-                side: String::from("Sell"),
-            },
-            FxAggBookEntry {
-                lp_vol: vec![
-                    (String::from("MS "), 5),
-                    (String::from("UBS "), 1),
-                    (String::from("CITI "), 1),
-                    (String::from("BARX "), 1),
-                    (String::from("BARX "), 5),
-                ],
-                volume: 13,
-                price: 1.5564,
-                side: String::from("Sell"),
-            },
-            FxAggBookEntry {
-                lp_vol: vec![(String::from("MS "), 1), (String::from("JPMC "), 3)],
-                volume: 4,
-                price: 1.5566,
-                side: String::from("Sell"),
-            },
-        ];
+        let fx_sell_book = BookSide::from_entries(
+            "Sell",
+            vec![
+                FxAggBookEntry {
+                    lp_vol: vec![(String::from("MS "), 3), (String::from("JPMC "), 5)],
+                    volume: 8,
+                    price: price(1.5565),
+                    side: String::from("Sell"),
+                },
+                FxAggBookEntry {
+                    lp_vol: vec![
+                        (String::from("UBS "), 3),
+                        (String::from("CITI "), 3),
+                        (String::from("BARX "), 3),
+                    ],
+                    volume: 9,
+                    price: price(1.5563),
+                    side: String::from("Sell"),
+                },
+                FxAggBookEntry {
+                    lp_vol: vec![(String::from("JPMC "), 1)],
+                    volume: 1,
+                    price: price(1.5567),
+                    side: String::from("Sell"),
+                },
+                FxAggBookEntry {
+                    lp_vol: vec![
+                        (String::from("MS "), 5),
+                        (String::from("UBS "), 1),
+                        (String::from("CITI "), 1),
+                        (String::from("BARX "), 1),
+                        (String::from("BARX "), 5),
+                    ],
+                    volume: 13,
+                    price: price(1.5564),
+                    side: String::from("Sell"),
+                },
+                FxAggBookEntry {
+                    lp_vol: vec![(String::from("MS "), 1), (String::from("JPMC "), 3)],
+                    volume: 4,
+                    price: price(1.5566),
+                    side: String::from("Sell"),
+                },
+            ],
+        );
 
-        aggregator::sort_sell_book(&mut fx_sell_book);
         assert_eq!(
-            fx_sell_book,
+            fx_sell_book.to_vec(),
             vec![
                 FxAggBookEntry {
                     lp_vol: vec![
@@ -150,7 +191,7 @@ mod tests {
                         (String::from("BARX "), 3),
                     ],
                     volume: 9,
-                    price: 1.5563,
+                    price: price(1.5563),
                     side: String::from("Sell"),
                 },
                 FxAggBookEntry {
@@ -162,25 +203,25 @@ mod tests {
                         (String::from("BARX "), 5),
                     ],
                     volume: 13,
-                    price: 1.5564,
+                    price: price(1.5564),
                     side: String::from("Sell"),
                 },
                 FxAggBookEntry {
                     lp_vol: vec![(String::from("MS "), 3), (String::from("JPMC "), 5)],
                     volume: 8,
-                    price: 1.5565,
+                    price: price(1.5565),
                     side: String::from("Sell"),
                 },
                 FxAggBookEntry {
                     lp_vol: vec![(String::from("MS "), 1), (String::from("JPMC "), 3)],
                     volume: 4,
-                    price: 1.5566,
+                    price: price(1.5566),
                     side: String::from("Sell"),
                 },
                 FxAggBookEntry {
                     lp_vol: vec![(String::from("JPMC "), 1)],
                     volume: 1,
-                    price: 1.5567,
+                    price: price(1.5567),
                     side: String::from("Sell"),
                 },
             ]
@@ -190,8 +231,8 @@ mod tests {
     #[test]
     fn test_add_agg_book_entry() {
         let currency_pair = String::from("USD/EUR");
-        let buy_book: Vec<FxAggBookEntry> = Vec::new();
-        let sell_book: Vec<FxAggBookEntry> = Vec::new();
+        let buy_book = BookSide::new("Buy");
+        let sell_book = BookSide::new("Sell");
         let timestamp: u64 = 1753440851702449924;
 
         let mut fx_book = FxBook {
@@ -199,16 +240,17 @@ mod tests {
             buy_book,
             sell_book,
             timestamp,
+            ..Default::default()
         };
 
-        aggregator::add_agg_book_entry(&mut fx_book, "MS", 1, 1.5556, "Buy");
+        aggregator::add_agg_book_entry(&mut fx_book, "MS", 1, price(1.5556), "Buy");
 
         assert_eq!(
-            fx_book.buy_book,
+            fx_book.buy_book.to_vec(),
             vec![FxAggBookEntry {
                 lp_vol: vec![(String::from("MS "), 1),],
                 volume: 1,
-                price: 1.5556,
+                price: price(1.5556),
                 side: String::from("Buy"),
             }]
         )
@@ -218,42 +260,49 @@ mod tests {
     fn test_maintain_min_spread() {
         let mut fx_book = FxBook {
             currency_pair: String::from(" USD/EUR"),
-            buy_book: vec![
-                FxAggBookEntry {
-                    lp_vol: vec![
-                        (String::from("MS "), 1),
-                        (String::from("UBS "), 5),
-                        (String::from("CITI "), 3),
-                        (String::from("BARX "), 3),
-                    ],
-                    volume: 12,
-                    price: 1.5559,
-                    side: String::from("Buy"),
-                },
-                FxAggBookEntry {
-                    lp_vol: vec![
-                        (String::from("MS "), 3),
-                        (String::from("JPMC "), 1),
-                        (String::from("CITI "), 5),
-                    ],
-                    volume: 9,
-                    price: 1.5556,
-                    side: String::from("Buy"),
-                },
-            ],
-            sell_book: vec![FxAggBookEntry {
-                lp_vol: vec![(String::from("MS "), 3), (String::from("JPMC "), 5)],
-                volume: 8,
-                price: 1.5564,
-                side: String::from("Sell"),
-            }],
+            buy_book: BookSide::from_entries(
+                "Buy",
+                vec![
+                    FxAggBookEntry {
+                        lp_vol: vec![
+                            (String::from("MS "), 1),
+                            (String::from("UBS "), 5),
+                            (String::from("CITI "), 3),
+                            (String::from("BARX "), 3),
+                        ],
+                        volume: 12,
+                        price: price(1.5559),
+                        side: String::from("Buy"),
+                    },
+                    FxAggBookEntry {
+                        lp_vol: vec![
+                            (String::from("MS "), 3),
+                            (String::from("JPMC "), 1),
+                            (String::from("CITI "), 5),
+                        ],
+                        volume: 9,
+                        price: price(1.5556),
+                        side: String::from("Buy"),
+                    },
+                ],
+            ),
+            sell_book: BookSide::from_entries(
+                "Sell",
+                vec![FxAggBookEntry {
+                    lp_vol: vec![(String::from("MS "), 3), (String::from("JPMC "), 5)],
+                    volume: 8,
+                    price: price(1.5564),
+                    side: String::from("Sell"),
+                }],
+            ),
             timestamp: 1753430617683973406,
+            ..Default::default()
         };
 
-        aggregator::maintain_min_spread(&mut fx_book);
+        aggregator::maintain_min_spread(&mut fx_book, pips_to_ticks(aggregator::MIN_SPREAD_PIPS));
 
         assert_eq!(
-            fx_book.buy_book,
+            fx_book.buy_book.to_vec(),
             vec![FxAggBookEntry {
                 lp_vol: vec![
                     (String::from("MS "), 3),
@@ -261,7 +310,7 @@ mod tests {
                     (String::from("CITI "), 5),
                 ],
                 volume: 9,
-                price: 1.5556,
+                price: price(1.5556),
                 side: String::from("Buy"),
             }]
         )
@@ -271,7 +320,56 @@ mod tests {
     fn test_check_books_crossed() {
         let mut fx_book = FxBook {
             currency_pair: String::from(" USD/EUR"),
-            buy_book: vec![
+            buy_book: BookSide::from_entries(
+                "Buy",
+                vec![
+                    FxAggBookEntry {
+                        lp_vol: vec![
+                            (String::from("MS "), 1),
+                            (String::from("UBS "), 5),
+                            (String::from("CITI "), 3),
+                            (String::from("BARX "), 3),
+                        ],
+                        volume: 12,
+                        price: price(1.5559),
+                        side: String::from("Buy"),
+                    },
+                    FxAggBookEntry {
+                        lp_vol: vec![
+                            (String::from("MS "), 3),
+                            (String::from("JPMC "), 1),
+                            (String::from("CITI "), 5),
+                        ],
+                        volume: 9,
+                        price: price(1.5556),
+                        side: String::from("Buy"),
+                    },
+                ],
+            ),
+            sell_book: BookSide::from_entries(
+                "Sell",
+                vec![FxAggBookEntry {
+                    lp_vol: vec![(String::from("MS "), 3), (String::from("JPMC "), 5)],
+                    volume: 8,
+                    price: price(1.5558),
+                    side: String::from("Sell"),
+                }],
+            ),
+            timestamp: 1753430617683973406,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            aggregator::check_books_crossed(&mut fx_book),
+            Some((0, price(1.5558)))
+        );
+    }
+
+    #[test]
+    fn test_remove_entries_from_top() {
+        let mut fx_buy_book = BookSide::from_entries(
+            "Buy",
+            vec![
                 FxAggBookEntry {
                     lp_vol: vec![
                         (String::from("MS "), 1),
@@ -280,7 +378,7 @@ mod tests {
                         (String::from("BARX "), 3),
                     ],
                     volume: 12,
-                    price: 1.5559,
+                    price: price(1.5555),
                     side: String::from("Buy"),
                 },
                 FxAggBookEntry {
@@ -290,75 +388,13 @@ mod tests {
                         (String::from("CITI "), 5),
                     ],
                     volume: 9,
-                    price: 1.5556,
+                    price: price(1.5556),
                     side: String::from("Buy"),
                 },
-            ],
-            sell_book: vec![FxAggBookEntry {
-                lp_vol: vec![(String::from("MS "), 3), (String::from("JPMC "), 5)],
-                volume: 8,
-                price: 1.5558,
-                side: String::from("Sell"),
-            }],
-            timestamp: 1753430617683973406,
-        };
-
-        assert_eq!(
-            aggregator::check_books_crossed(&mut fx_book),
-            Some((0, 1.5558))
-        );
-    }
-
-    #[test]
-    fn test_remove_entries_from_top() {
-        let mut fx_buy_book: Vec<FxAggBookEntry> = vec![
-            FxAggBookEntry {
-                lp_vol: vec![
-                    (String::from("MS "), 1),
-                    (String::from("UBS "), 5),
-                    (String::from("CITI "), 3),
-                    (String::from("BARX "), 3),
-                ],
-                volume: 12,
-                price: 1.5555,
-                side: String::from("Buy"),
-            },
-            FxAggBookEntry {
-                lp_vol: vec![
-                    (String::from("MS "), 3),
-                    (String::from("JPMC "), 1),
-                    (String::from("CITI "), 5),
-                ],
-                volume: 9,
-                price: 1.5556,
-                side: String::from("Buy"),
-            },
-            FxAggBookEntry {
-                lp_vol: vec![(String::from("UBS "), 1)],
-                volume: 1,
-                price: 1.5553,
-                side: String::from("Buy"),
-            },
-            FxAggBookEntry {
-                lp_vol: vec![
-                    (String::from("UBS "), 3),
-                    (String::from("CITI "), 1),
-                    (String::from("BARX "), 1),
-                    (String::from("BARX "), 5),
-                ],
-                volume: 10,
-                price: 1.5554,
-                side: String::from("Buy"),
-            },
-        ];
-        aggregator::remove_range_entries_from_top(&mut fx_buy_book, 1, "Buy");
-        assert_eq!(
-            fx_buy_book,
-            vec![
                 FxAggBookEntry {
                     lp_vol: vec![(String::from("UBS "), 1)],
                     volume: 1,
-                    price: 1.5553,
+                    price: price(1.5553),
                     side: String::from("Buy"),
                 },
                 FxAggBookEntry {
@@ -369,91 +405,15 @@ mod tests {
                         (String::from("BARX "), 5),
                     ],
                     volume: 10,
-                    price: 1.5554,
+                    price: price(1.5554),
                     side: String::from("Buy"),
                 },
-            ]
-        )
-    }
-
-    #[test]
-    fn test_check_expired_quotes() {
-        let liquidity_provider = "JPMC ";
-        let volume = 1;
-        let mut fx_buy_book: Vec<FxAggBookEntry> = vec![
-            FxAggBookEntry {
-                lp_vol: vec![
-                    (String::from("MS "), 1),
-                    (String::from("UBS "), 5),
-                    (String::from("CITI "), 3),
-                    (String::from("BARX "), 3),
-                ],
-                volume: 12,
-                price: 1.5555,
-                side: String::from("Buy"),
-            },
-            FxAggBookEntry {
-                lp_vol: vec![
-                    (String::from("MS "), 3),
-                    (String::from("JPMC "), 1),
-                    (String::from("CITI "), 5),
-                ],
-                volume: 9,
-                price: 1.5556,
-                side: String::from("Buy"),
-            },
-            FxAggBookEntry {
-                lp_vol: vec![(String::from("UBS "), 1)],
-                volume: 1,
-                price: 1.5553,
-                side: String::from("Buy"),
-            },
-            FxAggBookEntry {
-                lp_vol: vec![
-                    (String::from("UBS "), 3),
-                    (String::from("CITI "), 1),
-                    (String::from("BARX "), 1),
-                    (String::from("BARX "), 5),
-                ],
-                volume: 10,
-                price: 1.5554,
-                side: String::from("Buy"),
-            },
-        ];
-
-        match aggregator::check_expired_quotes(&mut fx_buy_book, liquidity_provider, volume) {
-            Some(entry_to_remove) => {
-                println!("test check expired quotes, index to remove is {entry_to_remove}")
-            }
-            None => (),
-        };
-
+            ],
+        );
+        aggregator::remove_range_entries_from_top(&mut fx_buy_book, 1, "Buy");
         assert_eq!(
-            fx_buy_book,
+            fx_buy_book.to_vec(),
             vec![
-                FxAggBookEntry {
-                    lp_vol: vec![
-                        (String::from("MS "), 1),
-                        (String::from("UBS "), 5),
-                        (String::from("CITI "), 3),
-                        (String::from("BARX "), 3),
-                    ],
-                    volume: 12,
-                    price: 1.5555,
-                    side: String::from("Buy"),
-                },
-                FxAggBookEntry {
-                    lp_vol: vec![(String::from("MS "), 3), (String::from("CITI "), 5),],
-                    volume: 9,
-                    price: 1.5556,
-                    side: String::from("Buy"),
-                },
-                FxAggBookEntry {
-                    lp_vol: vec![(String::from("UBS "), 1)],
-                    volume: 1,
-                    price: 1.5553,
-                    side: String::from("Buy"),
-                },
                 FxAggBookEntry {
                     lp_vol: vec![
                         (String::from("UBS "), 3),
@@ -462,17 +422,25 @@ mod tests {
                         (String::from("BARX "), 5),
                     ],
                     volume: 10,
-                    price: 1.5554,
+                    price: price(1.5554),
+                    side: String::from("Buy"),
+                },
+                FxAggBookEntry {
+                    lp_vol: vec![(String::from("UBS "), 1)],
+                    volume: 1,
+                    price: price(1.5553),
                     side: String::from("Buy"),
                 },
             ]
         )
     }
+
     #[test]
-    fn test_find_buy_index_when_crossed() {
-        let mut fx_book = FxBook {
-            currency_pair: String::from(" USD/EUR"),
-            buy_book: vec![
+    fn test_remove_lp_quotes() {
+        let liquidity_provider = "UBS ";
+        let mut fx_buy_book = BookSide::from_entries(
+            "Buy",
+            vec![
                 FxAggBookEntry {
                     lp_vol: vec![
                         (String::from("MS "), 1),
@@ -481,7 +449,7 @@ mod tests {
                         (String::from("BARX "), 3),
                     ],
                     volume: 12,
-                    price: 1.5566,
+                    price: price(1.5555),
                     side: String::from("Buy"),
                 },
                 FxAggBookEntry {
@@ -491,13 +459,13 @@ mod tests {
                         (String::from("CITI "), 5),
                     ],
                     volume: 9,
-                    price: 1.5565,
+                    price: price(1.5556),
                     side: String::from("Buy"),
                 },
                 FxAggBookEntry {
                     lp_vol: vec![(String::from("UBS "), 1)],
                     volume: 1,
-                    price: 1.5553,
+                    price: price(1.5553),
                     side: String::from("Buy"),
                 },
                 FxAggBookEntry {
@@ -508,40 +476,2646 @@ mod tests {
                         (String::from("BARX "), 5),
                     ],
                     volume: 10,
-                    price: 1.5554,
+                    price: price(1.5554),
                     side: String::from("Buy"),
                 },
             ],
-            sell_book: vec![
-                FxAggBookEntry {
-                    lp_vol: vec![(String::from("MS "), 3), (String::from("JPMC "), 5)],
-                    volume: 8,
-                    price: 1.5565,
-                    side: String::from("Sell"),
+        );
+
+        aggregator::remove_lp_quotes(&mut fx_buy_book, liquidity_provider);
+
+        // the entry only quoted by UBS is removed and the volumes of the others re-summed
+        let prices: Vec<Price> = fx_buy_book.iter().map(|entry| entry.price).collect();
+        assert_eq!(prices, vec![price(1.5556), price(1.5555), price(1.5554)]);
+        let volumes: Vec<i32> = fx_buy_book.iter().map(|entry| entry.volume).collect();
+        assert_eq!(volumes, vec![9, 7, 7]);
+        assert_eq!(
+            fx_buy_book.iter().nth(2).unwrap().lp_vol,
+            vec![
+                (String::from("CITI "), 1),
+                (String::from("BARX "), 1),
+                (String::from("BARX "), 5),
+            ]
+        );
+    }
+    #[test]
+    fn test_find_buy_index_when_crossed() {
+        let mut fx_book = FxBook {
+            currency_pair: String::from(" USD/EUR"),
+            buy_book: BookSide::from_entries(
+                "Buy",
+                vec![
+                    FxAggBookEntry {
+                        lp_vol: vec![
+                            (String::from("MS "), 1),
+                            (String::from("UBS "), 5),
+                            (String::from("CITI "), 3),
+                            (String::from("BARX "), 3),
+                        ],
+                        volume: 12,
+                        price: price(1.5566),
+                        side: String::from("Buy"),
+                    },
+                    FxAggBookEntry {
+                        lp_vol: vec![
+                            (String::from("MS "), 3),
+                            (String::from("JPMC "), 1),
+                            (String::from("CITI "), 5),
+                        ],
+                        volume: 9,
+                        price: price(1.5565),
+                        side: String::from("Buy"),
+                    },
+                    FxAggBookEntry {
+                        lp_vol: vec![(String::from("UBS "), 1)],
+                        volume: 1,
+                        price: price(1.5553),
+                        side: String::from("Buy"),
+                    },
+                    FxAggBookEntry {
+                        lp_vol: vec![
+                            (String::from("UBS "), 3),
+                            (String::from("CITI "), 1),
+                            (String::from("BARX "), 1),
+                            (String::from("BARX "), 5),
+                        ],
+                        volume: 10,
+                        price: price(1.5554),
+                        side: String::from("Buy"),
+                    },
+                ],
+            ),
+            sell_book: BookSide::from_entries(
+                "Sell",
+                vec![
+                    FxAggBookEntry {
+                        lp_vol: vec![(String::from("MS "), 3), (String::from("JPMC "), 5)],
+                        volume: 8,
+                        price: price(1.5565),
+                        side: String::from("Sell"),
+                    },
+                    FxAggBookEntry {
+                        lp_vol: vec![
+                            (String::from("UBS "), 3),
+                            (String::from("CITI "), 3),
+                            (String::from("BARX "), 3),
+                        ],
+                        volume: 9,
+                        price: price(1.5563),
+                        side: String::from("Sell"),
+                    },
+                    FxAggBookEntry {
+                        lp_vol: vec![(String::from("JPMC "), 1)],
+                        volume: 1,
+                        price: price(1.5567),
+                        side: String::from("Sell"),
+                    },
+                ],
+            ),
+            timestamp: 1753430617683973406,
+            ..Default::default()
+        };
+        let fx_book_side = aggregator::get_book_side(&mut fx_book, "Buy");
+        assert_eq!(
+            aggregator::find_buy_index_when_crossed(fx_book_side, price(1.5565)),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_price_parse_is_exact() {
+        assert_eq!(Price::parse("1.55523", 5).unwrap(), Price::new(155523, 5));
+        assert_eq!(Price::parse("1.5552", 5).unwrap(), Price::new(155520, 5));
+        assert_eq!(Price::parse("151.234", 3).unwrap(), Price::new(151234, 3));
+        assert_eq!(Price::parse("1.55523", 5).unwrap().to_string(), "1.55523");
+        assert_eq!(Price::new(151230, 3).to_string(), "151.230");
+        assert!(Price::parse("1.555231", 5).is_err());
+        assert!(Price::parse("1.5a52", 5).is_err());
+        assert!(Price::parse("NaN", 5).is_err());
+    }
+
+    #[test]
+    fn test_price_pips_and_precision() {
+        assert_eq!(price_decimals("USD/EUR"), 5);
+        assert_eq!(price_decimals("USD/JPY"), 3);
+        // 0.1 + 0.2 style float noise must not produce a different level
+        assert_eq!(price(1.5552).add_pips(0.2), price(1.55522));
+        assert_eq!(
+            Price::from_f64(151.23, 3).unwrap().add_pips(1.0),
+            Price::from_f64(151.24, 3).unwrap()
+        );
+        assert_eq!(price(1.5564).diff_ticks(price(1.5558)), 60);
+    }
+
+    #[test]
+    fn test_price_rejects_non_finite_values_and_saturates() {
+        assert!(Price::from_f64(f64::NAN, 5).is_err());
+        assert!(Price::from_f64(f64::INFINITY, 5).is_err());
+        assert!(Price::from_f64(f64::NEG_INFINITY, 3).is_err());
+        assert!(Price::from_f64(1e300, 5).is_err());
+        assert_eq!(
+            Price::new(i64::MAX - 1, 5).add_ticks(10),
+            Price::new(i64::MAX, 5)
+        );
+        assert_eq!(
+            Price::new(i64::MIN + 1, 5).add_ticks(-10),
+            Price::new(i64::MIN, 5)
+        );
+    }
+
+    #[test]
+    fn test_book_side_keeps_levels_in_price_order() {
+        let mut buy_book = BookSide::new("Buy");
+        buy_book.add("MS", 1, price(1.5553));
+        buy_book.add("UBS", 2, price(1.5555));
+        buy_book.add("CITI", 3, price(1.5553));
+        buy_book.add("MS", 4, price(1.5554));
+        let prices: Vec<Price> = buy_book.iter().map(|entry| entry.price).collect();
+        assert_eq!(prices, vec![price(1.5555), price(1.5554), price(1.5553)]);
+        assert_eq!(buy_book.level(price(1.5553)).unwrap().volume, 4);
+
+        // withdrawing MS leaves CITI alone at its level and removes the level only MS quoted
+        buy_book.remove_lp("MS");
+        assert_eq!(buy_book.len(), 2);
+        assert_eq!(
+            buy_book.level(price(1.5553)).unwrap().lp_vol,
+            vec![(String::from("CITI"), 3)]
+        );
+        assert!(buy_book.level(price(1.5554)).is_none());
+
+        // a partial fill leaves the quote in the book, filling the rest withdraws it
+        buy_book.fill("UBS", price(1.5555), 1);
+        assert_eq!(buy_book.first().unwrap().volume, 1);
+        buy_book.fill("UBS", price(1.5555), 1);
+        assert_eq!(buy_book.first().unwrap().price, price(1.5553));
+        assert_eq!(buy_book.pop_first().unwrap().volume, 3);
+        assert!(buy_book.is_empty());
+
+        // the offers are best first from the lowest price
+        let sell_book = BookSide::from_entries(
+            "Sell",
+            [1.5565, 1.5563, 1.5564].map(|value| FxAggBookEntry {
+                lp_vol: vec![(String::from("MS"), 1)],
+                volume: 1,
+                price: price(value),
+                side: String::from("Sell"),
+            }),
+        );
+        let prices: Vec<Price> = sell_book.iter().map(|entry| entry.price).collect();
+        assert_eq!(prices, vec![price(1.5563), price(1.5564), price(1.5565)]);
+    }
+
+    #[test]
+    fn test_book_matches_latest_quotes_of_many_lps() {
+        // after every update from 20 liquidity providers the book holds exactly the volume of
+        // each liquidity provider's latest quote at each price
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut fx_book = FxBook::new(
+            "USD/EUR",
+            &BookPolicyConfig::new(CrossedBookPolicy::Allow, None),
+        );
+        let mut latest: std::collections::BTreeMap<String, QuoteUpdate> = Default::default();
+        for _ in 0..2000 {
+            let liquidity_provider = format!("LP{}", rng.random_range(1..=20));
+            let mid = 155500 + rng.random_range(-20..=20);
+            let tiers = rng.random_range(0..=4);
+            let tier = |i: i64, sign: i64| QuoteTier {
+                price: Price::new(mid + sign * (5 + 10 * i), 5),
+                volume: i as i32 + 1,
+            };
+            let quote = QuoteUpdate {
+                bids: (0..tiers).map(|i| tier(i, -1)).collect(),
+                offers: (0..tiers).map(|i| tier(i, 1)).collect(),
+                ..quote(&liquidity_provider, 0.0, 0.0)
+            };
+            fx_book.update(quote.clone()).unwrap();
+            latest.insert(liquidity_provider, quote);
+
+            for (side, book_side) in [("Buy", &fx_book.buy_book), ("Sell", &fx_book.sell_book)] {
+                let mut expected: std::collections::BTreeMap<Price, i32> = Default::default();
+                for (quote_side, tier) in latest.values().flat_map(|quote| quote.tiers()) {
+                    if quote_side == side {
+                        *expected.entry(tier.price).or_default() += tier.volume;
+                    }
+                }
+                let mut levels: Vec<(Price, i32)> = expected.into_iter().collect();
+                if side == "Buy" {
+                    levels.reverse();
+                }
+                let book_levels: Vec<(Price, i32)> = book_side
+                    .iter()
+                    .map(|entry| (entry.price, entry.volume))
+                    .collect();
+                assert_eq!(book_levels, levels);
+            }
+        }
+    }
+
+    #[test]
+    fn test_level_events_match_a_diff_of_the_whole_book() {
+        // the level events built from the prices each update touches, including those the book
+        // policies remove, match a diff of every level before and after the update
+        let mut fx_book = FxBook {
+            currency_pair: String::from("USD/EUR"),
+            ..Default::default()
+        };
+        let mut receiver = fx_book.subscribe();
+        let levels = |fx_book: &FxBook| {
+            let mut levels: std::collections::BTreeMap<(String, Price), i32> = Default::default();
+            for entry in fx_book.buy_book.iter().chain(fx_book.sell_book.iter()) {
+                levels.insert((entry.side.clone(), entry.price), entry.volume);
+            }
+            levels
+        };
+        for quote in SyntheticQuotes::new(10, 3, 2.0, 3).take(500) {
+            let before = levels(&fx_book);
+            fx_book.update(quote).unwrap();
+            let mut after = before.clone();
+            for change in book_changes(&mut receiver) {
+                match change {
+                    BookChange::LevelAdded {
+                        side,
+                        price,
+                        volume,
+                    } => assert_eq!(after.insert((side, price), volume), None),
+                    BookChange::LevelChanged {
+                        side,
+                        price,
+                        volume,
+                        previous_volume,
+                    } => {
+                        assert_ne!(volume, previous_volume);
+                        assert_eq!(after.insert((side, price), volume), Some(previous_volume));
+                    }
+                    BookChange::LevelRemoved {
+                        side,
+                        price,
+                        previous_volume,
+                    } => assert_eq!(after.remove(&(side, price)), Some(previous_volume)),
+                    _ => {}
+                }
+            }
+            assert_eq!(after, levels(&fx_book));
+        }
+
+        // a level withdrawn and quoted again at the same volume hasn't changed
+        let mut buy_book = BookSide::new("Buy");
+        buy_book.add("CITI", 1, price(1.5550));
+        buy_book.record_level_changes();
+        buy_book.remove_lp("CITI");
+        buy_book.add("CITI", 1, price(1.5550));
+        buy_book.add("UBS", 2, price(1.5551));
+        assert_eq!(
+            buy_book.take_level_changes(),
+            vec![BookChange::LevelAdded {
+                side: String::from("Buy"),
+                price: price(1.5551),
+                volume: 2,
+            }]
+        );
+        // and nothing is recorded until asked for again
+        buy_book.pop_first();
+        assert!(buy_book.take_level_changes().is_empty());
+    }
+
+    #[test]
+    fn test_synthetic_quotes() {
+        let quotes: Vec<QuoteUpdate> = SyntheticQuotes::new(20, 5, 1.0, 7).take(1000).collect();
+        assert_eq!(
+            quotes,
+            SyntheticQuotes::new(20, 5, 1.0, 7)
+                .take(1000)
+                .collect::<Vec<_>>()
+        );
+        let liquidity_providers: std::collections::BTreeSet<&str> = quotes
+            .iter()
+            .map(|quote| quote.liquidity_provider.as_str())
+            .collect();
+        assert_eq!(liquidity_providers.len(), 20);
+        for quote in &quotes {
+            assert_eq!((quote.bids.len(), quote.offers.len()), (5, 5));
+            // each liquidity provider's own quotes are never crossed and widen with each tier
+            assert!(quote.bids[0].price < quote.offers[0].price);
+            assert!(quote.bids[4].price < quote.bids[0].price);
+            assert!(quote.offers[4].price > quote.offers[0].price);
+        }
+        assert!(SyntheticQuotes::new(0, 5, 1.0, 7).next().is_none());
+    }
+
+    #[test]
+    fn test_write_fxbook_as_ladder() {
+        let mut fx_book = FxBook {
+            currency_pair: String::from("USD/EUR"),
+            ..Default::default()
+        };
+        fx_book.update(quote("CITI", 1.5550, 1.5560)).unwrap();
+        fx_book.update(quote("BARX", 1.5540, 1.5570)).unwrap();
+        let mut ladder: Vec<u8> = Vec::new();
+        aggregator::write_fxbook_as_ladder(&mut ladder, &fx_book).unwrap();
+        let ladder = String::from_utf8(ladder).unwrap();
+        let rows: Vec<&str> = ladder
+            .lines()
+            .filter(|line| line.starts_with("Buy:") || line.starts_with("Sell:"))
+            .collect();
+        // offers from the highest down, then bids from the highest down
+        assert_eq!(
+            rows,
+            vec![
+                "Sell:\t 1.55700\t   1\t\t (BARX: 1)",
+                "Sell:\t 1.55600\t   1\t\t (CITI: 1)",
+                "Buy:\t 1.55500\t   1\t\t (CITI: 1)",
+                "Buy:\t 1.55400\t   1\t\t (BARX: 1)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_latency_histogram_percentiles() {
+        let mut latency = LatencyHistogram::default();
+        assert!(latency.is_empty());
+        for micros in 1..=1000 {
+            latency.record(Duration::from_micros(micros));
+        }
+        assert_eq!(latency.len(), 1000);
+        // values are kept to 3 significant figures
+        let close = |actual: Duration, micros: u64| {
+            let expected = Duration::from_micros(micros).as_secs_f64();
+            (actual.as_secs_f64() - expected).abs() <= expected / 500.0
+        };
+        assert!(close(latency.percentile(50.0), 500));
+        assert!(close(latency.percentile(99.0), 990));
+        assert!(close(latency.max(), 1000));
+        assert!(latency.to_string().starts_with("1000 updates, p50 500."));
+        // latencies too long to track are recorded as the longest trackable
+        latency.record(Duration::from_secs(3600));
+        assert!(latency.max() >= Duration::from_secs(59));
+        latency.reset();
+        assert!(latency.is_empty());
+    }
+
+    #[test]
+    fn test_add_agg_book_entry_merges_exact_prices_only() {
+        let mut fx_book = FxBook {
+            currency_pair: String::from("USD/EUR"),
+            buy_book: BookSide::new("Buy"),
+            sell_book: BookSide::new("Sell"),
+            timestamp: 1753440851702449924,
+            ..Default::default()
+        };
+
+        aggregator::add_agg_book_entry(&mut fx_book, "MS", 1, price(1.55521), "Buy");
+        aggregator::add_agg_book_entry(&mut fx_book, "UBS", 1, price(1.55522), "Buy");
+        aggregator::add_agg_book_entry(
+            &mut fx_book,
+            "CITI",
+            3,
+            Price::parse("1.55521", 5).unwrap(),
+            "Buy",
+        );
+
+        // the higher bid is the best level, the merged level is behind it
+        assert_eq!(fx_book.buy_book.len(), 2);
+        assert_eq!(fx_book.buy_book.first().unwrap().price, price(1.55522));
+        assert_eq!(
+            fx_book.buy_book.iter().nth(1).unwrap().lp_vol,
+            vec![(String::from("MS"), 1), (String::from("CITI"), 3)]
+        );
+        assert_eq!(fx_book.buy_book.iter().nth(1).unwrap().volume, 4);
+    }
+
+    fn snapshot(liquidity_provider: &str, bid: f64, offer: f64) -> MarketDataSnapshot {
+        MarketDataSnapshot {
+            liquidity_provider: String::from(liquidity_provider),
+            msg_seq_num: 1,
+            sending_time: 1753440851702000000,
+            currency_pair: String::from("USD/EUR"),
+            entries: vec![
+                MdEntry {
+                    entry_id: Some(String::from("B1M")),
+                    side: String::from("Buy"),
+                    price: price(bid),
+                    volume: 1,
                 },
-                FxAggBookEntry {
-                    lp_vol: vec![
-                        (String::from("UBS "), 3),
-                        (String::from("CITI "), 3),
-                        (String::from("BARX "), 3),
-                    ],
-                    volume: 9,
-                    price: 1.5563,
+                MdEntry {
+                    entry_id: Some(String::from("S1M")),
                     side: String::from("Sell"),
-                },
-                FxAggBookEntry {
-                    lp_vol: vec![(String::from("JPMC "), 1)],
+                    price: price(offer),
                     volume: 1,
-                    price: 1.5567,
-                    side: String::from("Sell"),
                 },
             ],
-            timestamp: 1753430617683973406,
+        }
+    }
+
+    fn quote(liquidity_provider: &str, bid: f64, offer: f64) -> QuoteUpdate {
+        QuoteUpdate {
+            liquidity_provider: String::from(liquidity_provider),
+            currency_pair: String::from("USD/EUR"),
+            bids: vec![QuoteTier {
+                price: price(bid),
+                volume: 1,
+            }],
+            offers: vec![QuoteTier {
+                price: price(offer),
+                volume: 1,
+            }],
+            source_time: 1753440851702000000,
+            sequence_number: 1,
+        }
+    }
+
+    #[test]
+    fn test_price_history_steps_at_each_top_of_book_change() {
+        let mut book_manager = BookManager::new(&[], 0.0);
+        let start = 1753440851702000000;
+        let second = 1_000_000_000;
+        for (seconds, liquidity_provider, bid, offer) in [
+            (0, "CITI", 1.1000, 1.1004),
+            (1, "UBS", 1.1001, 1.1005),
+            (2, "CITI", 1.1000, 1.1004),
+        ] {
+            book_manager
+                .update(QuoteUpdate {
+                    source_time: start + seconds * second,
+                    ..quote(liquidity_provider, bid, offer)
+                })
+                .unwrap();
+        }
+        let history = &book_manager.book("USD/EUR").unwrap().history;
+        // CITI's repeated quote leaves the top of the book unchanged
+        assert_eq!(history.top_of_book.len(), 2);
+        assert_eq!(history.lp_quotes["CITI"].len(), 1);
+        assert_eq!(history.lp_quotes["UBS"].len(), 1);
+        assert_eq!(history.latest, start + 2 * second);
+        assert_eq!(history.top_of_book.last().unwrap().mid(), Some(1.10025));
+
+        let bid = |sample: &PriceSample| sample.bid.map(Price::to_f64);
+        let (old_bid, new_bid) = (price(1.1000).to_f64(), price(1.1001).to_f64());
+        assert_eq!(
+            history
+                .top_of_book
+                .steps(history.latest - 10 * second, history.latest, bid),
+            vec![vec![
+                [-2.0, old_bid],
+                [-1.0, old_bid],
+                [-1.0, new_bid],
+                [0.0, new_bid]
+            ]]
+        );
+        // a window starting between changes starts from the price in effect
+        assert_eq!(
+            history
+                .top_of_book
+                .steps(history.latest - second / 2, history.latest, bid),
+            vec![vec![[-0.5, new_bid], [0.0, new_bid]]]
+        );
+    }
+
+    #[test]
+    fn test_price_history_drops_samples_older_than_its_duration() {
+        let mut book_manager = BookManager::new(&[], 0.0);
+        let second = 1_000_000_000;
+        for i in 0..1000 {
+            let bid = if i % 2 == 0 { 1.1000 } else { 1.1001 };
+            book_manager
+                .update(QuoteUpdate {
+                    source_time: i * 2 * second,
+                    ..quote("CITI", bid, 1.1004)
+                })
+                .unwrap();
+        }
+        let history = &book_manager.book("USD/EUR").unwrap().history;
+        let cutoff = history.latest - HISTORY_DURATION.as_nanos() as u64;
+        let top_of_book: Vec<&PriceSample> = history.top_of_book.iter().collect();
+        assert!(top_of_book.len() < 1000);
+        // the price in effect at the start of the history is kept
+        assert!(top_of_book[0].time <= cutoff);
+        assert_eq!(history.lp_quotes["CITI"].len(), top_of_book.len());
+    }
+
+    #[test]
+    fn test_price_history_samples_expired_quotes_and_fills() {
+        let mut configs = vec![config("CITI", false), config("BARX", false)];
+        configs[0].quote_ttl = Some(Duration::from_secs(1));
+        let mut book_manager = BookManager::new(&configs, aggregator::MIN_SPREAD_PIPS);
+        book_manager.update(quote("CITI", 1.5552, 1.5562)).unwrap();
+        book_manager.update(quote("BARX", 1.5551, 1.5563)).unwrap();
+        let start = quote("CITI", 1.5552, 1.5562).source_time;
+        let second = 1_000_000_000;
+
+        // CITI's quotes expire, moving the top of the book to BARX's and breaking CITI's line
+        book_manager.expire_quotes(start + 2 * second);
+        let history = &book_manager.book("USD/EUR").unwrap().history;
+        assert_eq!(history.latest, start + 2 * second);
+        assert_eq!(
+            history.top_of_book.last(),
+            Some(&PriceSample {
+                time: start + 2 * second,
+                bid: Some(price(1.5551)),
+                offer: Some(price(1.5563)),
+            })
+        );
+        assert_eq!(history.lp_quotes["CITI"].last().unwrap().bid, None);
+        assert_eq!(
+            history.lp_quotes["BARX"].last().unwrap().bid,
+            Some(price(1.5551))
+        );
+
+        // filling BARX's bid empties the buy side
+        book_manager
+            .execute(&Order::market("USD/EUR", Side::Sell, 1))
+            .unwrap();
+        let history = &book_manager.book("USD/EUR").unwrap().history;
+        assert_eq!(history.top_of_book.len(), 3);
+        assert_eq!(
+            history.top_of_book.last(),
+            Some(&PriceSample {
+                time: start + 2 * second,
+                bid: None,
+                offer: Some(price(1.5563)),
+            })
+        );
+    }
+
+    #[test]
+    fn test_fix_snapshot_round_trip() {
+        let message = snapshot("CITI", 1.5552, 1.5562).encode();
+
+        assert!(message.starts_with("8=FIX.4.4\x019="));
+        assert!(message.contains(
+            "\x0135=W\x0149=CITI\x0156=FXSIMAGG\x0134=1\x0152=20250725-10:54:11.702\x01"
+        ));
+        assert!(
+            message.contains(
+                "\x01268=2\x01269=0\x01278=B1M\x01270=1.55520\x01271=1000000\x01269=1\x01"
+            )
+        );
+        assert_eq!(
+            MarketData::decode(&message).unwrap(),
+            MarketData::Snapshot(snapshot("CITI", 1.5552, 1.5562))
+        );
+    }
+
+    #[test]
+    fn test_fix_rejects_bad_checksum_and_body_length() {
+        let message = snapshot("CITI", 1.5552, 1.5562).encode();
+        let tampered = message.replace("270=1.55520", "270=1.55530");
+        assert!(matches!(
+            MarketData::decode(&tampered),
+            Err(AppError::Fix(_))
+        ));
+        let truncated = message.replace("\x0156=FXSIMAGG", "");
+        assert!(matches!(
+            MarketData::decode(&truncated),
+            Err(AppError::Fix(_))
+        ));
+    }
+
+    #[test]
+    fn test_fix_rejects_invalid_entry_sizes() {
+        // reframe the message with the size of its first entry replaced, keeping the body
+        // length and checksum valid
+        let message = snapshot("CITI", 1.5552, 1.5562).encode();
+        let body_start = message.find("\x0135=").unwrap() + 1;
+        let body_end = message.find("10=").unwrap();
+        let with_size = |size: &str| {
+            fix::frame_message(&message[body_start..body_end].replacen(
+                "271=1000000",
+                &format!("271={size}"),
+                1,
+            ))
+        };
+        assert!(MarketData::decode(&with_size("2000000")).is_ok());
+        for size in [
+            "500000",
+            "1500000",
+            "0",
+            "-1000000",
+            "3000000000000000",
+            "99999999999999999999",
+        ] {
+            assert!(
+                matches!(MarketData::decode(&with_size(size)), Err(AppError::Fix(_))),
+                "size {size} accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn test_update_from_fix_message() {
+        let mut fx_book = FxBook {
+            currency_pair: String::from("USD/EUR"),
+            ..Default::default()
+        };
+        let mut decoder = QuoteDecoder::default();
+        let citi = decoder.decode(&snapshot("CITI", 1.5552, 1.5562).encode());
+        assert_eq!(citi.as_ref().unwrap(), &quote("CITI", 1.5552, 1.5562));
+        fx_book.update(citi.unwrap()).unwrap();
+        let ubs = decoder.decode(&snapshot("UBS", 1.5552, 1.5563).encode());
+        fx_book.update(ubs.unwrap()).unwrap();
+
+        assert_eq!(fx_book.timestamp, 1753440851702000000);
+        assert_eq!(fx_book.buy_book.len(), 1);
+        assert_eq!(fx_book.buy_book.first().unwrap().volume, 2);
+        assert_eq!(fx_book.sell_book.len(), 2);
+        assert_eq!(fx_book.sell_book.first().unwrap().price, price(1.5562));
+    }
+
+    fn md_update(action: UpdateAction, entry_id: &str, side: &str, value: f64) -> MdUpdate {
+        MdUpdate {
+            action,
+            entry: MdEntry {
+                entry_id: Some(String::from(entry_id)),
+                side: String::from(side),
+                price: price(value),
+                volume: 1,
+            },
+        }
+    }
+
+    fn incremental(liquidity_provider: &str, updates: Vec<MdUpdate>) -> MarketDataIncremental {
+        MarketDataIncremental {
+            liquidity_provider: String::from(liquidity_provider),
+            msg_seq_num: 2,
+            sending_time: 1753440852702000000,
+            currency_pair: String::from("USD/EUR"),
+            updates,
+        }
+    }
+
+    #[test]
+    fn test_fix_incremental_round_trip() {
+        let message = incremental(
+            "CITI",
+            vec![
+                md_update(UpdateAction::Change, "B1M", "Buy", 1.5553),
+                md_update(UpdateAction::Delete, "S1M", "Sell", 0.0),
+            ],
+        )
+        .encode();
+
+        assert!(message.contains("\x0135=X\x01"));
+        assert!(message.contains("\x01279=1\x0155=USD/EUR\x01269=0\x01278=B1M\x01270=1.55530\x01"));
+        assert!(message.contains("\x01279=2\x0155=USD/EUR\x01269=1\x01278=S1M\x0110="));
+        let decoded = MarketData::decode(&message).unwrap();
+        let MarketData::Incremental(decoded) = decoded else {
+            panic!("expected an incremental refresh");
         };
-        let fx_book_side = aggregator::get_book_side(&mut fx_book, "Buy");
         assert_eq!(
-            aggregator::find_buy_index_when_crossed(fx_book_side, 1.5565),
-            Some(1)
+            decoded.updates[0],
+            md_update(UpdateAction::Change, "B1M", "Buy", 1.5553)
+        );
+        assert_eq!(decoded.updates[1].action, UpdateAction::Delete);
+        assert_eq!(decoded.updates[1].entry.entry_id, Some(String::from("S1M")));
+    }
+
+    #[test]
+    fn test_update_from_incremental_message() {
+        let mut fx_book = FxBook::default();
+        let mut decoder = QuoteDecoder::default();
+        for message in [
+            snapshot("CITI", 1.5552, 1.5562).encode(),
+            snapshot("UBS", 1.5551, 1.5563).encode(),
+        ] {
+            fx_book.update(decoder.decode(&message).unwrap()).unwrap();
+        }
+
+        // CITI moves its bid and withdraws its offer
+        let message = incremental(
+            "CITI",
+            vec![
+                md_update(UpdateAction::Change, "B1M", "Buy", 1.5550),
+                md_update(UpdateAction::Delete, "S1M", "Sell", 0.0),
+            ],
+        );
+        let citi = decoder.decode(&message.encode()).unwrap();
+        assert!(citi.offers.is_empty());
+        fx_book.update(citi).unwrap();
+
+        assert_eq!(fx_book.buy_book.len(), 2);
+        assert_eq!(fx_book.buy_book.first().unwrap().price, price(1.5551));
+        assert_eq!(
+            fx_book.buy_book.iter().nth(1).unwrap().lp_vol,
+            vec![(String::from("CITI"), 1)]
+        );
+        assert_eq!(fx_book.sell_book.len(), 1);
+        assert_eq!(
+            fx_book.sell_book.first().unwrap().lp_vol,
+            vec![(String::from("UBS"), 1)]
+        );
+
+        // and then sends a new offer
+        let message = incremental(
+            "CITI",
+            vec![md_update(UpdateAction::New, "S1M", "Sell", 1.5561)],
+        );
+        fx_book
+            .update(decoder.decode(&message.encode()).unwrap())
+            .unwrap();
+        assert_eq!(fx_book.sell_book.first().unwrap().price, price(1.5561));
+        assert_eq!(
+            fx_book.sell_book.first().unwrap().lp_vol,
+            vec![(String::from("CITI"), 1)]
+        );
+    }
+
+    #[test]
+    fn test_book_side_tier_index_follows_the_levels() {
+        let mut buy_book = BookSide::new("Buy");
+        buy_book.add_at("CITI", 1, price(1.5550), 10);
+        buy_book.add_at("CITI", 3, price(1.5549), 10);
+        buy_book.add_at("UBS", 1, price(1.5550), 20);
+
+        // a quote still sent at the same price and volume keeps its time, the others are replaced
+        let tiers = [
+            QuoteTier {
+                price: price(1.5550),
+                volume: 1,
+            },
+            QuoteTier {
+                price: price(1.5548),
+                volume: 3,
+            },
+        ];
+        buy_book.replace_lp_tiers("CITI", &tiers, 30);
+        assert_eq!(buy_book.tier_time("CITI", price(1.5550), 1), Some(10));
+        assert_eq!(buy_book.tier_time("CITI", price(1.5548), 3), Some(30));
+        assert!(buy_book.level(price(1.5549)).is_none());
+
+        // levels dropped by a book policy or filled are dropped from the index too
+        buy_book.pop_first();
+        assert!(buy_book.lp_tiers("UBS").is_empty());
+        assert_eq!(
+            buy_book.lp_tiers("CITI"),
+            [LpTier {
+                price: price(1.5548),
+                volume: 3,
+                time: 30,
+            }]
+        );
+        buy_book.fill("CITI", price(1.5548), 1);
+        assert_eq!(buy_book.tier_time("CITI", price(1.5548), 2), Some(30));
+        buy_book.retain(|_| false);
+        assert!(buy_book.lp_tiers("CITI").is_empty());
+
+        // a quote removed to keep the minimum spread is added again when it is sent again
+        let mut fx_book = FxBook::default();
+        fx_book.update(quote("CITI", 1.5550, 1.5560)).unwrap();
+        fx_book.update(quote("UBS", 1.5556, 1.5566)).unwrap();
+        assert_eq!(fx_book.buy_book.first().unwrap().price, price(1.5550));
+        fx_book.update(quote("CITI", 1.5540, 1.5570)).unwrap();
+        fx_book.update(quote("UBS", 1.5556, 1.5566)).unwrap();
+        assert_eq!(fx_book.buy_book.first().unwrap().price, price(1.5556));
+    }
+
+    #[test]
+    fn test_incremental_leaves_untouched_tiers_in_place() {
+        let mut fx_book = FxBook {
+            currency_pair: String::from("USD/EUR"),
+            ..Default::default()
+        };
+        let mut decoder = QuoteDecoder::default();
+        let entry = |entry_id: &str, side: &str, value: f64, volume: i32| MdEntry {
+            entry_id: Some(String::from(entry_id)),
+            side: String::from(side),
+            price: price(value),
+            volume,
+        };
+        let mut citi = snapshot("CITI", 1.5550, 1.5560);
+        citi.entries.push(entry("B3M", "Buy", 1.5549, 3));
+        citi.entries.push(entry("S3M", "Sell", 1.5561, 3));
+        let mut ubs = snapshot("UBS", 1.5549, 1.5562);
+        ubs.sending_time += 500_000_000;
+        for message in [citi.encode(), ubs.encode()] {
+            fx_book.update(decoder.decode(&message).unwrap()).unwrap();
+        }
+        let tier_time = |fx_book: &FxBook, side, value, volume| {
+            let book_side = if side == "Buy" {
+                &fx_book.buy_book
+            } else {
+                &fx_book.sell_book
+            };
+            book_side.tier_time("CITI", price(value), volume).unwrap()
+        };
+
+        // CITI's incremental only moves its 1M bid, its 3M bid stays ahead of UBS's bid at 1.5549
+        let mut receiver = fx_book.subscribe();
+        let message = incremental(
+            "CITI",
+            vec![md_update(UpdateAction::Change, "B1M", "Buy", 1.5551)],
+        );
+        fx_book
+            .update(decoder.decode(&message.encode()).unwrap())
+            .unwrap();
+        assert_eq!(
+            fx_book.buy_book.level(price(1.5549)).unwrap().lp_vol,
+            vec![(String::from("CITI"), 3), (String::from("UBS"), 1)]
+        );
+        assert_eq!(fx_book.buy_book.first().unwrap().price, price(1.5551));
+        assert!(fx_book.buy_book.level(price(1.5550)).is_none());
+        let offers: Vec<(Price, i32)> = fx_book
+            .sell_book
+            .iter()
+            .map(|entry| (entry.price, entry.volume))
+            .collect();
+        assert_eq!(
+            offers,
+            vec![(price(1.5560), 1), (price(1.5561), 3), (price(1.5562), 1)]
+        );
+
+        // only the moved tier's time and levels change
+        assert_eq!(tier_time(&fx_book, "Buy", 1.5551, 1), message.sending_time);
+        for (side, value, volume) in [("Buy", 1.5549, 3), ("Sell", 1.5560, 1), ("Sell", 1.5561, 3)]
+        {
+            assert_eq!(tier_time(&fx_book, side, value, volume), citi.sending_time);
+        }
+        assert_eq!(fx_book.quote_times["CITI"], message.sending_time);
+        let changes = book_changes(&mut receiver);
+        assert_eq!(
+            changes[1..3],
+            [
+                BookChange::LevelRemoved {
+                    side: String::from("Buy"),
+                    price: price(1.5550),
+                    previous_volume: 1,
+                },
+                BookChange::LevelAdded {
+                    side: String::from("Buy"),
+                    price: price(1.5551),
+                    volume: 1,
+                },
+            ]
         );
+        assert_eq!(changes.len(), 4);
+    }
+
+    #[test]
+    fn test_get_md_updates() {
+        let sent_entries = snapshot("CITI", 1.5552, 1.5562).entries;
+        let mut entries = snapshot("CITI", 1.5553, 1.5562).entries;
+        entries[1].volume = 3;
+
+        let updates = fix::get_md_updates(&sent_entries, &entries);
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[0].action, UpdateAction::Change);
+        assert_eq!(updates[1].action, UpdateAction::Change);
+
+        let updates = fix::get_md_updates(&sent_entries, &sent_entries);
+        assert!(updates.is_empty());
+
+        // a withdrawn offer is deleted and a new offer is added
+        let updates = fix::get_md_updates(&sent_entries, &entries[..1]);
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[0].action, UpdateAction::Change);
+        assert_eq!(updates[1].action, UpdateAction::Delete);
+        assert_eq!(updates[1].entry.entry_id, Some(String::from("S1M")));
+        let updates = fix::get_md_updates(&sent_entries[..1], &sent_entries);
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].action, UpdateAction::New);
+    }
+
+    #[test]
+    fn test_quote_encoder_sends_incremental_refreshes() {
+        let mut encoder = QuoteEncoder::new(&[config("MS", true)]);
+        let mut decoder = QuoteDecoder::default();
+        let first = quote("MS", 1.5552, 1.5562);
+        let Some(MarketData::Snapshot(_)) = encoder.encode(&first) else {
+            panic!("expected a snapshot");
+        };
+        assert!(encoder.encode(&first).is_none());
+
+        // the MS offer is withdrawn
+        let mut second = quote("MS", 1.5553, 1.5562);
+        second.offers.clear();
+        second.sequence_number = 2;
+        let Some(MarketData::Incremental(incremental)) = encoder.encode(&second) else {
+            panic!("expected an incremental refresh");
+        };
+        assert_eq!(incremental.msg_seq_num, 2);
+        assert_eq!(incremental.updates.len(), 2);
+        decoder.decode(&encoder_snapshot(&first)).unwrap();
+        assert_eq!(
+            decoder
+                .decode(&MarketData::Incremental(incremental).encode())
+                .unwrap(),
+            second
+        );
+
+        // liquidity providers not sending incremental refreshes always send a snapshot
+        let mut encoder = QuoteEncoder::new(&[config("MS", false)]);
+        encoder.encode(&first);
+        assert!(matches!(
+            encoder.encode(&second),
+            Some(MarketData::Snapshot(_))
+        ));
+    }
+
+    fn encoder_snapshot(quote: &QuoteUpdate) -> String {
+        QuoteEncoder::default().encode(quote).unwrap().encode()
+    }
+
+    fn config(liquidity_provider: &str, incremental: bool) -> simulator::Config {
+        simulator::Config {
+            liquidity_provider: String::from(liquidity_provider),
+            currency_pair: String::from("USD/EUR"),
+            buy_price: price(1.5552),
+            spread: 100,
+            tiers: simulator::legacy_tiers(1.0, 2.0),
+            run_iterations: 20,
+            incremental,
+            seed: None,
+            price_model: PriceModelConfig::default(),
+            quote_ttl: None,
+            last_look: LastLookConfig::default(),
+        }
+    }
+
+    #[test]
+    fn test_parse_tiers() {
+        let tiers = simulator::parse_tiers("1:0:0 2:0.5:0.5 5:1:1.5 10:2:2 25:4:4").unwrap();
+        assert_eq!(tiers.len(), 5);
+        assert_eq!(
+            tiers[2],
+            simulator::Tier {
+                volume: 5,
+                bid_markup: 10,
+                offer_markup: 15,
+            }
+        );
+        assert!(simulator::validate_tiers("CITI", &tiers).is_ok());
+
+        assert!(simulator::parse_tiers("1:0").is_err());
+        assert!(simulator::parse_tiers("1:x:0").is_err());
+        let duplicated = simulator::parse_tiers("1:0:0 1:1:1").unwrap();
+        assert!(simulator::validate_tiers("CITI", &duplicated).is_err());
+        let zero = simulator::parse_tiers("0:0:0").unwrap();
+        assert!(simulator::validate_tiers("CITI", &zero).is_err());
+        assert!(simulator::validate_tiers("CITI", &[]).is_err());
+    }
+
+    #[test]
+    fn test_configured_tiers_are_quoted() {
+        let mut config = config("CITI", false);
+        config.tiers = simulator::parse_tiers("1:0:0 2:0.5:0.5 5:1:1 10:2:2 25:4:4").unwrap();
+        let mut lp_simulator = simulator::LpSimulator::new(&config, 7);
+        let quote = lp_simulator.next_quote(0).unwrap();
+        let volumes: Vec<i32> = quote.bids.iter().map(|tier| tier.volume).collect();
+        assert_eq!(volumes, vec![1, 2, 5, 10, 25]);
+        assert_eq!(quote.offers.len(), 5);
+        // larger tiers are quoted wider
+        let bid = |i: usize| quote.bids[i].price;
+        let offer = |i: usize| quote.offers[i].price;
+        assert_eq!(bid(0).diff_ticks(bid(4)), 40);
+        assert_eq!(offer(4).diff_ticks(offer(0)), 40);
+
+        let mut fx_book = FxBook::default();
+        fx_book.update(quote).unwrap();
+        let mut book_volumes: Vec<i32> = fx_book.buy_book.iter().map(|e| e.volume).collect();
+        book_volumes.sort();
+        assert_eq!(book_volumes, vec![1, 2, 5, 10, 25]);
+    }
+
+    #[test]
+    fn test_book_manager_routes_by_currency_pair() {
+        let mut configs = vec![config("CITI", false), config("BARX", false)];
+        configs[1].currency_pair = String::from("USD/JPY");
+        let mut book_manager = BookManager::new(&configs, aggregator::MIN_SPREAD_PIPS);
+        assert_eq!(book_manager.currency_pairs(), vec!["USD/EUR", "USD/JPY"]);
+
+        let fx_book = book_manager.update(quote("CITI", 1.5552, 1.5562)).unwrap();
+        assert_eq!(fx_book.currency_pair, "USD/EUR");
+        let mut jpy_quote = quote("BARX", 0.0, 0.0);
+        jpy_quote.currency_pair = String::from("USD/JPY");
+        jpy_quote.bids[0].price = Price::from_f64(151.25, 3).unwrap();
+        jpy_quote.offers[0].price = Price::from_f64(151.35, 3).unwrap();
+        book_manager.update(jpy_quote).unwrap();
+
+        let usd_eur = book_manager.book("USD/EUR").unwrap();
+        assert_eq!(usd_eur.buy_book.len(), 1);
+        assert_eq!(usd_eur.buy_book.first().unwrap().lp_vol[0].0, "CITI");
+        let usd_jpy = book_manager.book("USD/JPY").unwrap();
+        assert_eq!(usd_jpy.buy_book.len(), 1);
+        assert_eq!(
+            usd_jpy.sell_book.first().unwrap().price.to_string(),
+            "151.350"
+        );
+        assert_eq!(usd_jpy.buy_book.first().unwrap().lp_vol[0].0, "BARX");
+
+        // a book is created for a currency pair that is not in the config
+        let mut gbp_quote = quote("UBS", 1.2710, 1.2720);
+        gbp_quote.currency_pair = String::from("GBP/USD");
+        book_manager.update(gbp_quote).unwrap();
+        assert_eq!(book_manager.currency_pairs().len(), 3);
+        assert_eq!(book_manager.book("USD/EUR").unwrap().buy_book.len(), 1);
+    }
+
+    fn book_prices(fx_book: &FxBook) -> (Vec<Price>, Vec<Price>) {
+        (
+            fx_book.buy_book.iter().map(|entry| entry.price).collect(),
+            fx_book.sell_book.iter().map(|entry| entry.price).collect(),
+        )
+    }
+
+    #[test]
+    fn test_book_policies_handle_crossed_quotes() {
+        // CITI's stale quote of 1.5570/1.5580 is crossed by BARX quoting 1.5550/1.5560
+        let crossed_book = |crossed: CrossedBookPolicy| {
+            let book_policies = [(
+                String::from("USD/EUR"),
+                BookPolicyConfig::new(crossed, None),
+            )]
+            .into();
+            let mut book_manager =
+                BookManager::new(&[config("CITI", false)], 0.0).with_book_policies(&book_policies);
+            book_manager.update(quote("CITI", 1.5570, 1.5580)).unwrap();
+            book_manager.update(quote("BARX", 1.5550, 1.5560)).unwrap();
+            let fx_book = book_manager.book("USD/EUR").unwrap();
+            (book_prices(fx_book), fx_book.crossed)
+        };
+
+        let uncrossed = |buys: &[f64], sells: &[f64]| {
+            let prices = |values: &[f64]| values.iter().map(|value| price(*value)).collect();
+            ((prices(buys), prices(sells)), false)
+        };
+        assert_eq!(
+            crossed_book(CrossedBookPolicy::LongestSide),
+            uncrossed(&[1.5570, 1.5550], &[1.5580])
+        );
+        assert_eq!(
+            crossed_book(CrossedBookPolicy::Allow),
+            (
+                (
+                    vec![price(1.5570), price(1.5550)],
+                    vec![price(1.5560), price(1.5580)]
+                ),
+                true
+            )
+        );
+        assert_eq!(
+            crossed_book(CrossedBookPolicy::DropOlder),
+            uncrossed(&[1.5550], &[1.5560, 1.5580])
+        );
+        assert_eq!(
+            crossed_book(CrossedBookPolicy::DropOffending),
+            uncrossed(&[1.5570, 1.5550], &[1.5580])
+        );
+        assert_eq!(
+            crossed_book(CrossedBookPolicy::Midpoint),
+            uncrossed(&[1.5550], &[1.5580])
+        );
+
+        // books without a policy of their own keep the book manager's minimum spread, a 1 pip
+        // spread survives the 0.5 pip minimum of USD/EUR but not the 6 pip default of GBP/USD
+        let book_policies = [(
+            String::from("USD/EUR"),
+            BookPolicyConfig::new(CrossedBookPolicy::LongestSide, Some(0.5)),
+        )]
+        .into();
+        let mut book_manager =
+            BookManager::new(&[], aggregator::MIN_SPREAD_PIPS).with_book_policies(&book_policies);
+        book_manager.update(quote("CITI", 1.5550, 1.5551)).unwrap();
+        let mut gbp_quote = quote("CITI", 1.2710, 1.2711);
+        gbp_quote.currency_pair = String::from("GBP/USD");
+        book_manager.update(gbp_quote).unwrap();
+        assert_eq!(book_manager.book("USD/EUR").unwrap().buy_book.len(), 1);
+        assert!(book_manager.book("GBP/USD").unwrap().buy_book.is_empty());
+    }
+
+    fn book_changes(receiver: &mut tokio::sync::broadcast::Receiver<BookEvent>) -> Vec<BookChange> {
+        let mut changes: Vec<BookChange> = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            assert_eq!(event.currency_pair, "USD/EUR");
+            changes.push(event.change);
+        }
+        changes
+    }
+
+    #[test]
+    fn test_book_events_are_broadcast_to_subscribers() {
+        let mut book_manager = BookManager::new(&[config("CITI", false)], 0.0);
+        let mut receiver = book_manager.subscribe();
+        let level = |side: &str, value: f64, volume: i32| BookChange::LevelAdded {
+            side: String::from(side),
+            price: price(value),
+            volume,
+        };
+        let quote_replaced = |liquidity_provider: &str| BookChange::QuoteReplaced {
+            liquidity_provider: String::from(liquidity_provider),
+            sequence_number: 1,
+            source_time: 1753440851702000000,
+        };
+
+        book_manager.update(quote("CITI", 1.5550, 1.5560)).unwrap();
+        assert_eq!(
+            book_changes(&mut receiver),
+            vec![
+                quote_replaced("CITI"),
+                level("Buy", 1.5550, 1),
+                level("Sell", 1.5560, 1),
+                BookChange::TopOfBookChanged {
+                    bid: Some((price(1.5550), 1)),
+                    offer: Some((price(1.5560), 1)),
+                },
+            ]
+        );
+
+        // joining CITI's bid changes the level, BARX's offer behind CITI's leaves the top offer
+        book_manager.update(quote("BARX", 1.5550, 1.5565)).unwrap();
+        assert_eq!(
+            book_changes(&mut receiver),
+            vec![
+                quote_replaced("BARX"),
+                BookChange::LevelChanged {
+                    side: String::from("Buy"),
+                    price: price(1.5550),
+                    volume: 2,
+                    previous_volume: 1,
+                },
+                level("Sell", 1.5565, 1),
+                BookChange::TopOfBookChanged {
+                    bid: Some((price(1.5550), 2)),
+                    offer: Some((price(1.5560), 1)),
+                },
+            ]
+        );
+
+        // BARX crossing CITI's offer is corrected by the book policy
+        book_manager.update(quote("BARX", 1.5565, 1.5575)).unwrap();
+        let changes = book_changes(&mut receiver);
+        assert_eq!(
+            changes[1],
+            BookChange::CrossedBookCorrected {
+                bid: price(1.5565),
+                offer: price(1.5560),
+            }
+        );
+        assert!(changes.contains(&BookChange::LevelRemoved {
+            side: String::from("Sell"),
+            price: price(1.5560),
+            previous_volume: 1,
+        }));
+        assert_eq!(
+            changes.last(),
+            Some(&BookChange::TopOfBookChanged {
+                bid: Some((price(1.5565), 1)),
+                offer: Some((price(1.5575), 1)),
+            })
+        );
+
+        // orders taking liquidity out of the book are published too
+        book_manager
+            .execute(&Order::market("USD/EUR", Side::Buy, 1))
+            .unwrap();
+        assert_eq!(
+            book_changes(&mut receiver),
+            vec![
+                BookChange::LevelRemoved {
+                    side: String::from("Sell"),
+                    price: price(1.5575),
+                    previous_volume: 1,
+                },
+                BookChange::TopOfBookChanged {
+                    bid: Some((price(1.5565), 1)),
+                    offer: None,
+                },
+            ]
+        );
+
+        // a book's own subscribers see the top of the book removed to keep the minimum spread
+        let mut fx_book = FxBook {
+            currency_pair: String::from("USD/EUR"),
+            ..Default::default()
+        };
+        let mut receiver = fx_book.subscribe();
+        fx_book.update(quote("CITI", 1.5550, 1.5552)).unwrap();
+        assert_eq!(
+            book_changes(&mut receiver)[1],
+            BookChange::SpreadEnforced {
+                min_spread: 60,
+                spread: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_snapshots_publish_books_and_positions() {
+        let mut book_manager = BookManager::new(&[config("CITI", false)], 0.0);
+        let mut position_keeper = PositionKeeper::default();
+        position_keeper.add_trade(&trade(Side::Buy, 2));
+        let snapshots = Snapshots::new(&book_manager, &position_keeper);
+        assert_eq!(snapshots.currency_pairs(), vec![String::from("USD/EUR")]);
+        assert!(snapshots.book("USD/EUR").unwrap().buy_book.is_empty());
+        assert!(snapshots.book("GBP/USD").is_none());
+
+        // a reader holding a snapshot keeps it unchanged while the writer publishes new ones
+        let held = snapshots.book("USD/EUR").unwrap();
+        let fx_book = book_manager.update(quote("CITI", 1.5550, 1.5560)).unwrap();
+        position_keeper.mark_to_market(fx_book);
+        snapshots.publish_book(fx_book);
+        snapshots.publish_positions(&position_keeper);
+        assert!(held.buy_book.is_empty());
+        let latest = snapshots.book("USD/EUR").unwrap();
+        assert_eq!(latest.buy_book.first().unwrap().price, price(1.5550));
+        assert_eq!(latest.sell_book.first().unwrap().price, price(1.5560));
+        let position = snapshots.positions().position("USD/EUR").cloned().unwrap();
+        assert_eq!(position.net_volume, 2);
+        assert_close(position.mark_price.unwrap(), 1.5555);
+
+        // readers on other threads load snapshots while the writer keeps publishing
+        let snapshots = std::sync::Arc::new(snapshots);
+        let reader = {
+            let snapshots = std::sync::Arc::clone(&snapshots);
+            std::thread::spawn(move || {
+                (0..1000)
+                    .filter_map(|_| snapshots.book("USD/EUR"))
+                    .all(|fx_book| fx_book.buy_book.len() == 1)
+            })
+        };
+        for i in 0..1000 {
+            let bid = 1.5550 + i as f64 * 0.0001;
+            let fx_book = book_manager
+                .update(quote("CITI", bid, bid + 0.0010))
+                .unwrap();
+            snapshots.publish_book(fx_book);
+        }
+        assert!(reader.join().unwrap());
+        assert_eq!(
+            snapshots
+                .book("USD/EUR")
+                .unwrap()
+                .buy_book
+                .first()
+                .unwrap()
+                .price,
+            price(1.5550 + 999.0 * 0.0001)
+        );
+    }
+
+    #[test]
+    fn test_pending_snapshots_publish_changed_books_once_due() {
+        let mut book_manager = BookManager::new(&[config("CITI", false)], 0.0);
+        let mut position_keeper = PositionKeeper::default();
+        position_keeper.add_trade(&trade(Side::Buy, 2));
+        let snapshots = Snapshots::new(&book_manager, &position_keeper);
+        let mut pending = PendingSnapshots::new(Duration::from_secs(3600));
+        assert!(!pending.publish(&snapshots, &book_manager, &position_keeper));
+
+        // a burst of quote updates waits for the publish interval
+        for bid in [1.5550, 1.5551, 1.5552] {
+            let fx_book = book_manager
+                .update(quote("CITI", bid, bid + 0.0010))
+                .unwrap();
+            position_keeper.mark_to_market(fx_book);
+            pending.book_changed(&fx_book.currency_pair);
+            pending.positions_changed();
+        }
+        assert!(!pending.is_due());
+        assert!(snapshots.book("USD/EUR").unwrap().buy_book.is_empty());
+
+        // and is published as the latest book and positions
+        assert!(pending.publish(&snapshots, &book_manager, &position_keeper));
+        assert!(pending.is_empty());
+        let latest = snapshots.book("USD/EUR").unwrap();
+        assert_eq!(latest.buy_book.first().unwrap().price, price(1.5552));
+        let position = snapshots.positions().position("USD/EUR").cloned().unwrap();
+        assert_close(position.mark_price.unwrap(), 1.5557);
+
+        // changes are due as soon as the interval has passed
+        let mut pending = PendingSnapshots::new(Duration::ZERO);
+        assert!(!pending.is_due());
+        pending.book_changed("USD/EUR");
+        assert!(pending.is_due());
+    }
+
+    #[test]
+    fn test_quote_ttl_expires_silent_lps() {
+        let mut configs = vec![config("CITI", false), config("BARX", false)];
+        configs[0].quote_ttl = Some(Duration::from_secs(1));
+        let mut book_manager = BookManager::new(&configs, aggregator::MIN_SPREAD_PIPS);
+        book_manager.update(quote("CITI", 1.5552, 1.5562)).unwrap();
+        book_manager.update(quote("BARX", 1.5551, 1.5563)).unwrap();
+        let start = quote("CITI", 1.5552, 1.5562).source_time;
+        let second = 1_000_000_000;
+
+        // nothing expires within the time to live
+        assert!(book_manager.expire_quotes(start).is_empty());
+        let usd_eur = book_manager.book("USD/EUR").unwrap();
+        assert_eq!(usd_eur.buy_book.len(), 2);
+        assert!(usd_eur.stale_lps.is_empty());
+
+        // CITI has gone silent, BARX has no time to live so keeps its quotes
+        let expired = book_manager.expire_quotes(start + 2 * second);
+        assert_eq!(
+            expired,
+            vec![(String::from("USD/EUR"), String::from("CITI"))]
+        );
+        let usd_eur = book_manager.book("USD/EUR").unwrap();
+        assert_eq!(usd_eur.buy_book.len(), 1);
+        assert_eq!(usd_eur.sell_book.len(), 1);
+        assert_eq!(
+            usd_eur.buy_book.first().unwrap().lp_vol,
+            vec![(String::from("BARX"), 1)]
+        );
+        assert!(usd_eur.stale_lps.contains("CITI"));
+        // a stale liquidity provider is only reported once
+        assert!(book_manager.expire_quotes(start + 3 * second).is_empty());
+
+        // and is no longer stale once it quotes again
+        book_manager.update(quote("CITI", 1.5552, 1.5562)).unwrap();
+        let usd_eur = book_manager.book("USD/EUR").unwrap();
+        assert!(usd_eur.stale_lps.is_empty());
+        assert_eq!(usd_eur.buy_book.len(), 2);
+    }
+
+    fn order_book() -> BookManager {
+        // offers of CITI and UBS 1M at 1.5562, BARX 3M at 1.5563 and MS 5M at 1.5565 with
+        // bids 20 pips below
+        let mut book_manager = BookManager::new(&[config("CITI", false)], 0.0);
+        for (liquidity_provider, offer, volume) in [
+            ("CITI", 1.5562, 1),
+            ("UBS", 1.5562, 1),
+            ("BARX", 1.5563, 3),
+            ("MS", 1.5565, 5),
+        ] {
+            let mut quote = quote(liquidity_provider, offer - 0.0020, offer);
+            quote.bids[0].volume = volume;
+            quote.offers[0].volume = volume;
+            book_manager.update(quote).unwrap();
+        }
+        book_manager
+    }
+
+    #[test]
+    fn test_market_order_sweeps_levels() {
+        let mut book_manager = order_book();
+        let report = book_manager
+            .execute(&Order::market("USD/EUR", Side::Buy, 4))
+            .unwrap();
+        assert_eq!(report.status, OrderStatus::Filled);
+        assert_eq!(report.filled_volume, 4);
+        let fills: Vec<(&str, Price, i32)> = report
+            .fills
+            .iter()
+            .map(|fill| (fill.liquidity_provider.as_str(), fill.price, fill.volume))
+            .collect();
+        assert_eq!(
+            fills,
+            vec![
+                ("CITI", price(1.5562), 1),
+                ("UBS", price(1.5562), 1),
+                ("BARX", price(1.5563), 2),
+            ]
+        );
+        assert!((report.average_price.unwrap() - 1.55625).abs() < 1e-9);
+
+        // the filled volume is taken out of the book
+        let usd_eur = book_manager.book("USD/EUR").unwrap();
+        assert_eq!(usd_eur.sell_book.first().unwrap().price, price(1.5563));
+        assert_eq!(
+            usd_eur.sell_book.first().unwrap().lp_vol,
+            vec![(String::from("BARX"), 1)]
+        );
+        assert_eq!(usd_eur.sell_book.first().unwrap().volume, 1);
+
+        // sell orders are filled from the bids
+        let report = book_manager
+            .execute(&Order::market("USD/EUR", Side::Sell, 2))
+            .unwrap();
+        assert_eq!(report.fills[0].liquidity_provider, "MS");
+        assert_eq!(report.fills[0].price, price(1.5545));
+    }
+
+    #[test]
+    fn test_limit_ioc_and_fok_orders() {
+        let mut book_manager = order_book();
+        // a limit order stops at its limit price and cancels the rest
+        let report = book_manager
+            .execute(&Order::limit("USD/EUR", Side::Buy, 10, price(1.5563)))
+            .unwrap();
+        assert_eq!(report.status, OrderStatus::PartiallyFilled);
+        assert_eq!((report.filled_volume, report.cancelled_volume), (5, 5));
+        assert_eq!(book_manager.book("USD/EUR").unwrap().sell_book.len(), 1);
+
+        // a fill or kill order is not filled at all unless it can be filled in full
+        let mut book_manager = order_book();
+        let order = Order::limit("USD/EUR", Side::Buy, 10, price(1.5563)).fill_or_kill();
+        let report = book_manager.execute(&order).unwrap();
+        assert_eq!(report.status, OrderStatus::Cancelled);
+        assert!(report.fills.is_empty());
+        assert_eq!(report.average_price, None);
+        assert_eq!(book_manager.book("USD/EUR").unwrap().sell_book.len(), 3);
+        let order = Order::market("USD/EUR", Side::Buy, 10).fill_or_kill();
+        let report = book_manager.execute(&order).unwrap();
+        assert_eq!(report.status, OrderStatus::Filled);
+        assert_eq!(report.fills.last().unwrap().liquidity_provider, "MS");
+
+        // a limit sell above the best bid is not filled
+        let order = Order::limit("USD/EUR", Side::Sell, 1, price(1.5560));
+        let report = book_manager.execute(&order).unwrap();
+        assert_eq!(report.status, OrderStatus::Cancelled);
+    }
+
+    #[test]
+    fn test_invalid_orders() {
+        let mut book_manager = order_book();
+        assert!(matches!(
+            book_manager.execute(&Order::market("USD/EUR", Side::Buy, 0)),
+            Err(AppError::InvalidOrder(_))
+        ));
+        assert!(matches!(
+            book_manager.execute(&Order::market("GBP/USD", Side::Buy, 1)),
+            Err(AppError::InvalidOrder(_))
+        ));
+        let mut fx_book = FxBook::default();
+        assert!(
+            order::execute_order(&mut fx_book, &Order::market("USD/EUR", Side::Buy, 1)).is_err()
+        );
+    }
+
+    #[test]
+    fn test_book_price_queries() {
+        let book_manager = order_book();
+        let usd_eur = book_manager.book("USD/EUR").unwrap();
+        // buying 7M sweeps CITI and UBS at 1.5562, BARX at 1.5563 and 2M of MS at 1.5565
+        let vwap = usd_eur.sweep_vwap(Side::Buy, 7).unwrap();
+        assert!((vwap - (2.0 * 1.5562 + 3.0 * 1.5563 + 2.0 * 1.5565) / 7.0).abs() < 1e-9);
+        assert!((usd_eur.sweep_vwap(Side::Sell, 5).unwrap() - 1.5545).abs() < 1e-9);
+        assert_eq!(usd_eur.sweep_vwap(Side::Buy, 11), None);
+        assert_eq!(usd_eur.sweep_vwap(Side::Buy, 0), None);
+
+        // a full amount price comes from a single liquidity provider quoting the whole volume
+        assert_eq!(
+            usd_eur.full_amount_price(Side::Buy, 1),
+            Some(("CITI", price(1.5562)))
+        );
+        assert_eq!(
+            usd_eur.full_amount_price(Side::Buy, 3),
+            Some(("BARX", price(1.5563)))
+        );
+        assert_eq!(
+            usd_eur.full_amount_price(Side::Buy, 5),
+            Some(("MS", price(1.5565)))
+        );
+        assert_eq!(usd_eur.full_amount_price(Side::Sell, 6), None);
+
+        assert_eq!(usd_eur.depth_within(Side::Buy, 0.5), 2);
+        assert_eq!(usd_eur.depth_within(Side::Buy, 1.0), 5);
+        assert_eq!(usd_eur.depth_within(Side::Buy, 3.0), 10);
+        assert_eq!(usd_eur.depth_within(Side::Sell, 2.0), 8);
+        assert_eq!(FxBook::default().depth_within(Side::Buy, 1.0), 0);
+        assert!((usd_eur.mid().unwrap() - 1.55535).abs() < 1e-9);
+        assert_eq!(FxBook::default().mid(), None);
+    }
+
+    fn trade_request(
+        liquidity_provider: &str,
+        side: Side,
+        value: f64,
+        volume: i32,
+    ) -> TradeRequest {
+        TradeRequest {
+            liquidity_provider: String::from(liquidity_provider),
+            currency_pair: String::from("USD/EUR"),
+            side,
+            price: price(value),
+            volume,
+        }
+    }
+
+    #[test]
+    fn test_last_look_fills_and_rejects() {
+        let mut configs = vec![config("CITI", false), config("UBS", false)];
+        configs[0].last_look = LastLookConfig::new(0, 0.0, Some(0.5));
+        configs[1].last_look = LastLookConfig::new(0, 1.0, None);
+        let snapshots = Arc::new(Snapshots::default());
+        let desk = LastLookDesk::new(&configs, Some(1), Arc::clone(&snapshots));
+        let mut citi = quote("CITI", 1.5552, 1.5562);
+        citi.offers.push(QuoteTier {
+            price: price(1.5563),
+            volume: 3,
+        });
+        let mut book_manager = BookManager::new(&configs, 0.0);
+        book_manager.update(citi).unwrap();
+        let fx_book = book_manager.update(quote("UBS", 1.5552, 1.5562)).unwrap();
+        snapshots.publish_book(fx_book);
+
+        let responses: Vec<TradeResponse> = crate::run(async {
+            let mut responses = Vec::new();
+            for request in [
+                trade_request("CITI", Side::Buy, 1.5562, 1),
+                // the 3M offer is used for 2M
+                trade_request("CITI", Side::Buy, 1.5563, 2),
+                // more than the largest tier is partially filled
+                trade_request("CITI", Side::Buy, 1.5563, 5),
+                // the 1M offer is 1 pip above the requested price
+                trade_request("CITI", Side::Buy, 1.5561, 1),
+                // a price move in the liquidity provider's favour is filled
+                trade_request("CITI", Side::Sell, 1.5550, 1),
+                trade_request("UBS", Side::Buy, 1.5562, 1),
+            ] {
+                responses.push(desk.request_trade(&request).await.unwrap());
+            }
+            responses
+        });
+        assert_eq!(
+            responses,
+            vec![
+                TradeResponse::Filled {
+                    price: price(1.5562),
+                    volume: 1
+                },
+                TradeResponse::Filled {
+                    price: price(1.5563),
+                    volume: 2
+                },
+                TradeResponse::PartiallyFilled {
+                    price: price(1.5563),
+                    volume: 3,
+                    rejected_volume: 2
+                },
+                TradeResponse::Rejected(RejectReason::PriceMoved(10)),
+                TradeResponse::Filled {
+                    price: price(1.5550),
+                    volume: 1
+                },
+                TradeResponse::Rejected(RejectReason::LastLook),
+            ]
+        );
+
+        // liquidity providers that aren't quoting or configured can't trade
+        let desk = LastLookDesk::new(&configs, Some(1), Arc::new(Snapshots::default()));
+        let request = trade_request("CITI", Side::Buy, 1.5562, 1);
+        let response = crate::run(desk.request_trade(&request));
+        assert_eq!(
+            response.unwrap(),
+            TradeResponse::Rejected(RejectReason::NoQuote)
+        );
+        let request = trade_request("MS", Side::Buy, 1.5562, 1);
+        assert!(crate::run(desk.request_trade(&request)).is_err());
+    }
+
+    #[test]
+    fn test_last_look_rejects_price_moves_during_hold() {
+        let mut configs = vec![config("CITI", false), config("UBS", false)];
+        configs[0].last_look = LastLookConfig::new(50, 0.0, Some(0.5));
+        let snapshots = Arc::new(Snapshots::default());
+        let desk = LastLookDesk::new(&configs, Some(1), Arc::clone(&snapshots));
+        let mut book_manager = BookManager::new(&configs, 0.0);
+        book_manager.update(quote("CITI", 1.5552, 1.5562)).unwrap();
+        let fx_book = book_manager.update(quote("UBS", 1.5552, 1.5563)).unwrap();
+        snapshots.publish_book(fx_book);
+        let execution_report = book_manager
+            .execute(&Order::market("USD/EUR", Side::Buy, 2))
+            .unwrap();
+
+        // CITI's offer moves up 2 pips while the request is held, UBS has no hold
+        let responses = crate::run(async {
+            let moved = async {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                let fx_book = book_manager.update(quote("CITI", 1.5554, 1.5564)).unwrap();
+                snapshots.publish_book(fx_book);
+            };
+            futures::join!(desk.request_fills(&execution_report), moved).0
+        });
+        let responses: Vec<TradeResponse> = responses.into_iter().map(Result::unwrap).collect();
+        assert_eq!(
+            responses,
+            vec![
+                TradeResponse::Rejected(RejectReason::PriceMoved(20)),
+                TradeResponse::Filled {
+                    price: price(1.5563),
+                    volume: 1
+                },
+            ]
+        );
+        let filled: i32 = responses.iter().map(TradeResponse::filled_volume).sum();
+        assert_eq!(filled, 1);
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "{actual} is not close to {expected}"
+        );
+    }
+
+    #[test]
+    fn test_position_average_cost_and_pnl() {
+        let mut position = Position::default();
+        // buying 1M at 1.5560 and 3M at 1.5564 averages 1.5563
+        position.add_trade(Side::Buy, price(1.5560), 1);
+        position.add_trade(Side::Buy, price(1.5564), 3);
+        assert_eq!(position.net_volume, 4);
+        assert_close(position.average_cost.unwrap(), 1.5563);
+        position.mark_to_market(1.5568);
+        assert_close(position.unrealised_pnl, 0.0005 * 4_000_000.0);
+
+        // closing 2M at 1.5573 realises 10 pips on 2M and keeps the average cost
+        position.add_trade(Side::Sell, price(1.5573), 2);
+        assert_eq!(position.net_volume, 2);
+        assert_close(position.average_cost.unwrap(), 1.5563);
+        assert_close(position.realised_pnl, 0.0010 * 2_000_000.0);
+        assert_close(position.unrealised_pnl, 0.0005 * 2_000_000.0);
+
+        // selling 5M at 1.5553 closes the 2M at a loss and opens a 3M short at 1.5553
+        position.add_trade(Side::Sell, price(1.5553), 5);
+        assert_eq!(position.net_volume, -3);
+        assert_close(position.average_cost.unwrap(), 1.5553);
+        assert_close(position.realised_pnl, 2000.0 - 0.0010 * 2_000_000.0);
+        assert_close(position.unrealised_pnl, -0.0015 * 3_000_000.0);
+
+        // buying back the 3M flattens the position
+        position.add_trade(Side::Buy, price(1.5543), 3);
+        assert_eq!(position.net_volume, 0);
+        assert_eq!(position.average_cost, None);
+        assert_close(position.realised_pnl, 0.0010 * 3_000_000.0);
+        assert_close(position.unrealised_pnl, 0.0);
+        assert_close(position.total_pnl(), 3000.0);
+    }
+
+    #[test]
+    fn test_position_keeper_marks_to_book_mid() {
+        let mut book_manager = order_book();
+        let mut position_keeper = PositionKeeper::default();
+        let report = book_manager
+            .execute(&Order::market("USD/EUR", Side::Buy, 3))
+            .unwrap();
+        position_keeper.add_execution_report(&report);
+        position_keeper.add_trade(&Trade {
+            currency_pair: String::from("USD/EUR"),
+            liquidity_provider: String::from("MS"),
+            side: Side::Sell,
+            price: price(1.5545),
+            volume: 1,
+        });
+
+        let usd_eur = position_keeper.position("USD/EUR").unwrap();
+        assert_eq!(usd_eur.net_volume, 2);
+        assert_eq!(usd_eur.mark_price, None);
+        let citi = position_keeper.lp_position("USD/EUR", "CITI").unwrap();
+        assert_eq!(citi.net_volume, 1);
+        assert_close(citi.average_cost.unwrap(), 1.5562);
+        assert_eq!(
+            position_keeper
+                .lp_position("USD/EUR", "MS")
+                .unwrap()
+                .net_volume,
+            -1
+        );
+        assert!(position_keeper.lp_position("GBP/USD", "CITI").is_none());
+
+        // the book's mid is now (1.5545 + 1.5563) / 2 = 1.5554 after the 3M was filled
+        position_keeper.mark_to_market(book_manager.book("USD/EUR").unwrap());
+        let usd_eur = position_keeper.position("USD/EUR").unwrap();
+        assert_close(usd_eur.mark_price.unwrap(), 1.5554);
+        let citi = position_keeper.lp_position("USD/EUR", "CITI").unwrap();
+        assert_close(citi.unrealised_pnl, (1.5554 - 1.5562) * 1_000_000.0);
+        let ms = position_keeper.lp_position("USD/EUR", "MS").unwrap();
+        assert_close(ms.unrealised_pnl, (1.5545 - 1.5554) * 1_000_000.0);
+        // the positions with each liquidity provider add up to the currency pair's position
+        let lp_pnl: f64 = position_keeper
+            .lp_positions
+            .values()
+            .map(Position::total_pnl)
+            .sum();
+        assert_close(usd_eur.total_pnl(), lp_pnl);
+    }
+
+    #[test]
+    fn test_parse_blotter() {
+        let trades = position::read_blotter("resources/blotter.csv").unwrap();
+        assert_eq!(trades.len(), 6);
+        assert_eq!(trades[5].price, Price::from_f64(151.27, 3).unwrap());
+        let position_keeper = PositionKeeper::from_blotter("resources/blotter.csv").unwrap();
+        assert_eq!(position_keeper.position("USD/EUR").unwrap().net_volume, 6);
+        assert_eq!(position_keeper.position("GBP/USD").unwrap().net_volume, -3);
+
+        for (contents, error) in [
+            (
+                "header\nUSD/EUR, CITI, Hold, 1.5561, 5",
+                "blotter.csv, line 2, field side - Hold must be Buy or Sell",
+            ),
+            (
+                "header\n\nUSD/EUR, CITI, Buy, 1.5561, -5",
+                "blotter.csv, line 3, field volume - must be a positive number of millions",
+            ),
+            (
+                "header\nUSD/EUR, , Buy, 1.5561, 5",
+                "blotter.csv, line 2, field liquidity provider - missing value",
+            ),
+            (
+                "header\nUSD/EUR, CITI, Buy",
+                "blotter.csv, line 2, field price - missing value",
+            ),
+        ] {
+            match position::parse_blotter("blotter.csv", contents) {
+                Err(AppError::InvalidTrade(message)) => assert_eq!(message, error),
+                result => panic!("expected invalid trade error, got {result:?}"),
+            }
+        }
+        assert!(PositionKeeper::from_blotter("no_such_blotter.csv").is_err());
+    }
+
+    fn trade(side: Side, volume: i32) -> Trade {
+        Trade {
+            currency_pair: String::from("USD/EUR"),
+            liquidity_provider: String::from("CITI"),
+            side,
+            price: price(1.5560),
+            volume,
+        }
+    }
+
+    #[test]
+    fn test_risk_limits_reject_and_cap_orders() {
+        let mut book_manager = order_book();
+        let mut position_keeper = PositionKeeper::default();
+        let mut risk_manager = RiskManager::new(RiskLimits {
+            max_order_volume: Some(8),
+            max_net_position: Some(10),
+            price_band: Some(pips_to_ticks(2.0)),
+            ..Default::default()
+        });
+        let mut execute = |position_keeper: &PositionKeeper, order: Order| {
+            risk_manager.execute(&mut book_manager, position_keeper, &order)
+        };
+
+        let e = execute(&position_keeper, Order::market("USD/EUR", Side::Buy, 9)).unwrap_err();
+        assert!(matches!(
+            e,
+            AppError::RiskLimit(RiskBreach::OrderSize {
+                volume: 9,
+                limit: 8
+            })
+        ));
+        assert_eq!(
+            e.to_string(),
+            "risk limit breached - order volume 9M is over the 8M limit"
+        );
+
+        // long 7M, a 5M buy is capped to the 3M left under the net position limit, and as a market
+        // order it is limited to 2 pips through the top of the book, short of MS at 1.5565
+        position_keeper.add_trade(&trade(Side::Buy, 7));
+        let report = execute(&position_keeper, Order::market("USD/EUR", Side::Buy, 5)).unwrap();
+        assert_eq!(report.order.volume, 3);
+        assert_eq!(report.order.order_type, OrderType::Limit(price(1.5564)));
+        assert_eq!(report.status, OrderStatus::Filled);
+        assert!(
+            report
+                .fills
+                .iter()
+                .all(|fill| fill.liquidity_provider != "MS")
+        );
+        position_keeper.add_execution_report(&report);
+
+        // at the limit only orders reducing the position are accepted
+        for order in [
+            Order::market("USD/EUR", Side::Buy, 1),
+            Order::market("USD/EUR", Side::Buy, 1).fill_or_kill(),
+        ] {
+            assert!(matches!(
+                execute(&position_keeper, order),
+                Err(AppError::RiskLimit(RiskBreach::NetPosition {
+                    net_volume: 10,
+                    limit: 10,
+                    room: 0,
+                    volume: 1,
+                    ..
+                }))
+            ));
+        }
+        let order = Order::market("USD/EUR", Side::Sell, 4);
+        let report = execute(&position_keeper, order).unwrap();
+        assert_eq!(report.filled_volume, 4);
+        position_keeper.add_execution_report(&report);
+
+        // a fill or kill order over the room left is rejected rather than capped
+        let order = Order::market("USD/EUR", Side::Buy, 5).fill_or_kill();
+        assert_eq!(
+            execute(&position_keeper, order).unwrap_err().to_string(),
+            "risk limit breached - USD/EUR order for 5M is over the 4M of room left by a net position of 6M under the 10M limit"
+        );
+        let order = Order::market("USD/EUR", Side::Sell, 4);
+        assert_eq!(execute(&position_keeper, order).unwrap().filled_volume, 4);
+
+        // limit orders more than 2 pips through the top of the book are fat fingers
+        position_keeper.add_trade(&trade(Side::Sell, 10));
+        let e = execute(
+            &position_keeper,
+            Order::limit("USD/EUR", Side::Buy, 1, price(1.5566)),
+        )
+        .unwrap_err();
+        match e {
+            AppError::RiskLimit(RiskBreach::PriceBand { price: p, top, .. }) => {
+                assert_eq!((p, top), (price(1.5566), price(1.5563)));
+            }
+            e => panic!("expected a price band breach, got {e:?}"),
+        }
+        let order = Order::limit("USD/EUR", Side::Buy, 1, price(1.5565));
+        assert_eq!(execute(&position_keeper, order).unwrap().filled_volume, 1);
+    }
+
+    #[test]
+    fn test_credit_lines_limit_fills() {
+        let mut book_manager = order_book();
+        let position_keeper = PositionKeeper::default();
+        let credit_lines = [("CITI", 1), ("UBS", 0), ("BARX", 2), ("MS", 1)]
+            .into_iter()
+            .map(|(liquidity_provider, credit_line)| {
+                (String::from(liquidity_provider), credit_line)
+            })
+            .collect();
+        let mut risk_manager = RiskManager::new(RiskLimits {
+            credit_lines,
+            ..Default::default()
+        });
+        assert_eq!(risk_manager.available_credit("BARX"), Some(2));
+        assert_eq!(risk_manager.available_credit("JPMC"), None);
+
+        // only 4M can be filled within the credit lines, so a 5M fill or kill order is not filled
+        let order = Order::market("USD/EUR", Side::Buy, 5).fill_or_kill();
+        let report = risk_manager
+            .execute(&mut book_manager, &position_keeper, &order)
+            .unwrap();
+        assert_eq!(report.status, OrderStatus::Cancelled);
+        assert_eq!(risk_manager.available_credit("CITI"), Some(1));
+
+        // UBS has no credit and is skipped, the others are filled up to their credit lines
+        let order = Order::market("USD/EUR", Side::Buy, 5);
+        let report = risk_manager
+            .execute(&mut book_manager, &position_keeper, &order)
+            .unwrap();
+        let fills: Vec<(&str, i32)> = report
+            .fills
+            .iter()
+            .map(|fill| (fill.liquidity_provider.as_str(), fill.volume))
+            .collect();
+        assert_eq!(fills, vec![("CITI", 1), ("BARX", 2), ("MS", 1)]);
+        assert_eq!(report.cancelled_volume, 1);
+        assert_eq!(risk_manager.available_credit("BARX"), Some(0));
+
+        // once every liquidity provider quoting the book has used its credit orders are rejected
+        let order = Order::market("USD/EUR", Side::Sell, 1);
+        match risk_manager.execute(&mut book_manager, &position_keeper, &order) {
+            Err(AppError::RiskLimit(RiskBreach::Credit {
+                liquidity_providers,
+                ..
+            })) => assert_eq!(liquidity_providers, vec!["BARX", "CITI", "MS", "UBS"]),
+            result => panic!("expected a credit breach, got {result:?}"),
+        }
+    }
+
+    fn config_error(result: Result<config::AppConfig, AppError>) -> config::ConfigError {
+        match result {
+            Err(AppError::Config(e)) => e,
+            other => panic!("expected a config error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_load_config_files() {
+        let app_config = config::load_config(config::DEFAULT_CONFIG_FILE).unwrap();
+        assert_eq!(app_config.liquidity_providers.len(), 9);
+        assert_eq!(app_config.general.min_spread_pips, 6.0);
+        let usd_jpy = &app_config.liquidity_providers[7];
+        assert_eq!(usd_jpy.currency_pair, "USD/JPY");
+        assert_eq!(usd_jpy.buy_price.to_string(), "151.250");
+        assert_eq!(app_config.liquidity_providers[3].tiers.len(), 5);
+        assert!(app_config.liquidity_providers[2].incremental);
+
+        // the original CSV config file is still imported
+        let app_config = config::load_config("resources/config.txt").unwrap();
+        assert_eq!(app_config.liquidity_providers.len(), 9);
+        assert_eq!(
+            app_config.liquidity_providers[0].tiers,
+            simulator::legacy_tiers(1.0, 2.0)
+        );
+    }
+
+    #[test]
+    fn test_csv_and_toml_config_files_build_the_same_book() {
+        // resources/config.toml is a translation of resources/config.txt, so with the same seed
+        // every quote update leaves the same book whichever file it was configured from
+        let ladders = |file: &str| {
+            let configs = config::load_config(file).unwrap().liquidity_providers;
+            let lp_simulators = (0..)
+                .zip(&configs)
+                .map(|(index, config)| {
+                    simulator::LpSimulator::new(config, simulator::lp_seed(config, Some(7), index))
+                })
+                .collect();
+            let mut book_manager = BookManager::new(&configs, aggregator::MIN_SPREAD_PIPS);
+            simulator::VirtualMarketData::new(lp_simulators, simulator::VIRTUAL_CLOCK_START)
+                .map(|(_, _, quote)| {
+                    let fx_book = book_manager.update(quote).unwrap();
+                    let mut ladder = Vec::new();
+                    aggregator::write_fxbook_as_ladder(&mut ladder, fx_book).unwrap();
+                    String::from_utf8(ladder).unwrap()
+                })
+                .collect::<Vec<String>>()
+        };
+        let csv_ladders = ladders("resources/config.txt");
+        assert!(csv_ladders.len() > 100);
+        assert_eq!(csv_ladders, ladders(config::DEFAULT_CONFIG_FILE));
+    }
+
+    #[test]
+    fn test_toml_config_defaults_and_errors() {
+        let lp = "[[liquidity_provider]]\nname = \"CITI\"\ncurrency_pair = \"USD/EUR\"\nbuy_price = 1.5552\nspread_pips = 10.0\n";
+        let app_config = config::parse_toml("test.toml", lp).unwrap();
+        assert_eq!(app_config.general.min_spread_pips, 6.0);
+        assert_eq!(app_config.logging.fix_log, "logs/fix.log");
+        let citi = &app_config.liquidity_providers[0];
+        assert_eq!(citi.spread, 100);
+        assert_eq!(citi.run_iterations, 20);
+        assert!(!citi.incremental);
+
+        // errors name the file, line, liquidity provider and field
+        let e = config_error(config::parse_toml(
+            "test.toml",
+            &format!("{lp}market_data = \"stream\"\n"),
+        ));
+        assert_eq!(e.file, "test.toml");
+        assert_eq!(e.line, Some(6));
+        assert_eq!(e.liquidity_provider.as_deref(), Some("CITI"));
+        assert_eq!(e.field.as_deref(), Some("market_data"));
+
+        let e = config_error(config::parse_toml("test.toml", &lp.replace("10.0", "-1.0")));
+        assert_eq!(e.line, Some(5));
+        assert_eq!(e.field.as_deref(), Some("spread_pips"));
+
+        let e = config_error(config::parse_toml(
+            "test.toml",
+            &format!("{lp}tiers = [{{ volume = 1 }}, {{ volume = 1 }}]\n"),
+        ));
+        assert_eq!(e.field.as_deref(), Some("tiers"));
+        let e = config_error(config::parse_toml("test.toml", &format!("{lp}{lp}")));
+        assert_eq!((e.line, e.field.as_deref()), (Some(7), Some("name")));
+
+        // parse errors give the line of the bad value
+        let e = config_error(config::parse_toml(
+            "test.toml",
+            &lp.replace("1.5552", "\"high\""),
+        ));
+        assert_eq!(e.line, Some(4));
+        let e = config_error(config::parse_toml(
+            "test.toml",
+            "[general]\nmin_spread = 1.0\n",
+        ));
+        assert_eq!(e.line, Some(2));
+
+        // NaN and infinite values are rejected with negative ones
+        for (toml, field) in [
+            ("[general]\nmin_spread_pips = nan\n", "min_spread_pips"),
+            ("[general]\nmin_spread_pips = inf\n", "min_spread_pips"),
+            (
+                "[[book_policy]]\ncurrency_pair = \"USD/EUR\"\nmin_spread_pips = nan\n",
+                "min_spread_pips",
+            ),
+            ("[risk]\nprice_band_pips = nan\n", "price_band_pips"),
+        ] {
+            let e = config_error(config::parse_toml("test.toml", &format!("{toml}{lp}")));
+            assert_eq!(e.field.as_deref(), Some(field), "{toml}");
+        }
+        for (value, field) in [
+            ("spread_pips = nan", "spread_pips"),
+            ("spread_pips = -inf", "spread_pips"),
+            ("buy_price = nan", "buy_price"),
+            ("buy_price = inf", "buy_price"),
+        ] {
+            let (key, _) = value.split_once(" = ").unwrap();
+            let toml = lp
+                .lines()
+                .map(|line| if line.starts_with(key) { value } else { line })
+                .collect::<Vec<&str>>()
+                .join("\n");
+            let e = config_error(config::parse_toml("test.toml", &toml));
+            assert_eq!(e.liquidity_provider.as_deref(), Some("CITI"));
+            assert_eq!(e.field.as_deref(), Some(field), "{value}");
+        }
+        let e = config_error(config::parse_toml(
+            "test.toml",
+            &format!("{lp}tiers = [{{ volume = 1, bid_markup_pips = nan }}]\n"),
+        ));
+        assert_eq!(e.field.as_deref(), Some("tiers"));
+    }
+
+    #[test]
+    fn test_toml_risk_limits() {
+        let app_config = config::load_config(config::DEFAULT_CONFIG_FILE).unwrap();
+        assert_eq!(app_config.risk.max_order_volume, Some(50));
+        assert_eq!(app_config.risk.price_band, Some(200));
+        assert_eq!(app_config.risk.credit_lines.get("BARX"), Some(&150));
+
+        let lp = "[[liquidity_provider]]\nname = \"CITI\"\ncurrency_pair = \"USD/EUR\"\nbuy_price = 1.5552\nspread_pips = 10.0\n";
+        let app_config = config::parse_toml("test.toml", lp).unwrap();
+        assert_eq!(app_config.risk, RiskLimits::default());
+
+        let e = config_error(config::parse_toml(
+            "test.toml",
+            &format!("[risk]\nmax_order_volume = 0\n{lp}"),
+        ));
+        assert_eq!(
+            (e.line, e.field.as_deref()),
+            (Some(2), Some("max_order_volume"))
+        );
+        let e = config_error(config::parse_toml(
+            "test.toml",
+            &format!("[risk]\nprice_band_pips = 1.0\ncredit_lines = {{ CITI = 5, MS = -1 }}\n{lp}"),
+        ));
+        assert_eq!(e.line, Some(3));
+        assert_eq!(e.liquidity_provider.as_deref(), Some("MS"));
+        assert_eq!(e.field.as_deref(), Some("credit_lines"));
+    }
+
+    #[test]
+    fn test_toml_book_policies() {
+        let lp = "[[liquidity_provider]]\nname = \"CITI\"\ncurrency_pair = \"USD/EUR\"\nbuy_price = 1.5552\nspread_pips = 10.0\n";
+        let contents = format!(
+            "[general]\nmin_spread_pips = 3.0\n\n[[book_policy]]\ncurrency_pair = \"USD/EUR\"\ncrossed = \"drop_offending\"\n\n[[book_policy]]\ncurrency_pair = \"USD/JPY\"\nmin_spread_pips = 1.5\n{lp}"
+        );
+        let app_config = config::parse_toml("test.toml", &contents).unwrap();
+        assert_eq!(
+            app_config.book_policies["USD/EUR"],
+            BookPolicyConfig::new(CrossedBookPolicy::DropOffending, Some(3.0))
+        );
+        assert_eq!(
+            app_config.book_policies["USD/JPY"],
+            BookPolicyConfig::new(CrossedBookPolicy::LongestSide, Some(1.5))
+        );
+
+        let e = config_error(config::parse_toml(
+            "test.toml",
+            &format!("[[book_policy]]\ncurrency_pair = \"USD/EUR\"\ncrossed = \"ignore\"\n{lp}"),
+        ));
+        assert_eq!((e.line, e.field.as_deref()), (Some(3), Some("crossed")));
+        assert!(e.message.starts_with("unknown crossed book policy ignore"));
+        let book_policy = "[[book_policy]]\ncurrency_pair = \"USD/EUR\"\n";
+        let e = config_error(config::parse_toml(
+            "test.toml",
+            &format!("{book_policy}{book_policy}{lp}"),
+        ));
+        assert_eq!(
+            (e.line, e.field.as_deref()),
+            (Some(4), Some("currency_pair"))
+        );
+    }
+
+    #[test]
+    fn test_toml_quote_ttl() {
+        let lp = "[[liquidity_provider]]\nname = \"CITI\"\ncurrency_pair = \"USD/EUR\"\nbuy_price = 1.5552\nspread_pips = 10.0\n";
+        let app_config = config::parse_toml("test.toml", lp).unwrap();
+        assert_eq!(app_config.liquidity_providers[0].quote_ttl, None);
+
+        // the general quote time to live applies to liquidity providers without their own
+        let contents = format!(
+            "[general]\nquote_ttl_secs = 10\n{lp}{}",
+            lp.replace("CITI", "UBS")
+        );
+        let contents = format!("{contents}quote_ttl_secs = 2.5\n");
+        let app_config = config::parse_toml("test.toml", &contents).unwrap();
+        assert_eq!(app_config.general.quote_ttl, Some(Duration::from_secs(10)));
+        let quote_ttls: Vec<Option<Duration>> = app_config
+            .liquidity_providers
+            .iter()
+            .map(|config| config.quote_ttl)
+            .collect();
+        assert_eq!(
+            quote_ttls,
+            vec![
+                Some(Duration::from_secs(10)),
+                Some(Duration::from_millis(2500))
+            ]
+        );
+
+        let e = config_error(config::parse_toml(
+            "test.toml",
+            &format!("{lp}quote_ttl_secs = 0\n"),
+        ));
+        assert_eq!(e.line, Some(6));
+        assert_eq!(e.liquidity_provider.as_deref(), Some("CITI"));
+        assert_eq!(e.field.as_deref(), Some("quote_ttl_secs"));
+
+        let contents = format!(
+            "{lp}last_look = {{ hold_ms = 20, reject_probability = 0.1, max_price_move_pips = 0.5 }}\n"
+        );
+        let app_config = config::parse_toml("test.toml", &contents).unwrap();
+        assert_eq!(
+            app_config.liquidity_providers[0].last_look,
+            LastLookConfig {
+                hold_time: Duration::from_millis(20),
+                reject_probability: 0.1,
+                max_price_move: Some(5),
+            }
+        );
+        let e = config_error(config::parse_toml(
+            "test.toml",
+            &format!("{lp}last_look = {{ reject_probability = 1.5 }}\n"),
+        ));
+        assert_eq!((e.line, e.field.as_deref()), (Some(6), Some("last_look")));
+        for max_price_move_pips in ["nan", "inf", "-0.5"] {
+            let e = config_error(config::parse_toml(
+                "test.toml",
+                &format!("{lp}last_look = {{ max_price_move_pips = {max_price_move_pips} }}\n"),
+            ));
+            assert_eq!((e.line, e.field.as_deref()), (Some(6), Some("last_look")));
+        }
+    }
+
+    #[test]
+    fn test_csv_config_errors() {
+        let header = "Name, currency pair, 1M buy price, spread (pips), 3M markup (pips), 5M markip (pips), no. iterations\n";
+        let e = match config::import_csv(
+            "config.txt",
+            &format!(
+                "{header}CITI, USD/EUR, 1.5552, 10, 1, 2, 20\nBARX, USD/EUR, 1.5553, ten, 1, 2, 20\n"
+            ),
+        ) {
+            Err(AppError::Config(e)) => e,
+            other => panic!("expected a config error, got {other:?}"),
+        };
+        assert_eq!(e.line, Some(3));
+        assert_eq!(e.liquidity_provider.as_deref(), Some("BARX"));
+        assert_eq!(e.field.as_deref(), Some("spread"));
+        assert!(
+            e.to_string()
+                .starts_with("config.txt, line 3, liquidity provider BARX, field spread - ten")
+        );
+        for (row, field) in [
+            ("BARX, USD/EUR, 1.5553, NaN, 1, 2, 20", "spread"),
+            ("BARX, USD/EUR, 1.5553, 10, inf, 2, 20", "3M markup"),
+            ("BARX, USD/EUR, 1.5553, 10, 1, -2, 20", "5M markup"),
+            (
+                "BARX, USD/EUR, 1.5553, 10, 1, 2, 20, , , , 1:0:inf",
+                "tiers",
+            ),
+            (
+                "BARX, USD/EUR, 1.5553, 10, 1, 2, 20, , , , 1:nan:0",
+                "tiers",
+            ),
+            (
+                "BARX, USD/EUR, 1.5553, 10, 1, 2, 20, , , , 1:0:0 3:-1:1",
+                "tiers",
+            ),
+        ] {
+            match config::import_csv("config.txt", &format!("{header}{row}\n")) {
+                Err(AppError::Config(e)) => assert_eq!(e.field.as_deref(), Some(field)),
+                other => panic!("expected a config error, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_cli_overrides_config() {
+        let cli = Cli::try_parse_from([
+            "fx_sim_agg",
+            "--config",
+            "resources/config.txt",
+            "--fix-log",
+            "/tmp/fix.log",
+            "--seed",
+            "42",
+            "--no-ladder",
+            "--duration",
+            "1.5",
+            "--blotter",
+            "resources/blotter.csv",
+        ])
+        .unwrap();
+        assert_eq!(cli.config, "resources/config.txt");
+        let mut app_config = config::AppConfig::default();
+        cli.apply(&mut app_config).unwrap();
+        assert_eq!(app_config.logging.fix_log, "/tmp/fix.log");
+        assert_eq!(app_config.logging.log_config, "logging_config.yaml");
+        assert_eq!(app_config.general.seed, Some(42));
+        assert!(!app_config.general.print_ladder);
+        assert_eq!(
+            app_config.general.run_duration,
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(
+            app_config.general.blotter.as_deref(),
+            Some("resources/blotter.csv")
+        );
+
+        // the last of --ladder and --no-ladder wins
+        let cli = Cli::try_parse_from(["fx_sim_agg", "--no-ladder", "--ladder"]).unwrap();
+        assert_eq!(cli.config, config::DEFAULT_CONFIG_FILE);
+        cli.apply(&mut app_config).unwrap();
+        assert!(app_config.general.print_ladder);
+
+        assert!(Cli::try_parse_from(["fx_sim_agg", "--duration", "0"]).is_err());
+        assert!(Cli::try_parse_from(["fx_sim_agg", "--seed", "x"]).is_err());
+        let cli = Cli::try_parse_from([
+            "fx_sim_agg",
+            "--replay",
+            "logs/fix.log",
+            "--replay-speed",
+            "10x",
+        ])
+        .unwrap();
+        assert_eq!(cli.replay_speed, Some(ReplaySpeed::Multiplier(10.0)));
+        let cli = Cli::try_parse_from(["fx_sim_agg", "--replay", "logs/fix.log"]).unwrap();
+        assert_eq!(cli.replay_speed, None);
+        assert!(
+            Cli::try_parse_from(["fx_sim_agg", "--replay", "x", "--replay-speed", "fast"]).is_err()
+        );
+        // a replay speed without a replay would be silently ignored, so is rejected
+        assert!(Cli::try_parse_from(["fx_sim_agg", "--replay-speed", "max"]).is_err());
+    }
+
+    #[test]
+    fn test_run_headless_stops_after_run_duration() {
+        let fix_log = std::env::temp_dir().join("fx_sim_agg_headless_test.log");
+        let mut app_config = config::AppConfig {
+            liquidity_providers: vec![config("CITI", false)],
+            ..Default::default()
+        };
+        app_config.logging.fix_log = fix_log.to_string_lossy().to_string();
+        app_config.general.print_ladder = false;
+        app_config.general.run_duration = Some(Duration::from_millis(100));
+        crate::run_headless(&app_config).unwrap();
+        assert!(fix_log.exists());
+
+        app_config.logging.fix_log = String::from("no_such_directory/fix.log");
+        assert!(crate::run_headless(&app_config).is_err());
+    }
+
+    #[test]
+    fn test_replay_speed() {
+        assert_eq!(
+            ReplaySpeed::parse("realtime").unwrap(),
+            ReplaySpeed::Multiplier(1.0)
+        );
+        assert_eq!(
+            ReplaySpeed::parse("10x").unwrap(),
+            ReplaySpeed::Multiplier(10.0)
+        );
+        assert_eq!(
+            ReplaySpeed::parse("max").unwrap(),
+            ReplaySpeed::AsFastAsPossible
+        );
+        assert!(ReplaySpeed::parse("0x").is_err());
+        assert!(ReplaySpeed::parse("fast").is_err());
+
+        let second = 1_000_000_000;
+        assert_eq!(
+            ReplaySpeed::Multiplier(1.0).delay(second, 3 * second),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            ReplaySpeed::Multiplier(4.0).delay(second, 3 * second),
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            ReplaySpeed::AsFastAsPossible.delay(second, 3 * second),
+            Duration::ZERO
+        );
+        // out of order timestamps replay straight away
+        assert_eq!(
+            ReplaySpeed::Multiplier(1.0).delay(3 * second, second),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn test_replay_rebuilds_book_from_fix_log() {
+        let configs = vec![config("CITI", false), config("MS", true)];
+        let (fix_log, book_prices) = run_seeded(&configs, 11);
+        let file = std::env::temp_dir().join("fx_sim_agg_replay_test.log");
+        std::fs::write(&file, fix_log.join("\n") + "\n").unwrap();
+
+        let replay = Replay::load(&file.to_string_lossy(), ReplaySpeed::AsFastAsPossible).unwrap();
+        assert_eq!(replay.messages.len(), fix_log.len());
+        let replayed: Vec<QuoteUpdate> =
+            crate::run(async { replay::replay_stream(&replay).collect().await });
+        let mut encoder = QuoteEncoder::new(&configs);
+        let encoded: Vec<String> = replayed
+            .iter()
+            .filter_map(|quote| encoder.encode(quote))
+            .map(|market_data| market_data.encode())
+            .collect();
+        assert_eq!(encoded, fix_log);
+
+        let mut fx_book = FxBook::default();
+        for quote in replayed {
+            fx_book.update(quote).unwrap();
+        }
+        let replayed_prices: Vec<Price> = fx_book
+            .buy_book
+            .iter()
+            .chain(fx_book.sell_book.iter())
+            .map(|entry| entry.price)
+            .collect();
+        assert_eq!(replayed_prices, book_prices);
+
+        // a headless replay doesn't write over the FIX log
+        let app_config = config::AppConfig {
+            logging: config::LoggingConfig {
+                log_config: String::from("logging_config.yaml"),
+                fix_log: file.to_string_lossy().to_string(),
+            },
+            replay: Some(replay),
+            ..Default::default()
+        };
+        crate::run_headless(&app_config).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&file).unwrap().lines().count(),
+            fix_log.len()
+        );
+    }
+
+    #[test]
+    fn test_replay_expires_silent_lps_on_source_clock() {
+        // CITI goes silent while BARX keeps quoting for 10 seconds of source time, replayed as
+        // fast as possible so far less than CITI's time to live passes on the wall clock
+        let mut configs = vec![config("CITI", false), config("BARX", false)];
+        configs[0].quote_ttl = Some(Duration::from_secs(2));
+        let second = 1_000_000_000;
+        let mut encoder = QuoteEncoder::new(&configs);
+        let mut fix_log: Vec<String> = Vec::new();
+        for (seconds, liquidity_provider, bid, offer) in [
+            (0, "CITI", 1.5552, 1.5562),
+            (0, "BARX", 1.5551, 1.5563),
+            (1, "BARX", 1.5550, 1.5563),
+            (10, "BARX", 1.5551, 1.5563),
+        ] {
+            let mut quote = quote(liquidity_provider, bid, offer);
+            quote.source_time += seconds * second;
+            fix_log.push(encoder.encode(&quote).unwrap().encode());
+        }
+        let file = std::env::temp_dir().join("fx_sim_agg_replay_expiry_test.log");
+        std::fs::write(&file, fix_log.join("\n") + "\n").unwrap();
+        let app_config = config::AppConfig {
+            liquidity_providers: configs.clone(),
+            replay: Some(
+                Replay::load(&file.to_string_lossy(), ReplaySpeed::AsFastAsPossible).unwrap(),
+            ),
+            ..Default::default()
+        };
+
+        let book_manager = BookManager::new(&configs, aggregator::MIN_SPREAD_PIPS);
+        let position_keeper = PositionKeeper::default();
+        let snapshots = Arc::new(Snapshots::new(&book_manager, &position_keeper));
+        let desk = LastLookDesk::new(&configs, Some(1), Arc::clone(&snapshots));
+        crate::run_async_fx_sim_agg(
+            None,
+            None,
+            book_manager,
+            position_keeper,
+            &snapshots,
+            &app_config,
+        );
+        let books = snapshots.books();
+        let usd_eur = &books["USD/EUR"];
+        assert!(usd_eur.stale_lps.contains("CITI"));
+        assert_eq!(
+            usd_eur.buy_book.first().unwrap().lp_vol,
+            vec![(String::from("BARX"), 1)]
+        );
+        assert_eq!(usd_eur.sell_book.len(), 1);
+
+        // the last look desk trades against the replayed quotes in the published books
+        let request = TradeRequest {
+            liquidity_provider: String::from("BARX"),
+            currency_pair: String::from("USD/EUR"),
+            side: Side::Sell,
+            price: price(1.5551),
+            volume: 1,
+        };
+        assert_eq!(
+            crate::run(desk.request_trade(&request)).unwrap(),
+            TradeResponse::Filled {
+                price: price(1.5551),
+                volume: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_merge_market_data_sources() {
+        let mut gbp_quote = quote("BARX", 1.2710, 1.2720);
+        gbp_quote.currency_pair = String::from("GBP/USD");
+        let sources: Vec<Box<dyn MarketDataSource>> = vec![
+            Box::new(FixtureSource::new(
+                "CITI",
+                vec![quote("CITI", 1.5552, 1.5562), quote("CITI", 1.5553, 1.5562)],
+            )),
+            Box::new(FixtureSource::new("BARX", vec![gbp_quote])),
+        ];
+        assert_eq!(sources[1].name(), "BARX");
+
+        let merged: Vec<(String, QuoteUpdate)> = crate::run(async {
+            merge_sources(sources)
+                .map(|(source, quote)| (source.to_string(), quote))
+                .collect()
+                .await
+        });
+        assert_eq!(merged.len(), 3);
+        let mut book_manager = BookManager::default();
+        for (source, quote) in merged {
+            let fx_book = book_manager.update(quote).unwrap();
+            let liquidity_provider = &fx_book.buy_book.first().unwrap().lp_vol[0].0;
+            assert_eq!(liquidity_provider, &source);
+        }
+        assert_eq!(book_manager.currency_pairs(), vec!["GBP/USD", "USD/EUR"]);
+        assert_eq!(
+            book_manager
+                .book("USD/EUR")
+                .unwrap()
+                .buy_book
+                .first()
+                .unwrap()
+                .price,
+            price(1.5553)
+        );
+    }
+
+    fn run_seeded(configs: &[simulator::Config], seed: u64) -> (Vec<String>, Vec<Price>) {
+        let lp_simulators = (0..)
+            .zip(configs)
+            .map(|(index, config)| {
+                simulator::LpSimulator::new(config, simulator::lp_seed(config, Some(seed), index))
+            })
+            .collect();
+        let mut fx_book = FxBook::default();
+        let mut encoder = QuoteEncoder::new(configs);
+        let mut fix_log: Vec<String> = Vec::new();
+        for (_virtual_time, _index, quote) in
+            simulator::VirtualMarketData::new(lp_simulators, simulator::VIRTUAL_CLOCK_START)
+        {
+            if let Some(market_data) = encoder.encode(&quote) {
+                fix_log.push(market_data.encode());
+            }
+            fx_book.update(quote).unwrap();
+        }
+        let book_prices = fx_book
+            .buy_book
+            .iter()
+            .chain(fx_book.sell_book.iter())
+            .map(|entry| entry.price)
+            .collect();
+        (fix_log, book_prices)
+    }
+
+    #[test]
+    fn test_seeded_simulation_is_reproducible() {
+        let configs = vec![
+            config("CITI", false),
+            config("MS", true),
+            config("UBS", false),
+        ];
+
+        let (fix_log, book_prices) = run_seeded(&configs, 42);
+        assert!(fix_log.len() > 40);
+        assert_eq!(run_seeded(&configs, 42), (fix_log.clone(), book_prices));
+        assert_ne!(run_seeded(&configs, 43).0, fix_log);
+        // messages are timestamped from the virtual clock
+        assert!(fix_log[0].contains("\x0152=20250101-00:00:0"));
+    }
+
+    #[test]
+    fn test_lp_seed_overrides_global_seed() {
+        let mut lp_config = config("CITI", false);
+        assert_eq!(simulator::lp_seed(&lp_config, Some(42), 2), 44);
+        lp_config.seed = Some(7);
+        assert_eq!(simulator::lp_seed(&lp_config, Some(42), 2), 7);
+        assert_eq!(simulator::lp_seed(&lp_config, None, 2), 7);
+        // derived and random seeds stay in the range of a TOML integer
+        lp_config.seed = None;
+        assert_eq!(
+            simulator::lp_seed(&lp_config, Some(simulator::MAX_SEED), 2),
+            1
+        );
+        assert!(simulator::lp_seed(&lp_config, None, 2) <= simulator::MAX_SEED);
+    }
+
+    #[test]
+    fn test_every_lp_seeded_runs_on_virtual_clock() {
+        let mut configs = vec![config("CITI", false), config("MS", true)];
+        assert!(!simulator::virtual_clock(&configs, None));
+        assert!(simulator::virtual_clock(&configs, Some(42)));
+        assert_eq!(simulator::sources(&configs, None).len(), 2);
+
+        configs[0].seed = Some(7);
+        assert!(!simulator::virtual_clock(&configs, None));
+        configs[1].seed = Some(8);
+        assert!(simulator::virtual_clock(&configs, None));
+        let sources = simulator::sources(&configs, None);
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].name(), "seeded simulation");
+        assert!(!simulator::virtual_clock(&[], None));
+
+        // a seed above the range of a TOML integer can't be given
+        let max_seed = simulator::MAX_SEED.to_string();
+        assert!(Cli::try_parse_from(["fx_sim_agg", "--seed", &max_seed]).is_ok());
+        let too_large = (simulator::MAX_SEED + 1).to_string();
+        assert!(Cli::try_parse_from(["fx_sim_agg", "--seed", &too_large]).is_err());
+        let header = "Name, currency pair, 1M buy price, spread (pips), 3M markup (pips), 5M markip (pips), no. iterations, market data type, seed\n";
+        match config::import_csv(
+            "config.txt",
+            &format!("{header}CITI, USD/EUR, 1.5552, 10, 1, 2, 20, snapshot, {too_large}\n"),
+        ) {
+            Err(AppError::Config(e)) => assert_eq!(e.field.as_deref(), Some("seed")),
+            other => panic!("expected a config error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_price_model() {
+        assert_eq!(
+            PriceModelConfig::parse("gbm sigma=0.0001").unwrap(),
+            PriceModelConfig::Gbm {
+                mu: 0.0,
+                sigma: 0.0001
+            }
+        );
+        assert_eq!(
+            PriceModelConfig::parse("mean_reversion mean=1.5560").unwrap(),
+            PriceModelConfig::MeanReversion {
+                theta: 0.05,
+                sigma_pips: 0.8,
+                mean: Some(1.5560)
+            }
+        );
+        assert!(PriceModelConfig::parse("sideways").is_err());
+        assert!(PriceModelConfig::parse("gbm vol=0.1").is_err());
+        assert!(PriceModelConfig::parse("gbm sigma").is_err());
+        assert!(PriceModelConfig::parse("gbm mu=-0.01").is_ok());
+        assert!(PriceModelConfig::parse("random_walk sigma_pips=0").is_ok());
+    }
+
+    #[test]
+    fn test_parse_price_model_rejects_invalid_parameters() {
+        for spec in [
+            "bull max_pips=0",
+            "bull max_pips=-1",
+            "bull max_pips=nan",
+            "bull max_pips=inf",
+            "random_walk sigma_pips=-0.8",
+            "random_walk sigma_pips=NaN",
+            "gbm sigma=-0.0001",
+            "gbm mu=nan",
+            "gbm mu=inf",
+            "mean_reversion theta=-0.05",
+            "mean_reversion theta=nan",
+            "mean_reversion mean=0",
+            "jump_diffusion jump_intensity=-0.02",
+            "jump_diffusion jump_intensity=nan",
+            "jump_diffusion jump_pips=-5",
+        ] {
+            assert!(
+                matches!(
+                    PriceModelConfig::parse(spec),
+                    Err(AppError::InvalidConfig(_))
+                ),
+                "{spec} accepted"
+            );
+        }
+        // the config file error names the liquidity provider and field
+        let lp = "[[liquidity_provider]]\nname = \"CITI\"\ncurrency_pair = \"USD/EUR\"\nbuy_price = 1.5552\nspread_pips = 10.0\n";
+        let e = config_error(config::parse_toml(
+            "test.toml",
+            &format!("{lp}price_model = \"bull max_pips=0\"\n"),
+        ));
+        assert_eq!(e.liquidity_provider.as_deref(), Some("CITI"));
+        assert_eq!((e.line, e.field.as_deref()), (Some(6), Some("price_model")));
+        assert!(e.message.contains("max_pips must be above 0"));
+    }
+
+    #[test]
+    fn test_price_models_move_both_ways() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        for spec in ["random_walk", "gbm", "jump_diffusion jump_intensity=0.5"] {
+            let mut model = PriceModelConfig::parse(spec).unwrap().create(price(1.5552));
+            let mut current = price(1.5552);
+            let (mut rises, mut falls) = (0, 0);
+            for _ in 0..200 {
+                let next = model.next_price(current, 3.0, &mut rng);
+                if next > current {
+                    rises += 1;
+                } else if next < current {
+                    falls += 1;
+                }
+                current = next;
+            }
+            assert!(
+                rises > 50 && falls > 50,
+                "{spec} rises {rises} falls {falls}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_mean_reversion_pulls_towards_mean() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut model = PriceModelConfig::parse("mean_reversion theta=0.2 mean=1.5552")
+            .unwrap()
+            .create(price(1.5552));
+        // start 100 pips above the reference rate
+        let mut current = price(1.5652);
+        for _ in 0..100 {
+            current = model.next_price(current, 3.0, &mut rng);
+        }
+        assert!(current.diff_ticks(price(1.5552)).abs() < 100);
     }
 }